@@ -5,6 +5,8 @@ use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 use rcgen::*;
 use std::io::{Read, Write};
 
+type SubcommandHandler = fn(&ArgMatches);
+
 fn main() {
     let name_arg = Arg::with_name("name").help("name for this certificate");
     let ca_arg = Arg::with_name("ca").help("name for the certificate authority");
@@ -42,7 +44,8 @@ fn main() {
         .setting(AppSettings::SubcommandRequiredElseHelp)
         .get_matches();
 
-    let subcommands: &[(&str, fn(&ArgMatches))] = &[("root", root), ("sign", sign), ("tree", tree)];
+    let subcommands: &[(&str, SubcommandHandler)] =
+        &[("root", root), ("sign", sign), ("tree", tree)];
     for (subcommand, handler) in subcommands {
         if let Some(sub_args) = args.subcommand_matches(subcommand) {
             handler(sub_args);
@@ -51,7 +54,7 @@ fn main() {
 }
 
 fn root(args: &ArgMatches) {
-    do_root(name_arg(&args));
+    do_root(name_arg(args));
 }
 
 fn do_root(name: &str) {
@@ -69,12 +72,12 @@ fn do_root(name: &str) {
 }
 
 fn sign(args: &ArgMatches) {
-    do_sign(ca_arg(&args), name_arg(&args));
+    do_sign(ca_arg(args), name_arg(args));
 }
 
 fn do_sign(ca: &str, name: &str) {
-    let key = key_filename(&ca);
-    let ca = cert_filename(&ca);
+    let key = key_filename(ca);
+    let ca = cert_filename(ca);
 
     let key = load(key.as_str());
     let ca = load(ca.as_str());
@@ -83,12 +86,12 @@ fn do_sign(ca: &str, name: &str) {
     let ca = CertificateParams::from_ca_cert_pem(ca.as_str(), key).expect("cert loading");
     let ca = Certificate::from_params(ca).expect("certificate");
 
-    let cert = generate(&name);
+    let cert = generate(name);
 
-    let filename = key_filename(&name);
+    let filename = key_filename(name);
     dump(filename.as_str(), &cert.serialize_private_key_pem());
 
-    let filename = cert_filename(&name);
+    let filename = cert_filename(name);
     let cert = cert.serialize_pem_with_signer(&ca).expect("signing");
     dump(filename.as_str(), &cert);
 }
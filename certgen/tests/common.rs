@@ -17,17 +17,21 @@ pub fn certgen(args: &[&str]) -> assert_cmd::Command {
 }
 
 pub fn assert_valid_key(filename: &str) {
-    certutils::read_key(&file_path(&filename)).expect("valid key");
+    certutils::read_key(&file_path(filename)).expect("valid key");
+}
+
+pub fn read_certs(filename: &str) -> Vec<rustls::Certificate> {
+    certutils::read_certs(&file_path(filename)).expect("valid cert")
 }
 
 pub fn assert_valid_cert(filename: &str) {
-    let certs = certutils::read_certs(&file_path(&filename)).expect("valid cert");
+    let certs = read_certs(filename);
     assert_eq!(1, certs.len(), "expected 1 cert");
 }
 
 pub fn make_server(key: &str, cert: &str, root: &str) -> ServerSession {
     let cfg = Arc::new(
-        certutils::make_server_config(&file_path(&cert), &file_path(&key), Some(&file_path(&root)))
+        certutils::make_server_config(&file_path(cert), &file_path(key), Some(&file_path(root)))
             .expect("server config"),
     );
     rustls::ServerSession::new(&cfg)
@@ -36,9 +40,9 @@ pub fn make_server(key: &str, cert: &str, root: &str) -> ServerSession {
 pub fn make_client(key: &str, cert: &str, root: &str, name: &str) -> ClientSession {
     let cfg = Arc::new(
         certutils::make_client_config(
-            &file_path(&root),
-            Some(&file_path(&cert)),
-            Some(&file_path(&key)),
+            &file_path(root),
+            Some(&file_path(cert)),
+            Some(&file_path(key)),
         )
         .expect("client config"),
     );
@@ -75,7 +79,7 @@ impl<'a> Write for OtherSession<'a> {
         let r = self.sess.read_tls(b.by_ref())?;
         self.sess.process_new_packets().map_err(|e| {
             let e = format!("{}", e);
-            std::io::Error::new(std::io::ErrorKind::Other, e)
+            std::io::Error::other(e)
         })?;
         Ok(r)
     }
@@ -57,7 +57,7 @@ fn tree_generates_keys_and_certs() {
         .ok()
         .unwrap();
 
-    for name in vec![ca, host1, host2] {
+    for name in [ca, host1, host2] {
         assert_valid_key(format!("{}-key.pem", name).as_str());
         assert_valid_cert(format!("{}-cert.pem", name).as_str());
     }
@@ -100,12 +100,12 @@ fn server_config_is_usable() {
         host.as_str(),
     );
 
-    assert_eq!(true, client.is_handshaking());
+    assert!(client.is_handshaking());
 
     client
         .complete_io(&mut OtherSession { sess: &mut server })
         .unwrap();
-    assert_eq!(false, client.is_handshaking());
+    assert!(!client.is_handshaking());
 }
 
 #[test]
@@ -132,7 +132,7 @@ fn client_rejects_bad_ca() {
         host.as_str(),
     );
 
-    assert_eq!(true, client.is_handshaking());
+    assert!(client.is_handshaking());
 
     client
         .complete_io(&mut OtherSession { sess: &mut server })
@@ -164,9 +164,34 @@ fn server_rejects_bad_ca() {
         host.as_str(),
     );
 
-    assert_eq!(true, client.is_handshaking());
+    assert!(client.is_handshaking());
 
     server
         .complete_io(&mut OtherSession { sess: &mut client })
         .expect_err("should reject");
 }
+
+#[test]
+fn certificate_has_common_name() {
+    let name = unique_name("named-root");
+    certgen(&["root", name.as_str()]).ok().unwrap();
+
+    let certs = read_certs(format!("{}-cert.pem", name).as_str());
+    assert!(certutils::common_name(&certs[0]).is_some());
+}
+
+#[test]
+fn certificate_info_lists_the_names() {
+    let name = unique_name("described-root");
+    certgen(&["root", name.as_str()]).ok().unwrap();
+
+    let certs = read_certs(format!("{}-cert.pem", name).as_str());
+    let info = certutils::certificate_info(&certs[0]).expect("parse");
+
+    let names: Vec<&str> = info.dns_names().collect();
+    assert_eq!(vec!["localhost", name.as_str()], names);
+    assert_eq!(info.subject, info.issuer);
+    assert!(!info.serial.is_empty());
+    assert!(!info.is_expired());
+    assert_eq!(32 * 3 - 1, info.fingerprint.len());
+}
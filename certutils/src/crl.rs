@@ -0,0 +1,97 @@
+use std::collections::HashSet;
+use std::time::SystemTime;
+use x509_parser::pem::Pem;
+use x509_parser::revocation_list::CertificateRevocationList;
+
+use super::Result;
+
+// The certificates an issuer revoked, as read from a certificate revocation list. Like the
+// roots, revocation lists are trusted as configured, their signatures are not checked.
+#[derive(Debug, Clone)]
+pub struct RevocationList {
+    issuer: Vec<u8>,
+    issuer_name: String,
+    serials: HashSet<Vec<u8>>,
+    next_update: Option<SystemTime>,
+}
+
+impl RevocationList {
+    fn new(crl: &CertificateRevocationList<'_>) -> RevocationList {
+        RevocationList {
+            issuer: crl.issuer().as_raw().to_vec(),
+            issuer_name: crl.issuer().to_string(),
+            serials: crl
+                .iter_revoked_certificates()
+                .map(|r| r.raw_serial().to_vec())
+                .collect(),
+            next_update: crl
+                .next_update()
+                .map(|t| super::info::system_time(t.timestamp())),
+        }
+    }
+
+    pub fn issuer(&self) -> &str {
+        &self.issuer_name
+    }
+
+    // The number of revoked certificates.
+    pub fn len(&self) -> usize {
+        self.serials.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.serials.is_empty()
+    }
+
+    // Whether the issuer should have published a newer list by now.
+    pub fn is_outdated(&self) -> bool {
+        match self.next_update {
+            Some(next) => next < SystemTime::now(),
+            None => false,
+        }
+    }
+
+    // Whether this list revokes the DER encoded certificate, certificates that can not be
+    // parsed count as revoked.
+    pub fn is_revoked(&self, cert: &rustls::Certificate) -> bool {
+        match x509_parser::parse_x509_certificate(&cert.0) {
+            Ok((_, cert)) => {
+                cert.issuer().as_raw() == self.issuer.as_slice()
+                    && self.serials.contains(cert.tbs_certificate.raw_serial())
+            }
+            Err(_) => true,
+        }
+    }
+}
+
+// Read all revocation lists from a file, either one in DER format or any number in PEM format.
+pub fn read_crls(filename: &str) -> Result<Vec<RevocationList>> {
+    let content = std::fs::read(filename)?;
+
+    let crls = if content.starts_with(b"-----BEGIN") {
+        let mut crls = vec![];
+        for pem in Pem::iter_from_buffer(&content) {
+            let pem = pem.map_err(|e| string_error::into_err(format!("{:?}", e)))?;
+            if pem.label == "X509 CRL" {
+                crls.push(parse(&pem.contents)?);
+            }
+        }
+        crls
+    } else {
+        vec![parse(&content)?]
+    };
+
+    if crls.is_empty() {
+        return Err(string_error::into_err(format!(
+            "no revocation lists in {}",
+            filename
+        )));
+    }
+    Ok(crls)
+}
+
+fn parse(der: &[u8]) -> Result<RevocationList> {
+    let (_, crl) = x509_parser::parse_x509_crl(der)
+        .map_err(|e| string_error::into_err(format!("invalid revocation list: {}", e)))?;
+    Ok(RevocationList::new(&crl))
+}
@@ -0,0 +1,145 @@
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime};
+use x509_parser::extensions::GeneralName;
+use x509_parser::x509::AttributeTypeAndValue;
+
+use super::Result;
+
+// The parts of a certificate that identify who it was issued to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CertificateInfo {
+    pub subject: String,
+    pub common_name: Option<String>,
+    pub organizational_units: Vec<String>,
+    pub subject_alt_names: Vec<SubjectAltName>,
+    pub issuer: String,
+    pub serial: String,
+    pub not_before: SystemTime,
+    pub not_after: SystemTime,
+    // SHA-256 of the DER encoding, formatted like openssl does: "AB:CD:..."
+    pub fingerprint: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SubjectAltName {
+    Dns(String),
+    Uri(String),
+    Email(String),
+    Ip(IpAddr),
+}
+
+impl CertificateInfo {
+    pub fn dns_names(&self) -> impl Iterator<Item = &str> {
+        self.subject_alt_names.iter().filter_map(|n| match n {
+            SubjectAltName::Dns(name) => Some(name.as_str()),
+            _ => None,
+        })
+    }
+
+    pub fn uris(&self) -> impl Iterator<Item = &str> {
+        self.subject_alt_names.iter().filter_map(|n| match n {
+            SubjectAltName::Uri(uri) => Some(uri.as_str()),
+            _ => None,
+        })
+    }
+
+    pub fn emails(&self) -> impl Iterator<Item = &str> {
+        self.subject_alt_names.iter().filter_map(|n| match n {
+            SubjectAltName::Email(email) => Some(email.as_str()),
+            _ => None,
+        })
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.not_after < SystemTime::now()
+    }
+}
+
+impl std::fmt::Display for SubjectAltName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SubjectAltName::Dns(name) => write!(f, "DNS:{}", name),
+            SubjectAltName::Uri(uri) => write!(f, "URI:{}", uri),
+            SubjectAltName::Email(email) => write!(f, "email:{}", email),
+            SubjectAltName::Ip(ip) => write!(f, "IP:{}", ip),
+        }
+    }
+}
+
+// Parse a DER encoded certificate.
+pub fn certificate_info(cert: &rustls::Certificate) -> Result<CertificateInfo> {
+    let fingerprint = fingerprint(cert);
+    let (_, cert) = x509_parser::parse_x509_certificate(&cert.0)
+        .map_err(|e| string_error::into_err(format!("invalid certificate: {}", e)))?;
+
+    let subject_alt_names = match cert.tbs_certificate.subject_alternative_name() {
+        Some((_, san)) => san
+            .general_names
+            .iter()
+            .filter_map(subject_alt_name)
+            .collect(),
+        None => vec![],
+    };
+
+    let validity = cert.validity();
+
+    Ok(CertificateInfo {
+        subject: cert.subject().to_string(),
+        common_name: strings(cert.subject().iter_common_name())
+            .into_iter()
+            .next(),
+        organizational_units: strings(cert.subject().iter_organizational_unit()),
+        subject_alt_names,
+        issuer: cert.issuer().to_string(),
+        serial: cert.tbs_certificate.raw_serial_as_string(),
+        not_before: system_time(validity.not_before.timestamp()),
+        not_after: system_time(validity.not_after.timestamp()),
+        fingerprint,
+    })
+}
+
+pub fn fingerprint(cert: &rustls::Certificate) -> String {
+    let digest = ring::digest::digest(&ring::digest::SHA256, &cert.0);
+    let hex: Vec<String> = digest
+        .as_ref()
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect();
+    hex.join(":")
+}
+
+fn strings<'a, 'b: 'a>(values: impl Iterator<Item = &'a AttributeTypeAndValue<'b>>) -> Vec<String> {
+    values
+        .filter_map(|v| v.as_str().ok().map(|s| s.to_string()))
+        .collect()
+}
+
+fn subject_alt_name(name: &GeneralName<'_>) -> Option<SubjectAltName> {
+    match name {
+        GeneralName::DNSName(name) => Some(SubjectAltName::Dns(name.to_string())),
+        GeneralName::URI(uri) => Some(SubjectAltName::Uri(uri.to_string())),
+        GeneralName::RFC822Name(email) => Some(SubjectAltName::Email(email.to_string())),
+        GeneralName::IPAddress(bytes) => match bytes.len() {
+            4 => {
+                let mut octets = [0u8; 4];
+                octets.copy_from_slice(bytes);
+                Some(SubjectAltName::Ip(IpAddr::from(octets)))
+            }
+            16 => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(bytes);
+                Some(SubjectAltName::Ip(IpAddr::from(octets)))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+pub(crate) fn system_time(timestamp: i64) -> SystemTime {
+    if timestamp >= 0 {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(timestamp as u64)
+    } else {
+        SystemTime::UNIX_EPOCH - Duration::from_secs(timestamp.unsigned_abs())
+    }
+}
@@ -1,7 +1,15 @@
 extern crate rustls;
 
+mod crl;
+mod info;
+mod ocsp;
+
 use std::io::Read;
 
+pub use crl::{read_crls, RevocationList};
+pub use info::{certificate_info, fingerprint, CertificateInfo, SubjectAltName};
+pub use ocsp::{parse_ocsp_response, read_ocsp_response, CertStatus, OcspResponse};
+
 pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
 pub fn make_server_config(
@@ -69,6 +77,11 @@ pub fn read_certs(filename: &str) -> Result<Vec<rustls::Certificate>> {
         .map_err(|_| string_error::new_err("failed to load certificates"))
 }
 
+// The subject common name of a DER encoded certificate, if it has one.
+pub fn common_name(cert: &rustls::Certificate) -> Option<String> {
+    certificate_info(cert).ok()?.common_name
+}
+
 pub fn dns_name(name: &str) -> webpki::DNSNameRef<'_> {
     webpki::DNSNameRef::try_from_ascii_str(name).unwrap()
 }
@@ -0,0 +1,403 @@
+use chrono::TimeZone;
+use std::time::SystemTime;
+use x509_parser::der_parser::der::*;
+use x509_parser::der_parser::error::{BerError, BerResult};
+use x509_parser::der_parser::oid::Oid;
+use x509_parser::nom;
+
+use super::Result;
+
+// The parts of an OCSP response needed to decide whether it can be stapled to a certificate. The
+// signature is not checked, that is up to the clients receiving the staple.
+#[derive(Debug, Clone)]
+pub struct OcspResponse {
+    der: Vec<u8>,
+    hash_algorithm: HashAlgorithm,
+    issuer_name_hash: Vec<u8>,
+    issuer_key_hash: Vec<u8>,
+    serial: Vec<u8>,
+    pub status: CertStatus,
+    pub this_update: SystemTime,
+    pub next_update: Option<SystemTime>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CertStatus {
+    Good,
+    Revoked,
+    Unknown,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum HashAlgorithm {
+    Sha1,
+    Sha256,
+}
+
+const OCSP_BASIC: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01, 0x01];
+const SHA1: &[u8] = &[0x2b, 0x0e, 0x03, 0x02, 0x1a];
+const SHA256: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01];
+
+impl OcspResponse {
+    // The response as read, to staple.
+    pub fn der(&self) -> &[u8] {
+        &self.der
+    }
+
+    pub fn is_expired(&self) -> bool {
+        match self.next_update {
+            Some(next) => next < SystemTime::now(),
+            None => false,
+        }
+    }
+
+    // Whether the response was produced for a later time than now, which a client would reject.
+    pub fn is_premature(&self) -> bool {
+        self.this_update > SystemTime::now()
+    }
+
+    // Whether the response is about this DER encoded certificate, as issued by the other.
+    pub fn matches(&self, cert: &rustls::Certificate, issuer: &rustls::Certificate) -> bool {
+        let (cert, issuer) = match (
+            x509_parser::parse_x509_certificate(&cert.0),
+            x509_parser::parse_x509_certificate(&issuer.0),
+        ) {
+            (Ok((_, cert)), Ok((_, issuer))) => (cert, issuer),
+            _ => return false,
+        };
+
+        self.is_about(
+            cert.issuer().as_raw(),
+            issuer.tbs_certificate.subject_pki.subject_public_key.data,
+            cert.tbs_certificate.raw_serial(),
+        )
+    }
+
+    // The certificate id holds hashes of the issuer's name and public key, and the serial.
+    fn is_about(&self, issuer_name: &[u8], issuer_key: &[u8], serial: &[u8]) -> bool {
+        let algorithm = match self.hash_algorithm {
+            HashAlgorithm::Sha1 => &ring::digest::SHA1_FOR_LEGACY_USE_ONLY,
+            HashAlgorithm::Sha256 => &ring::digest::SHA256,
+        };
+        let issuer_name_hash = ring::digest::digest(algorithm, issuer_name);
+        let issuer_key_hash = ring::digest::digest(algorithm, issuer_key);
+
+        serial == self.serial.as_slice()
+            && issuer_name_hash.as_ref() == self.issuer_name_hash.as_slice()
+            && issuer_key_hash.as_ref() == self.issuer_key_hash.as_slice()
+    }
+}
+
+pub fn read_ocsp_response(filename: &str) -> Result<OcspResponse> {
+    let der = std::fs::read(filename)?;
+    parse_ocsp_response(&der).map_err(|e| {
+        string_error::into_err(format!("invalid OCSP response in {}: {}", filename, e))
+    })
+}
+
+// Parse a DER encoded OCSP response, as described in RFC 6960. Only the first of the single
+// responses is looked at, responders answer for one certificate at a time.
+pub fn parse_ocsp_response(der: &[u8]) -> std::result::Result<OcspResponse, String> {
+    let (_, (status, bytes)) = ocsp_response(der).map_err(|e| e.to_string())?;
+    if status != 0 {
+        return Err(format!("response status is {}, not successful", status));
+    }
+    let (kind, basic) = bytes.ok_or_else(|| "no response bytes".to_string())?;
+    if kind.bytes() != OCSP_BASIC {
+        return Err("not a basic OCSP response".to_string());
+    }
+
+    let (_, single) = basic_ocsp_response(basic).map_err(|e| e.to_string())?;
+    let hash_algorithm = match single.hash_algorithm.bytes() {
+        SHA1 => HashAlgorithm::Sha1,
+        SHA256 => HashAlgorithm::Sha256,
+        _ => return Err("unsupported hash algorithm in certificate id".to_string()),
+    };
+
+    Ok(OcspResponse {
+        der: der.to_vec(),
+        hash_algorithm,
+        issuer_name_hash: single.issuer_name_hash.to_vec(),
+        issuer_key_hash: single.issuer_key_hash.to_vec(),
+        serial: single.serial.to_vec(),
+        status: single.status,
+        this_update: single.this_update,
+        next_update: single.next_update,
+    })
+}
+
+struct SingleResponse<'a> {
+    hash_algorithm: Oid<'a>,
+    issuer_name_hash: &'a [u8],
+    issuer_key_hash: &'a [u8],
+    serial: &'a [u8],
+    status: CertStatus,
+    this_update: SystemTime,
+    next_update: Option<SystemTime>,
+}
+
+// the response type and the response
+type ResponseBytes<'a> = (Oid<'a>, &'a [u8]);
+
+// OCSPResponse ::= SEQUENCE {
+//     responseStatus ENUMERATED,
+//     responseBytes  [0] EXPLICIT SEQUENCE { responseType OID, response OCTET STRING } OPTIONAL }
+fn ocsp_response(i: &[u8]) -> BerResult<'_, (u32, Option<ResponseBytes<'_>>)> {
+    parse_der_sequence_defined_g(|i, _| {
+        let (i, status) = parse_der_enum(i)?;
+        let (i, bytes) = optional(parse_der_tagged_explicit_g(0, |i, _| {
+            parse_der_sequence_defined_g(|i, _| {
+                let (i, kind) = parse_der_oid(i)?;
+                let (i, response) = parse_der_octetstring(i)?;
+                Ok((i, (kind.as_oid_val()?, response.as_slice()?)))
+            })(i)
+        }))(i)?;
+        Ok((i, (status.as_u32()?, bytes)))
+    })(i)
+}
+
+// BasicOCSPResponse ::= SEQUENCE { tbsResponseData ResponseData, signature... }
+// ResponseData ::= SEQUENCE {
+//     version     [0] EXPLICIT INTEGER OPTIONAL,
+//     responderID CHOICE { [1] Name, [2] KeyHash },
+//     producedAt  GeneralizedTime,
+//     responses   SEQUENCE OF SingleResponse,
+//     extensions... }
+fn basic_ocsp_response(i: &[u8]) -> BerResult<'_, SingleResponse<'_>> {
+    parse_der_sequence_defined_g(|i, _| {
+        parse_der_sequence_defined_g(|i, _| {
+            let (i, _) = optional(parse_der_tagged_explicit(0, parse_der_integer))(i)?;
+            let (i, _) = parse_der(i)?;
+            let (i, _) = parse_der_generalizedtime(i)?;
+            parse_der_sequence_defined_g(|i, _| single_response(i))(i)
+        })(i)
+    })(i)
+}
+
+// SingleResponse ::= SEQUENCE {
+//     certID     SEQUENCE {
+//         hashAlgorithm AlgorithmIdentifier,
+//         issuerNameHash OCTET STRING,
+//         issuerKeyHash OCTET STRING,
+//         serialNumber INTEGER },
+//     certStatus CHOICE {
+//         good [0] IMPLICIT NULL,
+//         revoked [1] IMPLICIT RevokedInfo,
+//         unknown [2] IMPLICIT NULL },
+//     thisUpdate GeneralizedTime,
+//     nextUpdate [0] EXPLICIT GeneralizedTime OPTIONAL,
+//     extensions... }
+fn single_response(i: &[u8]) -> BerResult<'_, SingleResponse<'_>> {
+    parse_der_sequence_defined_g(|i, _| {
+        let (i, (hash_algorithm, issuer_name_hash, issuer_key_hash, serial)) =
+            parse_der_sequence_defined_g(|i, _| {
+                let (i, algorithm) = parse_der_sequence_defined_g(|i, _| parse_der_oid(i))(i)?;
+                let (i, issuer_name_hash) = parse_der_octetstring(i)?;
+                let (i, issuer_key_hash) = parse_der_octetstring(i)?;
+                let (i, serial) = parse_der_integer(i)?;
+                Ok((
+                    i,
+                    (
+                        algorithm.as_oid_val()?,
+                        issuer_name_hash.as_slice()?,
+                        issuer_key_hash.as_slice()?,
+                        serial.as_slice()?,
+                    ),
+                ))
+            })(i)?;
+        let (i, status) = parse_der_container(|i, hdr| {
+            let status = match (hdr.class, hdr.tag.0) {
+                (DerClass::ContextSpecific, 0) => CertStatus::Good,
+                (DerClass::ContextSpecific, 1) => CertStatus::Revoked,
+                (DerClass::ContextSpecific, 2) => CertStatus::Unknown,
+                _ => return Err(nom::Err::Error(BerError::InvalidTag)),
+            };
+            Ok((i, status))
+        })(i)?;
+        let (i, this_update) = generalized_time(i)?;
+        let (i, next_update) =
+            optional(parse_der_tagged_explicit_g(0, |i, _| generalized_time(i)))(i)?;
+
+        Ok((
+            i,
+            SingleResponse {
+                hash_algorithm,
+                issuer_name_hash,
+                issuer_key_hash,
+                serial,
+                status,
+                this_update,
+                next_update,
+            },
+        ))
+    })(i)
+}
+
+// An optional element, which may be the last one.
+fn optional<'a, O, F>(f: F) -> impl FnMut(&'a [u8]) -> BerResult<'a, Option<O>>
+where
+    F: FnMut(&'a [u8]) -> BerResult<'a, O>,
+{
+    nom::combinator::opt(nom::combinator::complete(f))
+}
+
+// YYYYMMDDHHMMSS, optionally with fractional seconds, in UTC.
+fn generalized_time(i: &[u8]) -> BerResult<'_, SystemTime> {
+    let (i, time) = parse_der_generalizedtime(i)?;
+    let time = chrono::NaiveDateTime::parse_from_str(time.as_str()?, "%Y%m%d%H%M%S%.fZ")
+        .map_err(|_| BerError::BerValueError)?;
+    let time = chrono::Utc.from_utc_datetime(&time);
+    Ok((i, super::info::system_time(time.timestamp())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const INTEGER: u8 = 0x02;
+    const OCTET_STRING: u8 = 0x04;
+    const OID: u8 = 0x06;
+    const ENUMERATED: u8 = 0x0a;
+    const GENERALIZED_TIME: u8 = 0x18;
+    const SEQUENCE: u8 = 0x30;
+
+    fn context(n: u8) -> u8 {
+        0xa0 | n
+    }
+
+    fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+        let mut out = vec![tag];
+        if content.len() < 0x80 {
+            out.push(content.len() as u8);
+        } else {
+            out.extend_from_slice(&[0x81, content.len() as u8]);
+        }
+        out.extend_from_slice(content);
+        out
+    }
+
+    const ISSUER_NAME: &[u8] = b"issuer name";
+    const ISSUER_KEY: &[u8] = b"issuer key";
+    const SERIAL: &[u8] = &[0x12, 0x34];
+
+    fn sha1(data: &[u8]) -> Vec<u8> {
+        ring::digest::digest(&ring::digest::SHA1_FOR_LEGACY_USE_ONLY, data)
+            .as_ref()
+            .to_vec()
+    }
+
+    fn response(status: u8, next_update: Option<&str>) -> Vec<u8> {
+        response_at(status, "20200101000000Z", next_update)
+    }
+
+    fn response_at(status: u8, this_update: &str, next_update: Option<&str>) -> Vec<u8> {
+        let cert_id = der(
+            SEQUENCE,
+            &[
+                der(SEQUENCE, &der(OID, SHA1)),
+                der(OCTET_STRING, &sha1(ISSUER_NAME)),
+                der(OCTET_STRING, &sha1(ISSUER_KEY)),
+                der(INTEGER, SERIAL),
+            ]
+            .concat(),
+        );
+        let mut single = [
+            cert_id,
+            der(status, &[]),
+            der(GENERALIZED_TIME, this_update.as_bytes()),
+        ]
+        .concat();
+        if let Some(time) = next_update {
+            single.extend(der(context(0), &der(GENERALIZED_TIME, time.as_bytes())));
+        }
+
+        let data = der(
+            SEQUENCE,
+            &[
+                der(context(2), &der(OCTET_STRING, &[3; 20])),
+                der(GENERALIZED_TIME, b"20200101000000Z"),
+                der(SEQUENCE, &der(SEQUENCE, &single)),
+            ]
+            .concat(),
+        );
+        let basic = der(SEQUENCE, &data);
+        let bytes = der(
+            SEQUENCE,
+            &[der(OID, OCSP_BASIC), der(OCTET_STRING, &basic)].concat(),
+        );
+        der(
+            SEQUENCE,
+            &[der(ENUMERATED, &[0]), der(context(0), &bytes)].concat(),
+        )
+    }
+
+    #[test]
+    fn parses_a_response() {
+        let response = parse_ocsp_response(&response(0x80, Some("20300101000000Z"))).unwrap();
+
+        assert_eq!(CertStatus::Good, response.status);
+        assert_eq!(vec![0x12, 0x34], response.serial);
+        assert_eq!(sha1(ISSUER_NAME), response.issuer_name_hash);
+        assert_eq!(sha1(ISSUER_KEY), response.issuer_key_hash);
+        assert_eq!(
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1577836800),
+            response.this_update
+        );
+        assert_eq!(
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1893456000)),
+            response.next_update
+        );
+        assert!(!response.is_expired());
+    }
+
+    #[test]
+    fn tells_expired_responses() {
+        let expired = parse_ocsp_response(&response(0x80, Some("20200102000000Z"))).unwrap();
+        assert!(expired.is_expired());
+
+        let open_ended = parse_ocsp_response(&response(0x80, None)).unwrap();
+        assert!(!open_ended.is_expired());
+    }
+
+    #[test]
+    fn tells_premature_responses() {
+        let premature = parse_ocsp_response(&response_at(0x80, "20990101000000Z", None)).unwrap();
+        assert!(premature.is_premature());
+
+        let current = parse_ocsp_response(&response(0x80, None)).unwrap();
+        assert!(!current.is_premature());
+    }
+
+    #[test]
+    fn matches_the_certificate_id() {
+        let response = parse_ocsp_response(&response(0x80, None)).unwrap();
+
+        assert!(response.is_about(ISSUER_NAME, ISSUER_KEY, SERIAL));
+        assert!(!response.is_about(ISSUER_NAME, ISSUER_KEY, &[0x12, 0x35]));
+        assert!(!response.is_about(b"other name", ISSUER_KEY, SERIAL));
+        // the same name, but another issuer
+        assert!(!response.is_about(ISSUER_NAME, b"other key", SERIAL));
+    }
+
+    #[test]
+    fn parses_the_status() {
+        let revoked = parse_ocsp_response(&response(0xa1, None)).unwrap();
+        assert_eq!(CertStatus::Revoked, revoked.status);
+
+        let unknown = parse_ocsp_response(&response(0x82, None)).unwrap();
+        assert_eq!(CertStatus::Unknown, unknown.status);
+    }
+
+    #[test]
+    fn rejects_unsuccessful_responses() {
+        let unauthorized = der(SEQUENCE, &der(ENUMERATED, &[6]));
+        assert!(parse_ocsp_response(&unauthorized).is_err());
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        assert!(parse_ocsp_response(b"not a response").is_err());
+        assert!(parse_ocsp_response(&response(0x80, None)[..40]).is_err());
+    }
+}
@@ -2,16 +2,9 @@ mod fixture;
 
 use fixture::Fixture;
 
-const BASE_PORT: u16 = 6000;
-const PORT_STEP: u16 = 6;
-
-fn base_port(num: u16) -> u16 {
-    BASE_PORT + num * PORT_STEP
-}
-
 #[test]
 fn tcp_echo() {
-    let fix = Fixture::new(base_port(0));
+    let fix = Fixture::new();
 
     let mut client = fix.tcp_echo_client();
     client.assert_can_echo();
@@ -19,7 +12,7 @@ fn tcp_echo() {
 
 #[test]
 fn tcp_fibonacci() {
-    let fix = Fixture::new(base_port(1));
+    let fix = Fixture::new();
 
     let mut client = fix.tcp_fibonacci_client();
     client.assert_can_listen();
@@ -27,7 +20,7 @@ fn tcp_fibonacci() {
 
 #[test]
 fn tls_echo() {
-    let fix = Fixture::new(base_port(2));
+    let fix = Fixture::new();
 
     let mut client = fix.tls_echo_client("this-root");
     client.assert_can_echo();
@@ -35,7 +28,7 @@ fn tls_echo() {
 
 #[test]
 fn tls_rejects() {
-    let fix = Fixture::new(base_port(3));
+    let fix = Fixture::new();
 
     let mut client = fix.tls_echo_client("other-root");
     client.assert_rejected();
@@ -43,7 +36,7 @@ fn tls_rejects() {
 
 #[test]
 fn tls_fib() {
-    let fix = Fixture::new(base_port(4));
+    let fix = Fixture::new();
 
     let mut client = fix.tls_fib_client("this-root", "this-client");
     client.assert_can_listen();
@@ -51,8 +44,40 @@ fn tls_fib() {
 
 #[test]
 fn tls_reject_client() {
-    let fix = Fixture::new(base_port(5));
+    let fix = Fixture::new();
 
     let mut client = fix.tls_fib_client("this-root", "other-client");
     client.assert_rejected();
 }
+
+#[test]
+fn config_echo() {
+    let fix = Fixture::new();
+
+    let mut client = fix.config_echo_client("this-root");
+    client.assert_can_echo();
+}
+
+#[test]
+fn config_fib() {
+    let fix = Fixture::new();
+
+    let mut client = fix.config_fib_client("this-root", "this-client");
+    client.assert_can_listen();
+}
+
+#[test]
+fn config_reject_client() {
+    let fix = Fixture::new();
+
+    let mut client = fix.config_fib_client("this-root", "other-client");
+    client.assert_rejected();
+}
+
+#[test]
+fn tunnel_fib() {
+    let fix = Fixture::new();
+
+    let mut client = fix.tunnel_fib_client();
+    client.assert_can_listen();
+}
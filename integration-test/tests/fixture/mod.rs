@@ -2,12 +2,42 @@ extern crate escargot;
 extern crate tempfile;
 
 use std::io::{BufRead, Read, Write};
-use std::process::{Child, Stdio};
+use std::process::{Child, Command, Stdio};
 
 struct ChildProcess {
     child: Child,
 }
 
+impl ChildProcess {
+    // Start the process and wait until it announced the ports of its listeners.
+    fn start(command: &mut Command, listeners: usize) -> (ChildProcess, Vec<u16>) {
+        let mut child = command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("spawn");
+        let mut reader = std::io::BufReader::new(child.stdout.take().unwrap());
+        let process = ChildProcess { child };
+
+        let mut ports = Vec::new();
+        while ports.len() < listeners {
+            let mut line = String::new();
+            if reader.read_line(&mut line).expect("read") == 0 {
+                panic!("process exited before announcing its ports");
+            }
+            if let Some(address) = line.trim_end().strip_prefix("LISTEN ") {
+                let address: std::net::SocketAddr = address.parse().expect("address");
+                ports.push(address.port());
+            }
+        }
+
+        // keep reading whatever else comes out, so the process never blocks on a full pipe
+        std::thread::spawn(move || std::io::copy(&mut reader, &mut std::io::sink()));
+
+        (process, ports)
+    }
+}
+
 impl Drop for ChildProcess {
     fn drop(&mut self) {
         let _ = self.child.kill();
@@ -33,86 +63,111 @@ pub struct Fixture {
     #[allow(dead_code)]
     tls_fib: ChildProcess,
     tls_fib_port: u16,
+
+    #[allow(dead_code)]
+    tls_config: ChildProcess,
+    config_echo_port: u16,
+    config_fib_port: u16,
+
+    #[allow(dead_code)]
+    tunnel: ChildProcess,
+    tunnel_port: u16,
 }
 
 impl Fixture {
-    pub fn new(base_port: u16) -> Fixture {
+    pub fn new() -> Fixture {
         let tempdir = tempfile::TempDir::new().unwrap();
 
-        let echo_port = base_port;
-        let fib_port = base_port + 1;
-        let tls_echo_port = base_port + 2;
-        let tls_fib_port = base_port + 3;
-
         certgen(tempdir.path(), "this-root", &["this-server", "this-client"]);
         certgen(tempdir.path(), "other-root", &["other-client"]);
 
-        let tcp_echo = ChildProcess {
-            child: escargot::CargoBuild::new()
-                .manifest_path(manifest())
-                .bin("tcp-echo")
-                .run()
-                .expect("cargo run")
-                .command()
-                .arg(format!("--port={}", base_port))
-                .stdout(Stdio::null())
-                .spawn()
-                .expect("spawn"),
-        };
-        let tcp_fib = ChildProcess {
-            child: escargot::CargoBuild::new()
-                .manifest_path(manifest())
-                .bin("tcp-fibonacci")
-                .run()
-                .expect("cargo run")
-                .command()
-                .arg(format!("--port={}", fib_port))
+        let (tcp_echo, ports) = ChildProcess::start(bin("tcp-echo").arg("--port=0"), 1);
+        let echo_port = ports[0];
+
+        let (tcp_fib, ports) = ChildProcess::start(
+            bin("tcp-fibonacci")
+                .arg("--port=0")
                 .arg("--number=10")
-                .arg("--interval=0.01")
-                .stdout(Stdio::null())
-                .spawn()
-                .expect("spawn"),
-        };
-        let tls_echo = ChildProcess {
-            child: escargot::CargoBuild::new()
-                .manifest_path(manifest())
-                .bin("katey-el-es")
-                .run()
-                .expect("cargo run")
-                .command()
-                .arg(format!("{}", tls_echo_port))
+                .arg("--interval=0.01"),
+            1,
+        );
+        let fib_port = ports[0];
+
+        let (tls_echo, ports) = ChildProcess::start(
+            bin("katey-el-es")
+                .arg("0")
                 .arg(format!("127.0.0.1:{}", echo_port))
                 .arg("--cert")
                 .arg(certfile(tempdir.path(), "this-server"))
                 .arg("--key")
-                .arg(keyfile(tempdir.path(), "this-server"))
-                .stdout(Stdio::null())
-                .spawn()
-                .expect("spawn"),
-        };
-        let tls_fib = ChildProcess {
-            child: escargot::CargoBuild::new()
-                .manifest_path(manifest())
-                .bin("katey-el-es")
-                .run()
-                .expect("cargo run")
-                .command()
-                .arg(format!("{}", tls_fib_port))
+                .arg(keyfile(tempdir.path(), "this-server")),
+            1,
+        );
+        let tls_echo_port = ports[0];
+
+        let (tls_fib, ports) = ChildProcess::start(
+            bin("katey-el-es")
+                .arg("0")
                 .arg(format!("127.0.0.1:{}", fib_port))
                 .arg("--cert")
                 .arg(certfile(tempdir.path(), "this-server"))
                 .arg("--key")
                 .arg(keyfile(tempdir.path(), "this-server"))
                 .arg("--authenticate")
+                .arg(certfile(tempdir.path(), "this-root")),
+            1,
+        );
+        let tls_fib_port = ports[0];
+
+        let config_file = format!(
+            "{}/katey.toml",
+            tempdir.path().as_os_str().to_str().unwrap()
+        );
+        std::fs::write(
+            &config_file,
+            format!(
+                r#"
+                [[listener]]
+                port = 0
+                forward = "127.0.0.1:{}"
+                cert = "{}"
+                key = "{}"
+
+                [[listener]]
+                port = 0
+                forward = "127.0.0.1:{}"
+                cert = "{}"
+                key = "{}"
+                authenticate = "{}"
+                "#,
+                echo_port,
+                certfile(tempdir.path(), "this-server"),
+                keyfile(tempdir.path(), "this-server"),
+                fib_port,
+                certfile(tempdir.path(), "this-server"),
+                keyfile(tempdir.path(), "this-server"),
+                certfile(tempdir.path(), "this-root"),
+            ),
+        )
+        .expect("write config");
+        let (tls_config, ports) =
+            ChildProcess::start(bin("katey-el-es").arg("--config").arg(config_file), 2);
+        let config_echo_port = ports[0];
+        let config_fib_port = ports[1];
+
+        let (tunnel, ports) = ChildProcess::start(
+            bin("katey-client")
+                .arg(format!("localhost:{}", tls_fib_port))
+                .arg("--root")
                 .arg(certfile(tempdir.path(), "this-root"))
-                .stdout(Stdio::null())
-                .spawn()
-                .expect("spawn"),
-        };
-
-        for port in &[echo_port, fib_port, tls_echo_port, tls_fib_port] {
-            wait_for(*port, 1.0).expect("port");
-        }
+                .arg("--cert")
+                .arg(certfile(tempdir.path(), "this-client"))
+                .arg("--key")
+                .arg(keyfile(tempdir.path(), "this-client"))
+                .arg("--listen=0"),
+            1,
+        );
+        let tunnel_port = ports[0];
 
         Fixture {
             tempdir,
@@ -124,6 +179,11 @@ impl Fixture {
             tls_echo_port,
             tls_fib,
             tls_fib_port,
+            tls_config,
+            config_echo_port,
+            config_fib_port,
+            tunnel,
+            tunnel_port,
         }
     }
 
@@ -143,6 +203,18 @@ impl Fixture {
         self.tls_client(self.tls_fib_port, root, Some(name))
     }
 
+    pub fn config_echo_client(&self, root: &str) -> Client {
+        self.tls_client(self.config_echo_port, root, None)
+    }
+
+    pub fn config_fib_client(&self, root: &str, name: &str) -> Client {
+        self.tls_client(self.config_fib_port, root, Some(name))
+    }
+
+    pub fn tunnel_fib_client(&self) -> Client {
+        Fixture::tcp_client(self.tunnel_port)
+    }
+
     fn tcp_client(port: u16) -> Client {
         let mut process = escargot::CargoBuild::new()
             .manifest_path(manifest())
@@ -262,18 +334,13 @@ impl Drop for Client {
     }
 }
 
-fn wait_for(port: u16, timeout: f64) -> std::result::Result<(), &'static str> {
-    use std::time::{Duration, Instant};
-
-    let address = format!("127.0.0.1:{}", port);
-    let end = Instant::now() + Duration::from_secs_f64(timeout);
-
-    while Instant::now() <= end {
-        if std::net::TcpStream::connect(&address).is_ok() {
-            return Ok(());
-        }
-    }
-    Err("timeout")
+fn bin(name: &str) -> Command {
+    escargot::CargoBuild::new()
+        .manifest_path(manifest())
+        .bin(name)
+        .run()
+        .expect("cargo run")
+        .command()
 }
 
 fn manifest() -> std::path::PathBuf {
@@ -2,11 +2,28 @@ extern crate log;
 extern crate tokio;
 
 use std::marker::Unpin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
 const BUFSIZE: usize = 512;
 
-pub async fn copy<T, U>(mut from: T, mut to: U) -> std::io::Result<()>
+pub async fn copy<T, U>(from: T, to: U) -> std::io::Result<()>
+where
+    T: AsyncReadExt + Unpin,
+    U: AsyncWriteExt + Unpin,
+{
+    let mut copied = 0;
+    copy_counting(from, to, &mut copied, &Activity::new()).await
+}
+
+// Like copy, keeping count of the bytes copied so far, also when it fails or is cancelled.
+async fn copy_counting<T, U>(
+    mut from: T,
+    mut to: U,
+    copied: &mut u64,
+    activity: &Activity,
+) -> std::io::Result<()>
 where
     T: AsyncReadExt + Unpin,
     U: AsyncWriteExt + Unpin,
@@ -31,10 +48,107 @@ where
             log::debug!("write error: {}", e);
             return Err(e);
         }
+        *copied += n as u64;
+        activity.touch();
     }
 }
 
+// When bytes last went either way, shared by both directions of a transfer.
+struct Activity {
+    start: Instant,
+    // milliseconds since the start
+    last: AtomicU64,
+}
+
+impl Activity {
+    fn new() -> Activity {
+        Activity {
+            start: Instant::now(),
+            last: AtomicU64::new(0),
+        }
+    }
+
+    fn touch(&self) {
+        let now = self.start.elapsed().as_millis() as u64;
+        self.last.store(now, Ordering::Relaxed);
+    }
+
+    fn idle_for(&self) -> Duration {
+        let last = Duration::from_millis(self.last.load(Ordering::Relaxed));
+        self.start.elapsed().checked_sub(last).unwrap_or_default()
+    }
+
+    // Resolves once nothing was copied for the timeout, never without one.
+    async fn idle(&self, timeout: Option<Duration>) {
+        let timeout = match timeout {
+            Some(timeout) => timeout,
+            None => return std::future::pending().await,
+        };
+        loop {
+            let idle = self.idle_for();
+            if idle >= timeout {
+                return;
+            }
+            tokio::time::delay_for(timeout - idle).await;
+        }
+    }
+}
+
+async fn expire(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::delay_until(deadline.into()).await,
+        None => std::future::pending().await,
+    }
+}
+
+// Which of the two proxied streams ended the transfer, by closing or failing.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Side {
+    First,
+    Second,
+}
+
+// How a transfer ended.
+#[derive(Debug)]
+pub enum Ending {
+    Closed(Side),
+    Failed(Side, std::io::Error),
+    // nothing went either way for the idle timeout
+    Idle(Duration),
+    // the deadline passed
+    Expired,
+}
+
+// What went through a proxied pair of streams until it ended.
+#[derive(Debug)]
+pub struct Transfer {
+    pub first_to_second: u64,
+    pub second_to_first: u64,
+    pub ending: Ending,
+}
+
+// When to end a transfer while both streams are still open.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Limits {
+    pub idle_timeout: Option<Duration>,
+    pub deadline: Option<Instant>,
+}
+
 pub async fn proxy<T, U, V, W>(stream1: (T, U), stream2: (V, W)) -> std::io::Result<()>
+where
+    T: AsyncReadExt + Unpin,
+    U: AsyncWriteExt + Unpin,
+    V: AsyncReadExt + Unpin,
+    W: AsyncWriteExt + Unpin,
+{
+    match transfer(stream1, stream2, Limits::default()).await.ending {
+        Ending::Failed(_, e) => Err(e),
+        _ => Ok(()),
+    }
+}
+
+// Copy both ways until either stream ends or a limit is reached, and tell what was transferred.
+pub async fn transfer<T, U, V, W>(stream1: (T, U), stream2: (V, W), limits: Limits) -> Transfer
 where
     T: AsyncReadExt + Unpin,
     U: AsyncWriteExt + Unpin,
@@ -43,33 +157,51 @@ where
 {
     let (rx1, tx1) = stream1;
     let (rx2, tx2) = stream2;
+    let mut first_to_second = 0;
+    let mut second_to_first = 0;
+    let activity = Activity::new();
 
     // Q: select or join?
-    tokio::select! {
-        x = copy(rx1, tx2) => {
+    let ending = tokio::select! {
+        x = copy_counting(rx1, tx2, &mut first_to_second, &activity) => {
             match x {
                 Ok(_) => {
                     log::info!("rx1->tx2 completed");
-                    Ok(())
+                    Ending::Closed(Side::First)
                 },
                 Err(e) => {
                     log::warn!("rx1->tx2 errored: {}", e);
-                    Err(e)
+                    Ending::Failed(Side::First, e)
                 }
             }
         },
-        x = copy(rx2, tx1) => {
+        x = copy_counting(rx2, tx1, &mut second_to_first, &activity) => {
             match x {
                 Ok(_) => {
                     log::info!("rx2->tx1 completed");
-                    Ok(())
+                    Ending::Closed(Side::Second)
                 },
                 Err(e) => {
                     log::warn!("rx2->tx1 errored: {}", e);
-                    Err(e)
+                    Ending::Failed(Side::Second, e)
                 }
             }
         }
+        _ = activity.idle(limits.idle_timeout) => {
+            let idle = activity.idle_for();
+            log::info!("idle for {:?}, ending", idle);
+            Ending::Idle(idle)
+        }
+        _ = expire(limits.deadline) => {
+            log::info!("deadline passed, ending");
+            Ending::Expired
+        }
+    };
+
+    Transfer {
+        first_to_second,
+        second_to_first,
+        ending,
     }
 }
 
@@ -166,4 +298,55 @@ mod tests {
             .await
             .expect_err("err");
     }
+
+    #[tokio::test]
+    async fn transfer_counts() {
+        let mut reader: &[u8] = b"hello";
+        let mut writer: Vec<u8> = vec![];
+
+        let transfer = transfer(
+            (&mut reader, sink()),
+            (NeverReady {}, &mut writer),
+            Limits::default(),
+        )
+        .await;
+
+        assert_eq!(5, transfer.first_to_second);
+        assert_eq!(0, transfer.second_to_first);
+        assert!(matches!(transfer.ending, Ending::Closed(Side::First)));
+    }
+
+    #[tokio::test]
+    async fn transfer_tells_errors() {
+        let transfer = transfer(
+            (NeverReady {}, sink()),
+            (AlwaysBad {}, sink()),
+            Limits::default(),
+        )
+        .await;
+
+        assert!(matches!(transfer.ending, Ending::Failed(Side::Second, _)));
+    }
+
+    #[tokio::test]
+    async fn transfer_ends_when_idle() {
+        let limits = Limits {
+            idle_timeout: Some(Duration::from_millis(10)),
+            deadline: None,
+        };
+        let transfer = transfer((NeverReady {}, sink()), (NeverReady {}, sink()), limits).await;
+
+        assert!(matches!(transfer.ending, Ending::Idle(idle) if idle >= Duration::from_millis(10)));
+    }
+
+    #[tokio::test]
+    async fn transfer_ends_at_the_deadline() {
+        let limits = Limits {
+            idle_timeout: None,
+            deadline: Some(Instant::now() + Duration::from_millis(10)),
+        };
+        let transfer = transfer((NeverReady {}, sink()), (NeverReady {}, sink()), limits).await;
+
+        assert!(matches!(transfer.ending, Ending::Expired));
+    }
 }
@@ -8,7 +8,7 @@ extern crate tokio;
 use futures::future::Future;
 use std::io::Read;
 use std::sync::Arc;
-use tokio_rustls::TlsConnector;
+use tokio_rustls::{webpki, TlsConnector};
 
 pub type Stream = tokio_rustls::client::TlsStream<tokio::net::TcpStream>;
 
@@ -19,16 +19,16 @@ pub struct Config<'a> {
     address: &'a str,
     threaded: bool,
     shutdown_timeout: std::time::Duration,
-    tls: rustls::ClientConfig,
+    connector: Connector,
 }
 
 impl Config<'_> {
-    pub fn new(address: &str) -> Config {
+    pub fn new(address: &str) -> Config<'_> {
         Config {
             address,
             threaded: false,
             shutdown_timeout: std::time::Duration::from_secs(1),
-            tls: rustls::ClientConfig::new(),
+            connector: Connector::new(),
         }
     }
 
@@ -43,9 +43,55 @@ impl Config<'_> {
     }
 
     pub fn with_root(&mut self, root: &str) -> Result<&mut Self> {
-        let root = Config::load_file(root)?;
+        self.connector.with_root(root)?;
+        Ok(self)
+    }
+
+    pub fn with_certificate_and_key_files(
+        &mut self,
+        certfile: &str,
+        keyfile: &str,
+    ) -> Result<&mut Self> {
+        self.connector
+            .with_certificate_and_key_files(certfile, keyfile)?;
+        Ok(self)
+    }
+
+    pub fn with_server_name(&mut self, name: &str) -> Result<&mut Self> {
+        self.connector.with_server_name(name)?;
+        Ok(self)
+    }
+
+    pub fn connector(&self) -> Connector {
+        self.connector.clone()
+    }
+}
+
+// Sets up TLS over connections made elsewhere, for users that run their own runtime.
+#[derive(Clone)]
+pub struct Connector {
+    tls: Arc<rustls::ClientConfig>,
+    server_name: Option<String>,
+}
+
+impl Default for Connector {
+    fn default() -> Connector {
+        Connector::new()
+    }
+}
+
+impl Connector {
+    pub fn new() -> Connector {
+        Connector {
+            tls: Arc::new(rustls::ClientConfig::new()),
+            server_name: None,
+        }
+    }
+
+    pub fn with_root(&mut self, root: &str) -> Result<&mut Self> {
+        let root = Connector::load_file(root)?;
         let mut reader = std::io::BufReader::new(root.as_bytes());
-        self.tls
+        Arc::make_mut(&mut self.tls)
             .root_store
             .add_pem_file(&mut reader)
             .map_err(|_| string_error::static_err("failed to add certificate to root store"))?;
@@ -59,10 +105,46 @@ impl Config<'_> {
     ) -> Result<&mut Self> {
         let cert = certutils::read_certs(certfile)?;
         let key = certutils::read_key(keyfile)?;
-        self.tls.set_single_client_cert(cert, key)?;
+        Arc::make_mut(&mut self.tls).set_single_client_cert(cert, key)?;
         Ok(self)
     }
 
+    // The name to verify the server certificate against, instead of the host part of the address.
+    pub fn with_server_name(&mut self, name: &str) -> Result<&mut Self> {
+        webpki::DNSNameRef::try_from_ascii_str(name)
+            .map_err(|_| string_error::into_err(format!("invalid server name {}", name)))?;
+        self.server_name = Some(name.to_string());
+        Ok(self)
+    }
+
+    pub async fn connect(
+        &self,
+        address: &str,
+        stream: tokio::net::TcpStream,
+    ) -> std::io::Result<Stream> {
+        let name = self
+            .server_name
+            .as_deref()
+            .unwrap_or_else(|| Connector::domain(address));
+        let name = webpki::DNSNameRef::try_from_ascii_str(name).map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("invalid server name {}", name),
+            )
+        })?;
+
+        TlsConnector::from(self.tls.clone())
+            .connect(name, stream)
+            .await
+    }
+
+    fn domain(address: &str) -> &str {
+        match address.rfind(':') {
+            Some(n) => &address[..n],
+            None => address,
+        }
+    }
+
     fn load_file(filename: &str) -> Result<String> {
         let mut file = std::fs::File::open(filename)?;
         let mut content = String::new();
@@ -106,11 +188,9 @@ impl Client<'_> {
         match self.runtime.take() {
             Some(mut rt) => {
                 let res = rt.block_on(async {
-                    let dom = Client::domain(self.config.address);
-                    let cfg = self.config.tls.clone();
-                    let connector = TlsConnector::from(Arc::new(cfg));
-                    let stream = tokio::net::TcpStream::connect(self.config.address).await?;
-                    let stream = connector.connect(certutils::dns_name(dom), stream).await?;
+                    let address = self.config.address;
+                    let stream = tokio::net::TcpStream::connect(address).await?;
+                    let stream = self.config.connector.connect(address, stream).await?;
                     Ok(handler(stream).await)
                 });
                 self.wait(rt);
@@ -127,13 +207,6 @@ impl Client<'_> {
         );
         rt.shutdown_timeout(self.config.shutdown_timeout);
     }
-
-    fn domain(address: &str) -> &str {
-        match address.find(':') {
-            Some(n) => &address[..n],
-            None => address,
-        }
-    }
 }
 
 impl Drop for Client<'_> {
@@ -1,10 +1,13 @@
 extern crate clap;
 extern crate io_copy;
+extern crate log;
 extern crate simple_logger;
+extern crate tcp_server;
 extern crate tokio;
 
 use io_copy::proxy;
 use tokio::io::{split, stdin, stdout};
+use tokio::net::TcpStream;
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
@@ -47,10 +50,33 @@ fn main() -> Result<()> {
                 .requires("cert")
                 .takes_value(true)
         )
+        .arg(
+            clap::Arg::with_name("listen")
+                .help("instead of using stdin and stdout, listen on this port and tunnel every connection over tls to the address, 0 picks a free port")
+                .short("l")
+                .long("listen")
+                .takes_value(true)
+        )
+        .arg(
+            clap::Arg::with_name("public")
+                .help("when listening, bind publicly to 0.0.0.0 instead of 127.0.0.1")
+                .long("public")
+                .requires("listen")
+        )
         .get_matches();
 
-    if args.is_present("debug") {
-        simple_logger::init()?;
+    let level = if args.is_present("debug") {
+        Some(log::LevelFilter::Debug)
+    } else if args.is_present("listen") {
+        Some(log::LevelFilter::Info)
+    } else {
+        None
+    };
+    if let Some(level) = level {
+        simple_logger::SimpleLogger::new()
+            .with_level(level)
+            .with_utc_timestamps()
+            .init()?;
     }
 
     let address = args.value_of("address").unwrap();
@@ -70,10 +96,59 @@ fn main() -> Result<()> {
         )?;
     }
 
+    if let Some(port) = args.value_of("listen") {
+        return tunnel(
+            port.parse()?,
+            args.is_present("public"),
+            address,
+            config.connector(),
+        );
+    }
+
     let mut client = katey_client::Client::new(config)?;
     client.run(handle).and_then(|r| r.map_err(|e| e.into()))
 }
 
+fn tunnel(
+    port: u16,
+    public: bool,
+    address: &str,
+    connector: katey_client::Connector,
+) -> Result<()> {
+    log::info!("tunneling connections on port {} to {}", port, address);
+
+    let config = tcp_server::Config::new(port).with_public(public);
+    let server = tcp_server::Server::new(config)?;
+    let address = address.to_string();
+    let running = server.spawn(move |stream, _context| {
+        let address = address.clone();
+        let connector = connector.clone();
+        forward(stream, address, connector)
+    })?;
+    println!("LISTEN {}", running.address());
+    running.join()
+}
+
+async fn forward(stream: tcp_server::Stream, address: String, connector: katey_client::Connector) {
+    let remote = match TcpStream::connect(&address).await {
+        Ok(remote) => remote,
+        Err(e) => {
+            log::error!("could not connect to {}: {}", address, e);
+            return;
+        }
+    };
+
+    let remote = match connector.connect(&address, remote).await {
+        Ok(remote) => remote,
+        Err(e) => {
+            log::error!("could not set up tls with {}: {}", address, e);
+            return;
+        }
+    };
+
+    let _ = proxy(split(stream), split(remote)).await;
+}
+
 async fn handle(stream: katey_client::Stream) -> std::io::Result<()> {
     let stream = split(stream);
     let input = stdin();
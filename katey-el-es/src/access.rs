@@ -0,0 +1,397 @@
+use io_copy::{Ending, Side, Transfer};
+use serde::Serialize;
+use std::io::Write;
+use std::sync::{mpsc, Arc, Mutex};
+
+use super::Result;
+
+// Where to write a record of every finished connection, as one JSON object per line. Records
+// are written by a thread of their own, so connections never wait for the disk.
+#[derive(Clone)]
+pub struct AccessLog {
+    records: mpsc::Sender<String>,
+    writer: Arc<Mutex<Option<std::thread::JoinHandle<()>>>>,
+}
+
+// Why a connection ended.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Termination {
+    ClientClose,
+    BackendClose,
+    Error,
+    Timeout,
+    Shutdown,
+}
+
+#[derive(Debug, Serialize)]
+struct Record {
+    timestamp: String,
+    connection: u64,
+    client: String,
+    sni: Option<String>,
+    alpn: Option<String>,
+    client_subject: Option<String>,
+    backend: Option<String>,
+    bytes_in: u64,
+    bytes_out: u64,
+    duration: f64,
+    handshake_time: Option<f64>,
+    termination: Termination,
+    error: Option<String>,
+}
+
+// Collects what happens to a connection, to be written to the access log once it is finished.
+// A connection dropped before it finished is written as timed out when it was cut at its maximum
+// lifetime, and as ended by shutdown otherwise, as happens to those cut when the server stops.
+pub struct Entry {
+    log: Option<AccessLog>,
+    context: tls_server::ConnectionContext,
+    record: Option<Record>,
+}
+
+impl AccessLog {
+    // Append to the file, or write to stdout for "-".
+    pub fn open(path: &str) -> Result<AccessLog> {
+        let out: Box<dyn Write + Send> = if path == "-" {
+            Box::new(std::io::stdout())
+        } else {
+            Box::new(
+                std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)?,
+            )
+        };
+
+        let (records, received) = mpsc::channel();
+        let writer = std::thread::spawn(move || AccessLog::write_all(out, received));
+        Ok(AccessLog {
+            records,
+            writer: Arc::new(Mutex::new(Some(writer))),
+        })
+    }
+
+    // Wait for every record so far to be written. The writer only finishes once all clones of the
+    // log are gone, so only close after the servers using them stopped.
+    pub fn close(self) {
+        let writer = self.writer.lock().unwrap().take();
+        drop(self.records);
+        if let Some(writer) = writer {
+            if writer.join().is_err() {
+                log::error!("access log writer panicked");
+            }
+        }
+    }
+
+    // The connection never made it past the handshake.
+    pub fn rejected(
+        &self,
+        context: &tls_server::ConnectionContext,
+        rejection: &tls_server::Rejection,
+    ) {
+        let mut record = Record::new(context);
+        record.termination = match rejection {
+            tls_server::Rejection::TimedOut => Termination::Timeout,
+            _ => Termination::Error,
+        };
+        record.error = Some(rejection.to_string());
+        record.duration = context.elapsed().as_secs_f64();
+        self.write(&record);
+    }
+
+    fn write(&self, record: &Record) {
+        let line = match serde_json::to_string(record) {
+            Ok(line) => line,
+            Err(e) => {
+                log::error!("could not format access record: {}", e);
+                return;
+            }
+        };
+
+        if self.records.send(line).is_err() {
+            log::error!("could not write access record, the writer is gone");
+        }
+    }
+
+    fn write_all(mut out: Box<dyn Write + Send>, records: mpsc::Receiver<String>) {
+        for line in records {
+            if let Err(e) = writeln!(out, "{}", line).and_then(|_| out.flush()) {
+                log::error!("could not write access record: {}", e);
+            }
+        }
+    }
+}
+
+impl Record {
+    fn new(context: &tls_server::ConnectionContext) -> Record {
+        Record {
+            timestamp: chrono::DateTime::<chrono::Utc>::from(context.accepted_at())
+                .to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            connection: context.id(),
+            client: context.peer_addr().to_string(),
+            sni: None,
+            alpn: None,
+            client_subject: None,
+            backend: None,
+            bytes_in: 0,
+            bytes_out: 0,
+            duration: 0.0,
+            handshake_time: None,
+            termination: Termination::Shutdown,
+            error: None,
+        }
+    }
+}
+
+impl Entry {
+    pub fn new(
+        log: Option<AccessLog>,
+        context: &tls_server::ConnectionContext,
+        session: &tls_server::Session,
+    ) -> Entry {
+        let record = Record {
+            sni: session.server_name.clone(),
+            alpn: session
+                .alpn_protocol
+                .as_ref()
+                .map(|p| String::from_utf8_lossy(p).into_owned()),
+            client_subject: session.peer_certificate().map(|c| c.subject.clone()),
+            handshake_time: session.handshake_duration.map(|d| d.as_secs_f64()),
+            ..Record::new(context)
+        };
+
+        Entry {
+            log,
+            context: context.clone(),
+            record: Some(record),
+        }
+    }
+
+    pub fn backend(&mut self, address: &str) {
+        if let Some(record) = &mut self.record {
+            record.backend = Some(address.to_string());
+        }
+    }
+
+    // The connection was proxied until the client, the first of the streams, or the backend
+    // ended it, or until it timed out.
+    pub fn transferred(self, transfer: Transfer) {
+        let (termination, error) = match transfer.ending {
+            Ending::Closed(Side::First) => (Termination::ClientClose, None),
+            Ending::Closed(Side::Second) => (Termination::BackendClose, None),
+            Ending::Failed(_, e) if e.kind() == std::io::ErrorKind::TimedOut => {
+                (Termination::Timeout, Some(e.to_string()))
+            }
+            Ending::Failed(_, e) => (Termination::Error, Some(e.to_string())),
+            Ending::Idle(_) => (Termination::Timeout, Some("idle timeout".to_string())),
+            Ending::Expired => (Termination::Timeout, Some("maximum lifetime".to_string())),
+        };
+
+        self.finish(
+            termination,
+            error,
+            transfer.first_to_second,
+            transfer.second_to_first,
+        );
+    }
+
+    // The connection could not be proxied.
+    pub fn failed(self, error: &str) {
+        self.finish(Termination::Error, Some(error.to_string()), 0, 0);
+    }
+
+    fn finish(
+        mut self,
+        termination: Termination,
+        error: Option<String>,
+        bytes_in: u64,
+        bytes_out: u64,
+    ) {
+        if let Some(record) = &mut self.record {
+            record.termination = termination;
+            record.error = error;
+            record.bytes_in = bytes_in;
+            record.bytes_out = bytes_out;
+        }
+        self.write();
+    }
+
+    fn write(&mut self) {
+        if let (Some(log), Some(mut record)) = (&self.log, self.record.take()) {
+            record.duration = self.context.elapsed().as_secs_f64();
+            log.write(&record);
+        }
+    }
+}
+
+impl Drop for Entry {
+    fn drop(&mut self) {
+        let now = std::time::Instant::now();
+        if let Some(record) = &mut self.record {
+            if self
+                .context
+                .deadline()
+                .is_some_and(|deadline| deadline <= now)
+            {
+                record.termination = Termination::Timeout;
+                record.error = Some("maximum lifetime".to_string());
+            }
+        }
+        self.write();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transfer(ending: Ending) -> Transfer {
+        Transfer {
+            first_to_second: 3,
+            second_to_first: 5,
+            ending,
+        }
+    }
+
+    fn failed(side: Side, kind: std::io::ErrorKind) -> Ending {
+        Ending::Failed(side, std::io::Error::from(kind))
+    }
+
+    fn context() -> tls_server::ConnectionContext {
+        let (_trigger, shutdown) = tls_server::Shutdown::new();
+        tls_server::ConnectionContext::new(
+            "127.0.0.1:1234".parse().unwrap(),
+            "127.0.0.1:5000".parse().unwrap(),
+            shutdown,
+        )
+    }
+
+    fn record(write: impl FnOnce(Entry)) -> serde_json::Value {
+        record_with(context(), write)
+    }
+
+    fn record_with(
+        context: tls_server::ConnectionContext,
+        write: impl FnOnce(Entry),
+    ) -> serde_json::Value {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("access.log");
+        let log = AccessLog::open(path.to_str().unwrap()).unwrap();
+
+        let session = tls_server::Session {
+            version: None,
+            cipher_suite: None,
+            server_name: Some("echo.example.com".to_string()),
+            alpn_protocol: Some(b"h2".to_vec()),
+            handshake_duration: Some(std::time::Duration::from_millis(5)),
+            peer_certificates: vec![],
+        };
+        write(Entry::new(Some(log.clone()), &context, &session));
+        log.close();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(1, content.lines().count());
+        serde_json::from_str(&content).unwrap()
+    }
+
+    #[test]
+    fn writes_transferred_connections() {
+        let record = record(|mut entry| {
+            entry.backend("localhost:4000");
+            entry.transferred(transfer(Ending::Closed(Side::First)));
+        });
+
+        assert_eq!("127.0.0.1:1234", record["client"]);
+        assert_eq!("echo.example.com", record["sni"]);
+        assert_eq!("h2", record["alpn"]);
+        assert_eq!(serde_json::Value::Null, record["client_subject"]);
+        assert_eq!("localhost:4000", record["backend"]);
+        assert_eq!(3, record["bytes_in"]);
+        assert_eq!(5, record["bytes_out"]);
+        assert_eq!(0.005, record["handshake_time"]);
+        assert_eq!("client_close", record["termination"]);
+        assert!(record["timestamp"].as_str().unwrap().ends_with('Z'));
+    }
+
+    #[test]
+    fn tells_why_connections_ended() {
+        let termination =
+            |ending| record(|entry| entry.transferred(transfer(ending)))["termination"].clone();
+
+        assert_eq!("backend_close", termination(Ending::Closed(Side::Second)));
+        assert_eq!(
+            "error",
+            termination(failed(Side::First, std::io::ErrorKind::ConnectionReset))
+        );
+        assert_eq!(
+            "timeout",
+            termination(failed(Side::Second, std::io::ErrorKind::TimedOut))
+        );
+    }
+
+    #[test]
+    fn tells_timeouts_apart() {
+        let idle = record(|entry| {
+            entry.transferred(transfer(Ending::Idle(std::time::Duration::from_secs(30))))
+        });
+        assert_eq!("timeout", idle["termination"]);
+        assert_eq!("idle timeout", idle["error"]);
+
+        let expired = record(|entry| entry.transferred(transfer(Ending::Expired)));
+        assert_eq!("timeout", expired["termination"]);
+        assert_eq!("maximum lifetime", expired["error"]);
+    }
+
+    #[test]
+    fn writes_failed_connections() {
+        let record = record(|entry| entry.failed("no route"));
+
+        assert_eq!("error", record["termination"]);
+        assert_eq!("no route", record["error"]);
+        assert_eq!(serde_json::Value::Null, record["backend"]);
+    }
+
+    #[test]
+    fn writes_dropped_connections_as_shutdown() {
+        let record = record(drop);
+
+        assert_eq!("shutdown", record["termination"]);
+    }
+
+    #[test]
+    fn writes_rejected_connections() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("access.log");
+        let log = AccessLog::open(path.to_str().unwrap()).unwrap();
+
+        log.rejected(
+            &context(),
+            &tls_server::Rejection::Refused(tls_server::Refusal::Banned),
+        );
+        log.rejected(&context(), &tls_server::Rejection::TimedOut);
+        log.close();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let records: Vec<serde_json::Value> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(2, records.len());
+        assert_eq!("127.0.0.1:1234", records[0]["client"]);
+        assert_eq!("error", records[0]["termination"]);
+        assert_eq!("banned", records[0]["error"]);
+        assert_eq!(serde_json::Value::Null, records[0]["sni"]);
+        assert_eq!("timeout", records[1]["termination"]);
+        assert_eq!("handshake timeout", records[1]["error"]);
+    }
+
+    #[test]
+    fn writes_connections_dropped_at_their_maximum_lifetime_as_timeout() {
+        let context = context().with_max_lifetime(std::time::Duration::from_secs(0));
+        let record = record_with(context, drop);
+
+        assert_eq!("timeout", record["termination"]);
+        assert_eq!("maximum lifetime", record["error"]);
+    }
+}
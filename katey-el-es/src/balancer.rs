@@ -0,0 +1,324 @@
+use rand::Rng;
+use serde::Deserialize;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+
+use super::config::Backend as BackendConfig;
+
+#[derive(Debug, Copy, Clone, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Strategy {
+    #[default]
+    RoundRobin,
+    Random,
+    LeastConnections,
+    Weighted,
+}
+
+impl std::str::FromStr for Strategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Strategy, String> {
+        match s {
+            "round-robin" => Ok(Strategy::RoundRobin),
+            "random" => Ok(Strategy::Random),
+            "least-connections" => Ok(Strategy::LeastConnections),
+            "weighted" => Ok(Strategy::Weighted),
+            _ => Err(format!("unknown strategy {}", s)),
+        }
+    }
+}
+
+pub struct Backend {
+    address: String,
+    weight: usize,
+    active: AtomicUsize,
+    healthy: AtomicBool,
+
+    // consecutive health check results, used against the rise and fall thresholds
+    passed: AtomicU32,
+    failed: AtomicU32,
+}
+
+impl Backend {
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Relaxed)
+    }
+
+    pub fn lease(&self) -> Lease<'_> {
+        Lease::new(self)
+    }
+
+    // Record the result of a health check, returns whether the backend changed state.
+    pub fn report(&self, ok: bool, rise: u32, fall: u32) -> bool {
+        if ok {
+            self.failed.store(0, Ordering::Relaxed);
+            let passed = self.passed.fetch_add(1, Ordering::Relaxed) + 1;
+            passed >= rise && !self.healthy.swap(true, Ordering::Relaxed)
+        } else {
+            self.passed.store(0, Ordering::Relaxed);
+            let failed = self.failed.fetch_add(1, Ordering::Relaxed) + 1;
+            failed >= fall && self.healthy.swap(false, Ordering::Relaxed)
+        }
+    }
+}
+
+// Marks a connection to a backend as active for as long as it lives.
+pub struct Lease<'a> {
+    backend: &'a Backend,
+}
+
+impl<'a> Lease<'a> {
+    fn new(backend: &'a Backend) -> Lease<'a> {
+        backend.active.fetch_add(1, Ordering::Relaxed);
+        Lease { backend }
+    }
+
+    pub fn address(&self) -> &'a str {
+        self.backend.address()
+    }
+}
+
+impl Drop for Lease<'_> {
+    fn drop(&mut self) {
+        self.backend.active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// A set of backends to forward to, spreading connections according to the strategy. The
+// failover backends are only used when none of the others can be connected to.
+pub struct Pool {
+    backends: Vec<Backend>,
+    failover: Vec<Backend>,
+    strategy: Strategy,
+    next: AtomicUsize,
+}
+
+impl Pool {
+    pub fn new(backends: &[BackendConfig], failover: &[BackendConfig], strategy: Strategy) -> Pool {
+        let make = |backends: &[BackendConfig]| -> Vec<Backend> {
+            backends
+                .iter()
+                .map(|b| Backend {
+                    address: b.address.clone(),
+                    weight: b.weight as usize,
+                    active: AtomicUsize::new(0),
+                    healthy: AtomicBool::new(true),
+                    passed: AtomicU32::new(0),
+                    failed: AtomicU32::new(0),
+                })
+                .collect()
+        };
+
+        Pool {
+            backends: make(backends),
+            failover: make(failover),
+            strategy,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn backends(&self) -> impl Iterator<Item = &Backend> {
+        self.backends.iter().chain(self.failover.iter())
+    }
+
+    // All healthy backends in the order they should be tried: the one picked by the strategy
+    // first, then the remaining ones, then the failover backends.
+    pub fn candidates(&self) -> Vec<&Backend> {
+        let healthy: Vec<&Backend> = self.backends.iter().filter(|b| b.is_healthy()).collect();
+        let failover = self.failover.iter().filter(|b| b.is_healthy());
+
+        if healthy.is_empty() {
+            return failover.collect();
+        }
+
+        let first = self.choose(&healthy);
+        let rest = healthy.iter().copied().filter(|b| !std::ptr::eq(*b, first));
+
+        std::iter::once(first).chain(rest).chain(failover).collect()
+    }
+
+    fn choose<'a>(&self, healthy: &[&'a Backend]) -> &'a Backend {
+        match self.strategy {
+            Strategy::RoundRobin => {
+                let n = self.next.fetch_add(1, Ordering::Relaxed);
+                healthy[n % healthy.len()]
+            }
+            Strategy::Random => {
+                let n = rand::thread_rng().gen_range(0, healthy.len());
+                healthy[n]
+            }
+            Strategy::LeastConnections => {
+                // ties go to the earliest backend in the list
+                healthy.iter().min_by_key(|b| b.active()).unwrap()
+            }
+            Strategy::Weighted => {
+                let total: usize = healthy.iter().map(|b| b.weight).sum();
+                let mut n = self.next.fetch_add(1, Ordering::Relaxed) % total;
+                healthy
+                    .iter()
+                    .find(|b| {
+                        if n < b.weight {
+                            true
+                        } else {
+                            n -= b.weight;
+                            false
+                        }
+                    })
+                    .unwrap()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(backends: &[(&str, u32)], strategy: Strategy) -> Pool {
+        let backends: Vec<BackendConfig> = backends
+            .iter()
+            .map(|(address, weight)| BackendConfig {
+                address: address.to_string(),
+                weight: *weight,
+            })
+            .collect();
+        Pool::new(&backends, &[], strategy)
+    }
+
+    fn lease(pool: &Pool) -> Lease<'_> {
+        pool.candidates()[0].lease()
+    }
+
+    fn pick(pool: &Pool) -> String {
+        lease(pool).address().to_string()
+    }
+
+    #[test]
+    fn round_robin() {
+        let pool = pool(&[("a", 1), ("b", 1), ("c", 1)], Strategy::RoundRobin);
+
+        let picked: Vec<String> = (0..6).map(|_| pick(&pool)).collect();
+        assert_eq!(vec!["a", "b", "c", "a", "b", "c"], picked);
+    }
+
+    #[test]
+    fn random_stays_in_pool() {
+        let pool = pool(&[("a", 1), ("b", 1)], Strategy::Random);
+
+        for _ in 0..20 {
+            let picked = pick(&pool);
+            assert!(picked == "a" || picked == "b");
+        }
+    }
+
+    #[test]
+    fn least_connections() {
+        let pool = pool(&[("a", 1), ("b", 1), ("c", 1)], Strategy::LeastConnections);
+
+        let first = lease(&pool);
+        let second = lease(&pool);
+        assert_eq!("a", first.address());
+        assert_eq!("b", second.address());
+
+        drop(first);
+        assert_eq!("a", pick(&pool));
+
+        let _third = lease(&pool);
+        let fourth = lease(&pool);
+        assert_eq!("c", fourth.address());
+    }
+
+    #[test]
+    fn weighted() {
+        let pool = pool(&[("a", 3), ("b", 1)], Strategy::Weighted);
+
+        let picked: Vec<String> = (0..8).map(|_| pick(&pool)).collect();
+        assert_eq!(vec!["a", "a", "a", "b", "a", "a", "a", "b"], picked);
+    }
+
+    #[test]
+    fn lease_tracks_active() {
+        let pool = pool(&[("a", 1)], Strategy::RoundRobin);
+
+        let lease = lease(&pool);
+        assert_eq!(1, pool.backends[0].active());
+        drop(lease);
+        assert_eq!(0, pool.backends[0].active());
+    }
+
+    #[test]
+    fn skips_unhealthy() {
+        let pool = pool(&[("a", 1), ("b", 1), ("c", 1)], Strategy::RoundRobin);
+
+        assert!(pool.backends[1].report(false, 2, 1));
+        let picked: Vec<String> = (0..4).map(|_| pick(&pool)).collect();
+        assert_eq!(vec!["a", "c", "a", "c"], picked);
+    }
+
+    #[test]
+    fn none_when_all_unhealthy() {
+        let pool = pool(&[("a", 1)], Strategy::LeastConnections);
+
+        pool.backends[0].report(false, 2, 1);
+        assert!(pool.candidates().is_empty());
+    }
+
+    #[test]
+    fn rise_and_fall() {
+        let pool = pool(&[("a", 1)], Strategy::RoundRobin);
+        let backend = &pool.backends[0];
+
+        assert!(!backend.report(false, 2, 3));
+        assert!(!backend.report(false, 2, 3));
+        assert!(backend.is_healthy());
+        assert!(backend.report(false, 2, 3));
+        assert!(!backend.is_healthy());
+
+        assert!(!backend.report(true, 2, 3));
+        assert!(!backend.is_healthy());
+        assert!(backend.report(true, 2, 3));
+        assert!(backend.is_healthy());
+        assert!(!backend.report(true, 2, 3));
+    }
+
+    #[test]
+    fn candidates_in_order() {
+        let backends: Vec<BackendConfig> = ["a", "b", "c"]
+            .iter()
+            .map(|address| BackendConfig {
+                address: address.to_string(),
+                weight: 1,
+            })
+            .collect();
+        let pool = Pool::new(&backends[..2], &backends[2..], Strategy::RoundRobin);
+
+        let addresses = |pool: &Pool| -> Vec<String> {
+            pool.candidates()
+                .iter()
+                .map(|b| b.address().to_string())
+                .collect()
+        };
+
+        assert_eq!(vec!["a", "b", "c"], addresses(&pool));
+        assert_eq!(vec!["b", "a", "c"], addresses(&pool));
+
+        pool.backends[0].report(false, 1, 1);
+        pool.backends[1].report(false, 1, 1);
+        assert_eq!(vec!["c"], addresses(&pool));
+        assert_eq!("c", pick(&pool));
+    }
+
+    #[test]
+    fn parse_strategy() {
+        assert_eq!(Ok(Strategy::LeastConnections), "least-connections".parse());
+        assert!("fastest".parse::<Strategy>().is_err());
+    }
+}
@@ -0,0 +1,1809 @@
+use serde::Deserialize;
+
+use super::balancer::Strategy;
+use super::policy::ClientPolicy;
+use super::Result;
+
+// Top level configuration, as read from a toml file.
+//
+// threads = true
+// drain_timeout = 5.0              # seconds to wait for active connections to finish on shutdown
+// metrics_port = 9100              # optional, serve Prometheus metrics over plain HTTP at
+//                                  # /metrics, announced after the listeners
+// metrics_address = "127.0.0.1"    # the address to serve metrics on, 0.0.0.0 for everywhere
+// access_log = "access.log"        # optional, append a JSON record of every finished connection
+//                                  # to this file, or to stdout for "-"
+// ready_file = "ready"             # optional, write the "LISTEN <address>" lines to this file once
+//                                  # all listeners are up, rather than to stdout
+//
+// [[listener]]
+// port = 5000                      # 0 picks a free port, every listener announces its address on
+//                                  # stdout, or the ready file, as "LISTEN <address>", in this order
+// forward = "localhost:4000"       # optional default route, unknown server names are rejected without it
+// strategy = "round-robin"         # optional, how to spread connections over multiple backends
+// failover = "localhost:4100"      # optional, only used when no other backend can be connected to
+// connect_timeout = 5.0            # seconds to wait for a backend connection
+// retries = 0                      # times to retry when no backend could be connected to
+// backoff = 0.1                    # seconds before the first retry, doubling on every next one
+// handshake_timeout = 10.0         # seconds a client gets to send the PROXY header and finish the
+//                                  # TLS handshake
+// idle_timeout = 300.0             # optional, seconds without bytes going either way before a
+//                                  # connection is closed
+// max_lifetime = 3600.0            # optional, seconds after which a connection is closed no matter
+//                                  # what
+// max_connections = 1000           # optional, connections handled at once
+// max_connections_per_source = 10  # optional, connections handled at once from one address, more
+//                                  # are closed right away
+// over_limit = "pause"             # "pause" accepting at max_connections, or "close" new ones
+// accept_proxy = "optional"        # optional, expect a PROXY protocol header before the handshake,
+//                                  # "optional" or "required"
+// trusted_proxies = ["10.0.0.2"]   # with accept_proxy, the addresses a PROXY header is taken from,
+//                                  # others are closed when required or seen as direct when optional
+// send_proxy = "v2"                # optional, prepend a PROXY protocol header (v1 or v2) to backend
+//                                  # connections, v2 also carries the TLS details
+// cert = "server-cert.pem"         # optional default certificate
+// key = "server-key.pem"
+// ocsp = "server.ocsp"             # optional, with cert, an OCSP response in DER format to staple,
+//                                  # read again every minute, cert must hold the issuer too
+// authenticate = "root-cert.pem"   # optional
+// crl = ["revoked.crl.pem"]        # optional, with authenticate, revocation lists in PEM or DER
+//                                  # format, reloaded along with the certificates
+// alpn = ["h2", "line"]            # optional, protocols to advertise through ALPN
+// reload_interval = 60.0           # optional, seconds between checks for changed certificate, key
+//                                  # and root files, they are always reloaded on SIGHUP
+//
+// [[listener.route]]               # optional, routes by the server name (SNI) and/or the
+// sni = "echo.example.com"         # negotiated protocol (ALPN), at least one must be given
+// alpn = "h2"
+// forward = "localhost:4001"
+// failover = "localhost:4101"      # optional
+// cert = "echo-cert.pem"           # optional, only with sni, the default certificate is used without it
+// key = "echo-key.pem"
+//
+// A route matching both sni and alpn goes before one matching sni only, which goes before
+// one matching alpn only.
+//
+// Every forward can also be a list of backends, plain addresses or with a weight:
+//
+// forward = ["localhost:4000", { address = "localhost:4001", weight = 3 }]
+//
+// The strategy is one of round-robin, random, least-connections or weighted.
+//
+// Backends can be checked periodically, connections only go to healthy backends:
+//
+// [listener.health]
+// interval = 5.0                   # seconds between checks
+// timeout = 1.0                    # seconds to wait for the connection and the response
+// rise = 2                         # consecutive passes before a backend is healthy again
+// fall = 3                         # consecutive failures before a backend is unhealthy
+// send = "PING\n"                 # optional payload to send after connecting
+// expect = "PONG"                  # optional, the response must contain this
+//
+// New connections can be limited by rate per source address, and sources that keep failing the
+// handshake, which includes failing authentication, can be banned for a while:
+//
+// [listener.rate_limit]
+// rate = 10.0                      # new connections per second from a source, on average
+// burst = 20                       # optional, connections at once before the rate applies, rate
+//                                  # by default
+// ipv4_prefix = 32                 # sources within the same prefix count as one
+// ipv6_prefix = 64
+//
+// [listener.ban]
+// failures = 5                     # failed handshakes within the window that get a source banned
+// window = 60.0                    # seconds
+// duration = 600.0                 # seconds a source stays banned
+//
+// Both apply to the address connected from, or with accept_proxy to the one the PROXY header tells.
+//
+// With authenticate, clients can be let in or kept out by what their certificate says:
+//
+// [listener.clients]
+// allow = ["ou:payments", "uri:spiffe://example.com/ns/payments/*"]
+// deny = ["cn:mallory", "sha256:AB:CD:..."]
+//
+// Rules are one of cn, ou, dns, uri or email followed by a pattern where * matches anything, or
+// sha256 followed by a certificate fingerprint. A client matching any deny rule is rejected,
+// when there are allow rules a client has to match at least one of them.
+//
+// Connections to the backends can be encrypted too:
+//
+// [listener.backend_tls]
+// root = "backend-root-cert.pem"   # root certificates to verify the backends against
+// cert = "client-cert.pem"         # optional, to authenticate to the backends
+// key = "client-key.pem"
+// server_name = "backend.internal" # optional, the host part of the backend address by default
+//
+// Paths are taken as-is, so relative paths are relative to the working directory.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub threads: bool,
+
+    #[serde(default = "default_drain_timeout")]
+    pub drain_timeout: f64,
+
+    pub metrics_port: Option<u16>,
+
+    #[serde(default = "default_metrics_address")]
+    pub metrics_address: std::net::IpAddr,
+
+    pub access_log: Option<String>,
+
+    pub ready_file: Option<String>,
+
+    #[serde(rename = "listener", default)]
+    pub listeners: Vec<Listener>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Listener {
+    pub port: u16,
+    pub forward: Option<Forward>,
+
+    #[serde(default)]
+    pub strategy: Strategy,
+
+    pub failover: Option<Forward>,
+
+    #[serde(default = "default_connect_timeout")]
+    pub connect_timeout: f64,
+
+    #[serde(default)]
+    pub retries: u32,
+
+    #[serde(default = "default_backoff")]
+    pub backoff: f64,
+
+    #[serde(default = "default_handshake_timeout")]
+    pub handshake_timeout: f64,
+
+    pub idle_timeout: Option<f64>,
+    pub max_lifetime: Option<f64>,
+
+    pub max_connections: Option<usize>,
+    pub max_connections_per_source: Option<usize>,
+
+    #[serde(default)]
+    pub over_limit: OverLimit,
+
+    pub accept_proxy: Option<AcceptProxy>,
+    #[serde(default)]
+    pub trusted_proxies: Vec<std::net::IpAddr>,
+    pub send_proxy: Option<proxy_protocol::Version>,
+
+    pub cert: Option<String>,
+    pub key: Option<String>,
+    pub ocsp: Option<String>,
+    pub authenticate: Option<String>,
+    pub clients: Option<ClientPolicy>,
+
+    #[serde(default)]
+    pub crl: Vec<String>,
+
+    #[serde(default)]
+    pub alpn: Vec<String>,
+
+    pub reload_interval: Option<f64>,
+
+    pub health: Option<HealthCheck>,
+
+    pub rate_limit: Option<RateLimit>,
+    pub ban: Option<Ban>,
+
+    pub backend_tls: Option<BackendTls>,
+
+    #[serde(rename = "route", default)]
+    pub routes: Vec<Route>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Route {
+    pub sni: Option<String>,
+    pub alpn: Option<String>,
+    pub forward: Forward,
+    pub failover: Option<Forward>,
+    pub cert: Option<String>,
+    pub key: Option<String>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AcceptProxy {
+    Optional,
+    Required,
+}
+
+// What to do with new connections at max_connections.
+#[derive(Debug, Copy, Clone, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OverLimit {
+    #[default]
+    Pause,
+    Close,
+}
+
+impl std::str::FromStr for OverLimit {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<OverLimit, String> {
+        match s {
+            "pause" => Ok(OverLimit::Pause),
+            "close" => Ok(OverLimit::Close),
+            _ => Err(format!("unknown over limit policy {}", s)),
+        }
+    }
+}
+
+impl std::str::FromStr for AcceptProxy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<AcceptProxy, String> {
+        match s {
+            "optional" => Ok(AcceptProxy::Optional),
+            "required" => Ok(AcceptProxy::Required),
+            _ => Err(format!("unknown proxy protocol mode {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(from = "ForwardConfig")]
+pub struct Forward(pub Vec<Backend>);
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Backend {
+    pub address: String,
+
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ForwardConfig {
+    Single(String),
+    Multiple(Vec<BackendConfig>),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum BackendConfig {
+    Address(String),
+    Backend(Backend),
+}
+
+fn default_drain_timeout() -> f64 {
+    5.0
+}
+
+fn default_metrics_address() -> std::net::IpAddr {
+    std::net::Ipv4Addr::LOCALHOST.into()
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+fn default_connect_timeout() -> f64 {
+    5.0
+}
+
+fn default_backoff() -> f64 {
+    0.1
+}
+
+fn default_handshake_timeout() -> f64 {
+    10.0
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HealthCheck {
+    #[serde(default = "default_interval")]
+    pub interval: f64,
+
+    #[serde(default = "default_timeout")]
+    pub timeout: f64,
+
+    #[serde(default = "default_rise")]
+    pub rise: u32,
+
+    #[serde(default = "default_fall")]
+    pub fall: u32,
+
+    pub send: Option<String>,
+    pub expect: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BackendTls {
+    pub root: String,
+    pub cert: Option<String>,
+    pub key: Option<String>,
+    pub server_name: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    pub rate: f64,
+    pub burst: Option<u32>,
+
+    #[serde(default = "default_ipv4_prefix")]
+    pub ipv4_prefix: u8,
+
+    #[serde(default = "default_ipv6_prefix")]
+    pub ipv6_prefix: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Ban {
+    pub failures: u32,
+
+    #[serde(default = "default_ban_window")]
+    pub window: f64,
+
+    #[serde(default = "default_ban_duration")]
+    pub duration: f64,
+}
+
+fn default_ipv4_prefix() -> u8 {
+    32
+}
+
+fn default_ipv6_prefix() -> u8 {
+    64
+}
+
+fn default_ban_window() -> f64 {
+    60.0
+}
+
+fn default_ban_duration() -> f64 {
+    600.0
+}
+
+fn default_interval() -> f64 {
+    5.0
+}
+
+fn default_timeout() -> f64 {
+    1.0
+}
+
+fn default_rise() -> u32 {
+    2
+}
+
+fn default_fall() -> u32 {
+    3
+}
+
+impl HealthCheck {
+    pub fn new(interval: f64) -> HealthCheck {
+        HealthCheck {
+            interval,
+            timeout: default_timeout().min(interval),
+            rise: default_rise(),
+            fall: default_fall(),
+            send: None,
+            expect: None,
+        }
+    }
+
+    fn validate(&self) -> std::result::Result<(), String> {
+        let positive = |v: f64| v.is_finite() && v > 0.0;
+        if !positive(self.interval) || !positive(self.timeout) {
+            return Err("health check interval and timeout must be positive".to_string());
+        }
+        if self.rise == 0 || self.fall == 0 {
+            return Err("health check rise and fall must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl RateLimit {
+    pub fn new(rate: f64) -> RateLimit {
+        RateLimit {
+            rate,
+            burst: None,
+            ipv4_prefix: default_ipv4_prefix(),
+            ipv6_prefix: default_ipv6_prefix(),
+        }
+    }
+
+    // Enough for a second's worth of connections, and at least one.
+    pub fn burst(&self) -> u32 {
+        self.burst
+            .unwrap_or_else(|| self.rate.ceil().max(1.0) as u32)
+    }
+
+    fn validate(&self) -> std::result::Result<(), String> {
+        if !(self.rate.is_finite() && self.rate > 0.0) {
+            return Err("rate limit rate must be positive".to_string());
+        }
+        if self.burst == Some(0) {
+            return Err("rate limit burst must be at least 1".to_string());
+        }
+        if self.ipv4_prefix > 32 || self.ipv6_prefix > 128 {
+            return Err("rate limit prefix is longer than the address".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl Ban {
+    pub fn new(failures: u32) -> Ban {
+        Ban {
+            failures,
+            window: default_ban_window(),
+            duration: default_ban_duration(),
+        }
+    }
+
+    fn validate(&self) -> std::result::Result<(), String> {
+        let positive = |v: f64| v.is_finite() && v > 0.0;
+        if self.failures == 0 {
+            return Err("ban failures must be at least 1".to_string());
+        }
+        if !positive(self.window) || !positive(self.duration) {
+            return Err("ban window and duration must be positive".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl From<ForwardConfig> for Forward {
+    fn from(config: ForwardConfig) -> Forward {
+        match config {
+            ForwardConfig::Single(address) => Forward::from_addresses(&[address]),
+            ForwardConfig::Multiple(backends) => Forward(
+                backends
+                    .into_iter()
+                    .map(|b| match b {
+                        BackendConfig::Address(address) => Backend {
+                            address,
+                            weight: default_weight(),
+                        },
+                        BackendConfig::Backend(b) => b,
+                    })
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl Forward {
+    pub fn from_addresses<S: AsRef<str>>(addresses: &[S]) -> Forward {
+        Forward(
+            addresses
+                .iter()
+                .map(|a| Backend {
+                    address: a.as_ref().to_string(),
+                    weight: default_weight(),
+                })
+                .collect(),
+        )
+    }
+
+    fn validate(&self) -> std::result::Result<(), String> {
+        if self.0.is_empty() {
+            return Err("no backends to forward to".to_string());
+        }
+        if let Some(b) = self.0.iter().find(|b| b.weight == 0) {
+            return Err(format!("backend {} has weight 0", b.address));
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Forward {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let addresses: Vec<&str> = self.0.iter().map(|b| b.address.as_str()).collect();
+        write!(f, "{}", addresses.join(", "))
+    }
+}
+
+impl Config {
+    pub fn from_file(filename: &str) -> Result<Config> {
+        let content = std::fs::read_to_string(filename)?;
+        Config::parse(&content)
+    }
+
+    pub fn parse(content: &str) -> Result<Config> {
+        let config: Config = toml::from_str(content)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    // The command line shorthand, maps onto a single listener.
+    pub fn from_args(args: &clap::ArgMatches) -> Result<Config> {
+        let forward: Vec<&str> = args.values_of("forward").unwrap().collect();
+        let listener = Listener {
+            port: args.value_of("listen").unwrap().parse()?,
+            forward: Some(Forward::from_addresses(&forward)),
+            strategy: args.value_of("strategy").unwrap_or("round-robin").parse()?,
+            failover: args
+                .values_of("failover")
+                .map(|f| Forward::from_addresses(&f.collect::<Vec<&str>>())),
+            connect_timeout: match args.value_of("connect_timeout") {
+                Some(t) => t.parse()?,
+                None => default_connect_timeout(),
+            },
+            retries: args.value_of("retries").unwrap_or("0").parse()?,
+            backoff: match args.value_of("backoff") {
+                Some(b) => b.parse()?,
+                None => default_backoff(),
+            },
+            handshake_timeout: match args.value_of("handshake_timeout") {
+                Some(t) => t.parse()?,
+                None => default_handshake_timeout(),
+            },
+            idle_timeout: match args.value_of("idle_timeout") {
+                Some(t) => Some(t.parse()?),
+                None => None,
+            },
+            max_lifetime: match args.value_of("max_lifetime") {
+                Some(t) => Some(t.parse()?),
+                None => None,
+            },
+            max_connections: match args.value_of("max_connections") {
+                Some(n) => Some(n.parse()?),
+                None => None,
+            },
+            max_connections_per_source: match args.value_of("max_connections_per_source") {
+                Some(n) => Some(n.parse()?),
+                None => None,
+            },
+            over_limit: match args.value_of("over_limit") {
+                Some(p) => p.parse()?,
+                None => OverLimit::default(),
+            },
+            accept_proxy: match args.value_of("accept_proxy") {
+                Some(m) => Some(m.parse()?),
+                None => None,
+            },
+            trusted_proxies: args
+                .values_of("trusted_proxy")
+                .into_iter()
+                .flatten()
+                .map(|a| a.parse())
+                .collect::<std::result::Result<_, _>>()?,
+            send_proxy: match args.value_of("send_proxy") {
+                Some(v) => Some(v.parse()?),
+                None => None,
+            },
+            cert: args.value_of("cert").map(|s| s.to_string()),
+            key: args.value_of("key").map(|s| s.to_string()),
+            ocsp: args.value_of("ocsp").map(|s| s.to_string()),
+            authenticate: args.value_of("client_auth").map(|s| s.to_string()),
+            crl: args
+                .values_of("crl")
+                .into_iter()
+                .flatten()
+                .map(|s| s.to_string())
+                .collect(),
+            clients: {
+                let rules = |name| -> std::result::Result<Vec<_>, String> {
+                    args.values_of(name)
+                        .into_iter()
+                        .flatten()
+                        .map(|r| r.parse())
+                        .collect()
+                };
+                let policy = ClientPolicy {
+                    allow: rules("allow_client")?,
+                    deny: rules("deny_client")?,
+                };
+                if policy.is_empty() {
+                    None
+                } else {
+                    Some(policy)
+                }
+            },
+            alpn: vec![],
+            reload_interval: match args.value_of("reload_interval") {
+                Some(i) => Some(i.parse()?),
+                None => None,
+            },
+            health: match args.value_of("health_interval") {
+                Some(interval) => {
+                    let mut check = HealthCheck::new(interval.parse()?);
+                    check.send = args.value_of("health_send").map(|s| s.to_string());
+                    check.expect = args.value_of("health_expect").map(|s| s.to_string());
+                    Some(check)
+                }
+                None => None,
+            },
+            rate_limit: match args.value_of("rate_limit") {
+                Some(rate) => {
+                    let mut limit = RateLimit::new(rate.parse()?);
+                    if let Some(burst) = args.value_of("rate_burst") {
+                        limit.burst = Some(burst.parse()?);
+                    }
+                    Some(limit)
+                }
+                None => None,
+            },
+            ban: match args.value_of("ban_after") {
+                Some(failures) => {
+                    let mut ban = Ban::new(failures.parse()?);
+                    if let Some(window) = args.value_of("ban_window") {
+                        ban.window = window.parse()?;
+                    }
+                    if let Some(duration) = args.value_of("ban_duration") {
+                        ban.duration = duration.parse()?;
+                    }
+                    Some(ban)
+                }
+                None => None,
+            },
+            backend_tls: args.value_of("backend_root").map(|root| BackendTls {
+                root: root.to_string(),
+                cert: args.value_of("backend_cert").map(|s| s.to_string()),
+                key: args.value_of("backend_key").map(|s| s.to_string()),
+                server_name: args.value_of("backend_server_name").map(|s| s.to_string()),
+            }),
+            routes: vec![],
+        };
+
+        let config = Config {
+            threads: args.is_present("threads"),
+            drain_timeout: match args.value_of("drain_timeout") {
+                Some(t) => t.parse()?,
+                None => default_drain_timeout(),
+            },
+            metrics_port: match args.value_of("metrics_port") {
+                Some(p) => Some(p.parse()?),
+                None => None,
+            },
+            metrics_address: match args.value_of("metrics_address") {
+                Some(a) => a.parse()?,
+                None => default_metrics_address(),
+            },
+            access_log: args.value_of("access_log").map(|s| s.to_string()),
+            ready_file: args.value_of("ready_file").map(|s| s.to_string()),
+            listeners: vec![listener],
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.listeners.is_empty() {
+            return Err(string_error::static_err(
+                "configuration needs at least one listener",
+            ));
+        }
+
+        if !(self.drain_timeout.is_finite() && self.drain_timeout >= 0.0) {
+            return Err(string_error::static_err(
+                "drain_timeout can not be negative",
+            ));
+        }
+
+        let mut ports: Vec<u16> = self
+            .listeners
+            .iter()
+            .map(|l| l.port)
+            .chain(self.metrics_port)
+            .filter(|p| *p != 0)
+            .collect();
+        ports.sort_unstable();
+        if ports.windows(2).any(|w| w[0] == w[1]) {
+            return Err(string_error::static_err(
+                "configuration has multiple listeners on the same port",
+            ));
+        }
+
+        self.listeners.iter().try_for_each(|l| l.validate())
+    }
+}
+
+impl Listener {
+    fn validate(&self) -> Result<()> {
+        let fail = |msg: &str| -> Result<()> {
+            Err(string_error::into_err(format!(
+                "listener on port {}: {}",
+                self.port, msg
+            )))
+        };
+
+        if self.cert.is_some() != self.key.is_some() {
+            return fail("cert and key must be given together");
+        }
+        if self.ocsp.is_some() && self.cert.is_none() {
+            return fail("ocsp needs cert");
+        }
+        if self.accept_proxy.is_some() && self.trusted_proxies.is_empty() {
+            return fail("accept_proxy needs trusted_proxies");
+        }
+        if self.accept_proxy.is_none() && !self.trusted_proxies.is_empty() {
+            return fail("trusted_proxies needs accept_proxy");
+        }
+
+        if self.forward.is_none() && self.routes.is_empty() {
+            return fail("needs a forward address or at least one route");
+        }
+
+        if let Some(forward) = &self.forward {
+            if let Err(e) = forward.validate() {
+                return fail(&e);
+            }
+        }
+
+        if let Some(failover) = &self.failover {
+            if let Err(e) = failover.validate() {
+                return fail(&format!("failover: {}", e));
+            }
+        }
+
+        if !(self.connect_timeout.is_finite() && self.connect_timeout > 0.0) {
+            return fail("connect_timeout must be positive");
+        }
+
+        if !(self.backoff.is_finite() && self.backoff >= 0.0) {
+            return fail("backoff can not be negative");
+        }
+
+        if !(self.handshake_timeout.is_finite() && self.handshake_timeout > 0.0) {
+            return fail("handshake_timeout must be positive");
+        }
+
+        if let Some(timeout) = self.idle_timeout {
+            if !(timeout.is_finite() && timeout > 0.0) {
+                return fail("idle_timeout must be positive");
+            }
+        }
+
+        if let Some(lifetime) = self.max_lifetime {
+            if !(lifetime.is_finite() && lifetime > 0.0) {
+                return fail("max_lifetime must be positive");
+            }
+        }
+
+        if self.max_connections == Some(0) || self.max_connections_per_source == Some(0) {
+            return fail("connection limits must be at least 1");
+        }
+
+        if let Some(health) = &self.health {
+            if let Err(e) = health.validate() {
+                return fail(&e);
+            }
+        }
+
+        if let Some(Err(e)) = self.rate_limit.as_ref().map(|r| r.validate()) {
+            return fail(&e);
+        }
+
+        if let Some(Err(e)) = self.ban.as_ref().map(|b| b.validate()) {
+            return fail(&e);
+        }
+
+        if let Some(interval) = self.reload_interval {
+            if !(interval.is_finite() && interval > 0.0) {
+                return fail("reload_interval must be positive");
+            }
+        }
+
+        if self.clients.is_some() && self.authenticate.is_none() {
+            return fail("clients needs authenticate");
+        }
+
+        if !self.crl.is_empty() && self.authenticate.is_none() {
+            return fail("crl needs authenticate");
+        }
+
+        if let Some(tls) = &self.backend_tls {
+            if tls.cert.is_some() != tls.key.is_some() {
+                return fail("backend_tls: cert and key must be given together");
+            }
+        }
+
+        for route in self.routes.iter() {
+            let name = route.name();
+
+            if route.sni.is_none() && route.alpn.is_none() {
+                return fail(&format!("route {}: needs sni or alpn", name));
+            }
+
+            if let Err(e) = route.forward.validate() {
+                return fail(&format!("route {}: {}", name, e));
+            }
+
+            if let Some(Err(e)) = route.failover.as_ref().map(|f| f.validate()) {
+                return fail(&format!("route {}: failover: {}", name, e));
+            }
+
+            if route.cert.is_some() != route.key.is_some() {
+                return fail(&format!(
+                    "route {}: cert and key must be given together",
+                    name
+                ));
+            }
+
+            if route.cert.is_some() && route.sni.is_none() {
+                return fail(&format!("route {}: a certificate needs sni", name));
+            }
+
+            if route.cert.is_none() && self.cert.is_none() {
+                return fail(&format!("route {}: no certificate", name));
+            }
+
+            if let Some(alpn) = &route.alpn {
+                if !self.alpn.contains(alpn) {
+                    return fail(&format!(
+                        "route {}: protocol {} is not advertised",
+                        name, alpn
+                    ));
+                }
+            }
+        }
+
+        let mut keys: Vec<(Option<String>, Option<&String>)> = self
+            .routes
+            .iter()
+            .map(|r| (r.sni.as_ref().map(|s| s.to_lowercase()), r.alpn.as_ref()))
+            .collect();
+        keys.sort_unstable();
+        if keys.windows(2).any(|w| w[0] == w[1]) {
+            return fail("multiple routes for the same server name and protocol");
+        }
+
+        let mut certified: Vec<String> = self
+            .routes
+            .iter()
+            .filter(|r| r.cert.is_some())
+            .filter_map(|r| r.sni.as_ref().map(|s| s.to_lowercase()))
+            .collect();
+        certified.sort_unstable();
+        if certified.windows(2).any(|w| w[0] == w[1]) {
+            return fail("multiple certificates for the same server name");
+        }
+
+        if self.cert.is_none() && self.routes.is_empty() {
+            return fail("no certificate");
+        }
+
+        Ok(())
+    }
+}
+
+impl Route {
+    pub fn name(&self) -> String {
+        format!(
+            "{}/{}",
+            self.sni.as_deref().unwrap_or("*"),
+            self.alpn.as_deref().unwrap_or("*")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_listeners() {
+        let config = Config::parse(
+            r#"
+            threads = true
+
+            [[listener]]
+            port = 5000
+            forward = "localhost:4000"
+            cert = "server-cert.pem"
+            key = "server-key.pem"
+
+            [[listener]]
+            port = 5001
+            forward = "localhost:4001"
+            cert = "other-cert.pem"
+            key = "other-key.pem"
+            authenticate = "root-cert.pem"
+            "#,
+        )
+        .expect("valid config");
+
+        assert!(config.threads);
+        assert_eq!(5.0, config.drain_timeout);
+        assert_eq!(2, config.listeners.len());
+
+        assert_eq!(5000, config.listeners[0].port);
+        assert_eq!(
+            Some(Forward::from_addresses(&["localhost:4000"])),
+            config.listeners[0].forward
+        );
+        assert_eq!(None, config.listeners[0].authenticate);
+
+        assert_eq!(5001, config.listeners[1].port);
+        assert_eq!(Some("other-key.pem".to_string()), config.listeners[1].key);
+        assert_eq!(
+            Some("root-cert.pem".to_string()),
+            config.listeners[1].authenticate
+        );
+    }
+
+    #[test]
+    fn allows_multiple_free_ports() {
+        Config::parse(
+            r#"
+            [[listener]]
+            port = 0
+            forward = "localhost:4000"
+            cert = "server-cert.pem"
+            key = "server-key.pem"
+
+            [[listener]]
+            port = 0
+            forward = "localhost:4001"
+            cert = "server-cert.pem"
+            key = "server-key.pem"
+            "#,
+        )
+        .expect("valid config");
+    }
+
+    #[test]
+    fn parse_metrics_port() {
+        let config = Config::parse(
+            r#"
+            metrics_port = 9100
+
+            [[listener]]
+            port = 5000
+            forward = "localhost:4000"
+            cert = "server-cert.pem"
+            key = "server-key.pem"
+            "#,
+        )
+        .expect("valid config");
+        assert_eq!(Some(9100), config.metrics_port);
+        assert_eq!("127.0.0.1", config.metrics_address.to_string());
+        assert_eq!(None, config.access_log);
+
+        let config = Config::parse(
+            r#"
+            metrics_port = 9100
+            metrics_address = "0.0.0.0"
+
+            [[listener]]
+            port = 5000
+            forward = "localhost:4000"
+            cert = "server-cert.pem"
+            key = "server-key.pem"
+            "#,
+        )
+        .expect("valid config");
+        assert_eq!("0.0.0.0", config.metrics_address.to_string());
+
+        Config::parse(
+            r#"
+            metrics_port = 5000
+
+            [[listener]]
+            port = 5000
+            forward = "localhost:4000"
+            cert = "server-cert.pem"
+            key = "server-key.pem"
+            "#,
+        )
+        .expect_err("metrics on a listener port");
+    }
+
+    #[test]
+    fn parse_access_log() {
+        let config = Config::parse(
+            r#"
+            access_log = "access.log"
+
+            [[listener]]
+            port = 5000
+            forward = "localhost:4000"
+            cert = "server-cert.pem"
+            key = "server-key.pem"
+            "#,
+        )
+        .expect("valid config");
+        assert_eq!(Some("access.log".to_string()), config.access_log);
+
+        let config = Config::parse(
+            r#"
+            access_log = "-"
+
+            [[listener]]
+            port = 5000
+            forward = "localhost:4000"
+            cert = "server-cert.pem"
+            key = "server-key.pem"
+            "#,
+        )
+        .expect("access log on stdout");
+        assert_eq!(Some("-".to_string()), config.access_log);
+    }
+
+    #[test]
+    fn parse_ready_file() {
+        let config = Config::parse(
+            r#"
+            ready_file = "ready"
+
+            [[listener]]
+            port = 0
+            forward = "localhost:4000"
+            cert = "server-cert.pem"
+            key = "server-key.pem"
+            "#,
+        )
+        .expect("valid config");
+        assert_eq!(Some("ready".to_string()), config.ready_file);
+    }
+
+    #[test]
+    fn needs_listener() {
+        Config::parse("threads = true").expect_err("no listeners");
+    }
+
+    #[test]
+    fn rejects_duplicate_ports() {
+        Config::parse(
+            r#"
+            [[listener]]
+            port = 5000
+            forward = "localhost:4000"
+            cert = "server-cert.pem"
+            key = "server-key.pem"
+
+            [[listener]]
+            port = 5000
+            forward = "localhost:4001"
+            cert = "server-cert.pem"
+            key = "server-key.pem"
+            "#,
+        )
+        .expect_err("duplicate ports");
+    }
+
+    #[test]
+    fn parse_routes() {
+        let config = Config::parse(
+            r#"
+            [[listener]]
+            port = 5000
+            cert = "server-cert.pem"
+            key = "server-key.pem"
+
+            [[listener.route]]
+            sni = "echo"
+            forward = "localhost:4000"
+
+            [[listener.route]]
+            sni = "fib"
+            forward = "localhost:4001"
+            cert = "fib-cert.pem"
+            key = "fib-key.pem"
+            "#,
+        )
+        .expect("valid config");
+
+        let listener = &config.listeners[0];
+        assert_eq!(None, listener.forward);
+        assert_eq!(2, listener.routes.len());
+        assert_eq!(Some("echo".to_string()), listener.routes[0].sni);
+        assert_eq!(None, listener.routes[0].cert);
+        assert_eq!(
+            Forward::from_addresses(&["localhost:4001"]),
+            listener.routes[1].forward
+        );
+        assert_eq!(Some("fib-cert.pem".to_string()), listener.routes[1].cert);
+    }
+
+    #[test]
+    fn parse_alpn_routes() {
+        let config = Config::parse(
+            r#"
+            [[listener]]
+            port = 5000
+            cert = "server-cert.pem"
+            key = "server-key.pem"
+            alpn = ["h2", "line"]
+
+            [[listener.route]]
+            alpn = "h2"
+            forward = "localhost:4000"
+
+            [[listener.route]]
+            sni = "fib"
+            alpn = "line"
+            forward = "localhost:4001"
+            "#,
+        )
+        .expect("valid config");
+
+        let listener = &config.listeners[0];
+        assert_eq!(vec!["h2".to_string(), "line".to_string()], listener.alpn);
+        assert_eq!(None, listener.routes[0].sni);
+        assert_eq!(Some("h2".to_string()), listener.routes[0].alpn);
+        assert_eq!(Some("line".to_string()), listener.routes[1].alpn);
+    }
+
+    #[test]
+    fn route_protocol_must_be_advertised() {
+        Config::parse(
+            r#"
+            [[listener]]
+            port = 5000
+            cert = "server-cert.pem"
+            key = "server-key.pem"
+            alpn = ["h2"]
+
+            [[listener.route]]
+            alpn = "line"
+            forward = "localhost:4000"
+            "#,
+        )
+        .expect_err("protocol not advertised");
+    }
+
+    #[test]
+    fn route_needs_sni_or_alpn() {
+        Config::parse(
+            r#"
+            [[listener]]
+            port = 5000
+            cert = "server-cert.pem"
+            key = "server-key.pem"
+
+            [[listener.route]]
+            forward = "localhost:4000"
+            "#,
+        )
+        .expect_err("route matches nothing");
+    }
+
+    #[test]
+    fn parse_backends() {
+        let config = Config::parse(
+            r#"
+            [[listener]]
+            port = 5000
+            cert = "server-cert.pem"
+            key = "server-key.pem"
+            strategy = "weighted"
+            forward = ["localhost:4000", { address = "localhost:4001", weight = 3 }]
+            "#,
+        )
+        .expect("valid config");
+
+        let listener = &config.listeners[0];
+        assert_eq!(Strategy::Weighted, listener.strategy);
+        assert_eq!(
+            Some(Forward(vec![
+                Backend {
+                    address: "localhost:4000".to_string(),
+                    weight: 1
+                },
+                Backend {
+                    address: "localhost:4001".to_string(),
+                    weight: 3
+                },
+            ])),
+            listener.forward
+        );
+    }
+
+    #[test]
+    fn parse_health_check() {
+        let config = Config::parse(
+            r#"
+            [[listener]]
+            port = 5000
+            cert = "server-cert.pem"
+            key = "server-key.pem"
+            forward = ["localhost:4000", "localhost:4001"]
+
+            [listener.health]
+            interval = 2.5
+            fall = 1
+            send = "PING\n"
+            expect = "PONG"
+            "#,
+        )
+        .expect("valid config");
+
+        assert_eq!(
+            Some(HealthCheck {
+                interval: 2.5,
+                timeout: 1.0,
+                rise: 2,
+                fall: 1,
+                send: Some("PING\n".to_string()),
+                expect: Some("PONG".to_string()),
+            }),
+            config.listeners[0].health
+        );
+
+        Config::parse(
+            r#"
+            [[listener]]
+            port = 5000
+            cert = "server-cert.pem"
+            key = "server-key.pem"
+            forward = "localhost:4000"
+
+            [listener.health]
+            rise = 0
+            "#,
+        )
+        .expect_err("rise must be positive");
+    }
+
+    #[test]
+    fn parse_rate_limit_and_ban() {
+        let config = Config::parse(
+            r#"
+            [[listener]]
+            port = 5000
+            cert = "server-cert.pem"
+            key = "server-key.pem"
+            forward = "localhost:4000"
+
+            [listener.rate_limit]
+            rate = 2.5
+            ipv4_prefix = 24
+
+            [listener.ban]
+            failures = 5
+            duration = 300.0
+            "#,
+        )
+        .expect("valid config");
+
+        let limit = config.listeners[0].rate_limit.clone().unwrap();
+        assert_eq!(2.5, limit.rate);
+        assert_eq!(3, limit.burst());
+        assert_eq!(24, limit.ipv4_prefix);
+        assert_eq!(64, limit.ipv6_prefix);
+        assert_eq!(
+            Some(Ban {
+                failures: 5,
+                window: 60.0,
+                duration: 300.0,
+            }),
+            config.listeners[0].ban
+        );
+
+        for (table, error) in &[
+            (
+                "[listener.rate_limit]\nrate = 0.0",
+                "rate limit rate must be positive",
+            ),
+            (
+                "[listener.rate_limit]\nrate = 1.0\nburst = 0",
+                "rate limit burst must be at least 1",
+            ),
+            (
+                "[listener.rate_limit]\nrate = 1.0\nipv4_prefix = 33",
+                "rate limit prefix is longer than the address",
+            ),
+            (
+                "[listener.ban]\nfailures = 0",
+                "ban failures must be at least 1",
+            ),
+            (
+                "[listener.ban]\nfailures = 3\nwindow = -1.0",
+                "ban window and duration must be positive",
+            ),
+        ] {
+            let e = Config::parse(&format!(
+                "[[listener]]\nport = 5000\ncert = \"server-cert.pem\"\nkey = \"server-key.pem\"\nforward = \"localhost:4000\"\n{}",
+                table
+            ))
+            .expect_err("invalid rate limit or ban");
+            assert!(e.to_string().ends_with(error), "{}", e);
+        }
+    }
+
+    #[test]
+    fn parse_retries_and_failover() {
+        let config = Config::parse(
+            r#"
+            [[listener]]
+            port = 5000
+            cert = "server-cert.pem"
+            key = "server-key.pem"
+            forward = ["localhost:4000", "localhost:4001"]
+            failover = "localhost:4100"
+            connect_timeout = 0.5
+            retries = 3
+
+            [[listener.route]]
+            sni = "echo"
+            forward = "localhost:4002"
+            failover = ["localhost:4101", "localhost:4102"]
+            "#,
+        )
+        .expect("valid config");
+
+        let listener = &config.listeners[0];
+        assert_eq!(
+            Some(Forward::from_addresses(&["localhost:4100"])),
+            listener.failover
+        );
+        assert_eq!(0.5, listener.connect_timeout);
+        assert_eq!(3, listener.retries);
+        assert_eq!(0.1, listener.backoff);
+        assert_eq!(None, listener.send_proxy);
+        assert_eq!(None, listener.reload_interval);
+        assert_eq!(
+            Some(Forward::from_addresses(&[
+                "localhost:4101",
+                "localhost:4102"
+            ])),
+            listener.routes[0].failover
+        );
+    }
+
+    #[test]
+    fn parse_send_proxy() {
+        let config = Config::parse(
+            r#"
+            [[listener]]
+            port = 5000
+            cert = "server-cert.pem"
+            key = "server-key.pem"
+            forward = "localhost:4000"
+            send_proxy = "v2"
+            "#,
+        )
+        .expect("valid config");
+        assert_eq!(
+            Some(proxy_protocol::Version::V2),
+            config.listeners[0].send_proxy
+        );
+        assert_eq!(None, config.listeners[0].accept_proxy);
+
+        Config::parse(
+            r#"
+            [[listener]]
+            port = 5000
+            cert = "server-cert.pem"
+            key = "server-key.pem"
+            forward = "localhost:4000"
+            send_proxy = "v3"
+            "#,
+        )
+        .expect_err("unknown version");
+    }
+
+    #[test]
+    fn parse_accept_proxy() {
+        let config = Config::parse(
+            r#"
+            [[listener]]
+            port = 5000
+            cert = "server-cert.pem"
+            key = "server-key.pem"
+            forward = "localhost:4000"
+            accept_proxy = "required"
+            trusted_proxies = ["10.0.0.2", "fd00::2"]
+            "#,
+        )
+        .expect("valid config");
+        assert_eq!(
+            Some(AcceptProxy::Required),
+            config.listeners[0].accept_proxy
+        );
+        assert_eq!(
+            vec![
+                "10.0.0.2".parse::<std::net::IpAddr>().unwrap(),
+                "fd00::2".parse().unwrap()
+            ],
+            config.listeners[0].trusted_proxies
+        );
+
+        Config::parse(
+            r#"
+            [[listener]]
+            port = 5000
+            cert = "server-cert.pem"
+            key = "server-key.pem"
+            forward = "localhost:4000"
+            accept_proxy = "optional"
+            "#,
+        )
+        .expect_err("no trusted proxies");
+
+        Config::parse(
+            r#"
+            [[listener]]
+            port = 5000
+            cert = "server-cert.pem"
+            key = "server-key.pem"
+            forward = "localhost:4000"
+            accept_proxy = "sometimes"
+            trusted_proxies = ["10.0.0.2"]
+            "#,
+        )
+        .expect_err("unknown mode");
+    }
+
+    #[test]
+    fn parse_connection_limits() {
+        let config = Config::parse(
+            r#"
+            [[listener]]
+            port = 5000
+            cert = "server-cert.pem"
+            key = "server-key.pem"
+            forward = "localhost:4000"
+            "#,
+        )
+        .expect("valid config");
+        assert_eq!(None, config.listeners[0].max_connections);
+        assert_eq!(None, config.listeners[0].max_connections_per_source);
+        assert_eq!(OverLimit::Pause, config.listeners[0].over_limit);
+
+        let config = Config::parse(
+            r#"
+            [[listener]]
+            port = 5000
+            cert = "server-cert.pem"
+            key = "server-key.pem"
+            forward = "localhost:4000"
+            max_connections = 1000
+            max_connections_per_source = 10
+            over_limit = "close"
+            "#,
+        )
+        .expect("valid config");
+        assert_eq!(Some(1000), config.listeners[0].max_connections);
+        assert_eq!(Some(10), config.listeners[0].max_connections_per_source);
+        assert_eq!(OverLimit::Close, config.listeners[0].over_limit);
+
+        Config::parse(
+            r#"
+            [[listener]]
+            port = 5000
+            cert = "server-cert.pem"
+            key = "server-key.pem"
+            forward = "localhost:4000"
+            max_connections = 0
+            "#,
+        )
+        .expect_err("limits must be at least 1");
+
+        Config::parse(
+            r#"
+            [[listener]]
+            port = 5000
+            cert = "server-cert.pem"
+            key = "server-key.pem"
+            forward = "localhost:4000"
+            over_limit = "drop"
+            "#,
+        )
+        .expect_err("unknown policy");
+    }
+
+    #[test]
+    fn parse_backend_tls() {
+        let config = Config::parse(
+            r#"
+            [[listener]]
+            port = 5000
+            cert = "server-cert.pem"
+            key = "server-key.pem"
+            forward = "localhost:4000"
+
+            [listener.backend_tls]
+            root = "root-cert.pem"
+            cert = "client-cert.pem"
+            key = "client-key.pem"
+            server_name = "backend"
+            "#,
+        )
+        .expect("valid config");
+        assert_eq!(
+            Some(BackendTls {
+                root: "root-cert.pem".to_string(),
+                cert: Some("client-cert.pem".to_string()),
+                key: Some("client-key.pem".to_string()),
+                server_name: Some("backend".to_string()),
+            }),
+            config.listeners[0].backend_tls
+        );
+
+        Config::parse(
+            r#"
+            [[listener]]
+            port = 5000
+            cert = "server-cert.pem"
+            key = "server-key.pem"
+            forward = "localhost:4000"
+
+            [listener.backend_tls]
+            root = "root-cert.pem"
+            cert = "client-cert.pem"
+            "#,
+        )
+        .expect_err("cert without key");
+    }
+
+    #[test]
+    fn parse_clients() {
+        let config = Config::parse(
+            r#"
+            [[listener]]
+            port = 5000
+            cert = "server-cert.pem"
+            key = "server-key.pem"
+            authenticate = "root-cert.pem"
+            forward = "localhost:4000"
+
+            [listener.clients]
+            allow = ["ou:payments", "uri:spiffe://example.com/*"]
+            deny = ["cn:mallory"]
+            "#,
+        )
+        .expect("valid config");
+        let clients = config.listeners[0].clients.as_ref().expect("clients");
+        let allow: Vec<String> = clients.allow.iter().map(|r| r.to_string()).collect();
+        let deny: Vec<String> = clients.deny.iter().map(|r| r.to_string()).collect();
+        assert_eq!(vec!["ou:payments", "uri:spiffe://example.com/*"], allow);
+        assert_eq!(vec!["cn:mallory"], deny);
+
+        Config::parse(
+            r#"
+            [[listener]]
+            port = 5000
+            cert = "server-cert.pem"
+            key = "server-key.pem"
+            forward = "localhost:4000"
+
+            [listener.clients]
+            allow = ["cn:alice"]
+            "#,
+        )
+        .expect_err("clients without authenticate");
+
+        Config::parse(
+            r#"
+            [[listener]]
+            port = 5000
+            cert = "server-cert.pem"
+            key = "server-key.pem"
+            authenticate = "root-cert.pem"
+            forward = "localhost:4000"
+
+            [listener.clients]
+            allow = ["name:alice"]
+            "#,
+        )
+        .expect_err("unknown field");
+    }
+
+    #[test]
+    fn parse_crl() {
+        let config = Config::parse(
+            r#"
+            [[listener]]
+            port = 5000
+            cert = "server-cert.pem"
+            key = "server-key.pem"
+            authenticate = "root-cert.pem"
+            crl = ["a.crl.pem", "b.crl"]
+            forward = "localhost:4000"
+            "#,
+        )
+        .expect("valid config");
+        assert_eq!(vec!["a.crl.pem", "b.crl"], config.listeners[0].crl);
+
+        Config::parse(
+            r#"
+            [[listener]]
+            port = 5000
+            cert = "server-cert.pem"
+            key = "server-key.pem"
+            crl = ["a.crl.pem"]
+            forward = "localhost:4000"
+            "#,
+        )
+        .expect_err("crl without authenticate");
+    }
+
+    #[test]
+    fn parse_ocsp() {
+        let config = Config::parse(
+            r#"
+            [[listener]]
+            port = 5000
+            cert = "server-cert.pem"
+            key = "server-key.pem"
+            ocsp = "server.ocsp"
+            forward = "localhost:4000"
+            "#,
+        )
+        .expect("valid config");
+        assert_eq!(Some("server.ocsp".to_string()), config.listeners[0].ocsp);
+
+        Config::parse(
+            r#"
+            [[listener]]
+            port = 5000
+            ocsp = "server.ocsp"
+            forward = "localhost:4000"
+            "#,
+        )
+        .expect_err("ocsp without cert");
+    }
+
+    #[test]
+    fn parse_timeouts() {
+        let config = Config::parse(
+            r#"
+            [[listener]]
+            port = 5000
+            cert = "server-cert.pem"
+            key = "server-key.pem"
+            forward = "localhost:4000"
+            "#,
+        )
+        .expect("valid config");
+        assert_eq!(10.0, config.listeners[0].handshake_timeout);
+        assert_eq!(None, config.listeners[0].idle_timeout);
+        assert_eq!(None, config.listeners[0].max_lifetime);
+
+        let config = Config::parse(
+            r#"
+            [[listener]]
+            port = 5000
+            cert = "server-cert.pem"
+            key = "server-key.pem"
+            forward = "localhost:4000"
+            handshake_timeout = 2.5
+            idle_timeout = 300.0
+            max_lifetime = 3600.0
+            "#,
+        )
+        .expect("valid config");
+        assert_eq!(2.5, config.listeners[0].handshake_timeout);
+        assert_eq!(Some(300.0), config.listeners[0].idle_timeout);
+        assert_eq!(Some(3600.0), config.listeners[0].max_lifetime);
+
+        for timeout in &[
+            "handshake_timeout = 0.0",
+            "idle_timeout = -1.0",
+            "max_lifetime = 0.0",
+        ] {
+            Config::parse(&format!(
+                r#"
+                [[listener]]
+                port = 5000
+                cert = "server-cert.pem"
+                key = "server-key.pem"
+                forward = "localhost:4000"
+                {}
+                "#,
+                timeout
+            ))
+            .expect_err("timeouts must be positive");
+        }
+    }
+
+    #[test]
+    fn parse_reload_interval() {
+        let config = Config::parse(
+            r#"
+            [[listener]]
+            port = 5000
+            cert = "server-cert.pem"
+            key = "server-key.pem"
+            forward = "localhost:4000"
+            reload_interval = 60.0
+            "#,
+        )
+        .expect("valid config");
+        assert_eq!(Some(60.0), config.listeners[0].reload_interval);
+
+        Config::parse(
+            r#"
+            [[listener]]
+            port = 5000
+            cert = "server-cert.pem"
+            key = "server-key.pem"
+            forward = "localhost:4000"
+            reload_interval = 0.0
+            "#,
+        )
+        .expect_err("reload interval must be positive");
+    }
+
+    #[test]
+    fn rejects_bad_backends() {
+        Config::parse(
+            r#"
+            [[listener]]
+            port = 5000
+            cert = "server-cert.pem"
+            key = "server-key.pem"
+            forward = []
+            "#,
+        )
+        .expect_err("no backends");
+
+        Config::parse(
+            r#"
+            [[listener]]
+            port = 5000
+            cert = "server-cert.pem"
+            key = "server-key.pem"
+            forward = [{ address = "localhost:4000", weight = 0 }]
+            "#,
+        )
+        .expect_err("zero weight");
+
+        Config::parse(
+            r#"
+            [[listener]]
+            port = 5000
+            cert = "server-cert.pem"
+            key = "server-key.pem"
+            forward = "localhost:4000"
+            strategy = "fastest"
+            "#,
+        )
+        .expect_err("unknown strategy");
+    }
+
+    #[test]
+    fn needs_forward_or_route() {
+        Config::parse(
+            r#"
+            [[listener]]
+            port = 5000
+            cert = "server-cert.pem"
+            key = "server-key.pem"
+            "#,
+        )
+        .expect_err("nowhere to forward to");
+    }
+
+    #[test]
+    fn route_needs_certificate() {
+        Config::parse(
+            r#"
+            [[listener]]
+            port = 5000
+
+            [[listener.route]]
+            sni = "echo"
+            forward = "localhost:4000"
+            "#,
+        )
+        .expect_err("no certificate for route");
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        Config::parse(
+            r#"
+            [[listener]]
+            port = 5000
+            forward = "localhost:4000"
+            cert = "server-cert.pem"
+            key = "server-key.pem"
+            frobnicate = true
+            "#,
+        )
+        .expect_err("unknown field");
+    }
+}
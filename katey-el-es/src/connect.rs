@@ -0,0 +1,186 @@
+use std::time::Duration;
+use tls_server::Metrics;
+use tokio::net::TcpStream;
+use tokio::time::{delay_for, timeout};
+
+use super::balancer::{Lease, Pool};
+use super::config::Listener;
+
+// How hard to try connecting to the backends of a pool.
+#[derive(Debug, Copy, Clone)]
+pub struct Retry {
+    timeout: Duration,
+    retries: u32,
+    backoff: Duration,
+}
+
+impl Retry {
+    pub fn new(listener: &Listener) -> Retry {
+        Retry {
+            timeout: Duration::from_secs_f64(listener.connect_timeout),
+            retries: listener.retries,
+            backoff: Duration::from_secs_f64(listener.backoff),
+        }
+    }
+}
+
+// Counts connections to the backends and attempts that failed, by listener and backend.
+pub struct BackendMetrics<'a> {
+    metrics: &'a Metrics,
+    listener: String,
+}
+
+impl<'a> BackendMetrics<'a> {
+    pub fn new(metrics: &'a Metrics, listener: &str) -> BackendMetrics<'a> {
+        BackendMetrics {
+            metrics,
+            listener: listener.to_string(),
+        }
+    }
+
+    fn connected(&self, backend: &str) {
+        self.metrics
+            .counter(
+                "backend_connections_total",
+                "Connections made to backends.",
+                &[("listener", &self.listener), ("backend", backend)],
+            )
+            .inc();
+    }
+
+    fn failed(&self, backend: &str) {
+        self.metrics
+            .counter(
+                "backend_connect_failures_total",
+                "Attempts to connect to backends that failed or timed out.",
+                &[("listener", &self.listener), ("backend", backend)],
+            )
+            .inc();
+    }
+}
+
+// Connects to a backend of the pool, trying all healthy candidates in turn and starting over
+// after a backoff until out of retries. Gives up right away if there are no healthy backends.
+pub async fn connect<'a>(
+    pool: &'a Pool,
+    retry: &Retry,
+    metrics: &BackendMetrics<'_>,
+) -> Option<(Lease<'a>, TcpStream)> {
+    let mut backoff = retry.backoff;
+
+    for attempt in 0..=retry.retries {
+        if attempt > 0 {
+            log::info!(
+                "retrying in {:?}, attempt {} of {}",
+                backoff,
+                attempt,
+                retry.retries
+            );
+            delay_for(backoff).await;
+            backoff *= 2;
+        }
+
+        let candidates = pool.candidates();
+        if candidates.is_empty() {
+            log::warn!("no healthy backend available");
+            return None;
+        }
+
+        for backend in candidates {
+            let lease = backend.lease();
+            match timeout(retry.timeout, TcpStream::connect(backend.address())).await {
+                Ok(Ok(stream)) => {
+                    metrics.connected(backend.address());
+                    return Some((lease, stream));
+                }
+                Ok(Err(e)) => {
+                    log::warn!("could not connect to backend {}: {}", backend.address(), e)
+                }
+                Err(_) => log::warn!(
+                    "timed out connecting to backend {} after {:?}",
+                    backend.address(),
+                    retry.timeout
+                ),
+            }
+            metrics.failed(backend.address());
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::balancer::Strategy;
+    use crate::config::Backend;
+
+    fn backends(addresses: &[&str]) -> Vec<Backend> {
+        addresses
+            .iter()
+            .map(|address| Backend {
+                address: address.to_string(),
+                weight: 1,
+            })
+            .collect()
+    }
+
+    fn retry(retries: u32) -> Retry {
+        Retry {
+            timeout: Duration::from_secs_f64(0.5),
+            retries,
+            backoff: Duration::from_millis(1),
+        }
+    }
+
+    fn closed_address() -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn fails_over() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let open = listener.local_addr().unwrap().to_string();
+
+        let closed = closed_address();
+        let pool = Pool::new(
+            &backends(&[&closed]),
+            &backends(&[&open]),
+            Strategy::RoundRobin,
+        );
+
+        let metrics = Metrics::default();
+        let (lease, _stream) = connect(&pool, &retry(0), &BackendMetrics::new(&metrics, "5000"))
+            .await
+            .expect("connected");
+        assert_eq!(open, lease.address());
+
+        let rendered = metrics.render();
+        assert!(rendered.contains(&format!(
+            "backend_connect_failures_total{{listener=\"5000\",backend=\"{}\"}} 1",
+            closed
+        )));
+        assert!(rendered.contains(&format!(
+            "backend_connections_total{{listener=\"5000\",backend=\"{}\"}} 1",
+            open
+        )));
+    }
+
+    #[tokio::test]
+    async fn gives_up() {
+        let closed = [closed_address(), closed_address()];
+        let pool = Pool::new(
+            &backends(&[&closed[0], &closed[1]]),
+            &[],
+            Strategy::RoundRobin,
+        );
+
+        let metrics = Metrics::default();
+        assert!(
+            connect(&pool, &retry(2), &BackendMetrics::new(&metrics, "5000"))
+                .await
+                .is_none()
+        );
+    }
+}
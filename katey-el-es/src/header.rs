@@ -0,0 +1,20 @@
+use proxy_protocol::{Header, Tls};
+
+// The PROXY protocol header describing the client side of a connection.
+pub fn make(stream: &tls_server::Stream, session: &tls_server::Session) -> std::io::Result<Header> {
+    let tcp = stream.get_ref().0;
+
+    let mut header = Header::new(tcp.peer_addr()?, tcp.local_addr()?);
+    header.authority = session.server_name.clone();
+    header.alpn = session.alpn_protocol.clone();
+
+    let peer = session.peer_certificate();
+    header.tls = Some(Tls {
+        version: session.version_name(),
+        cipher: session.cipher_suite_name(),
+        client_certificate: peer.is_some(),
+        common_name: peer.and_then(|c| c.common_name.clone()),
+    });
+
+    Ok(header)
+}
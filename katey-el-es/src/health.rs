@@ -0,0 +1,179 @@
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use super::balancer::Backend;
+use super::config::HealthCheck;
+use super::router::Router;
+
+// Checks all backends of the router in the background, for as long as the process lives. Each
+// backend is probed by a task of its own, so dead ones that time out do not delay the others.
+pub fn spawn(router: Arc<Router>, check: HealthCheck) -> std::thread::JoinHandle<()> {
+    log::info!("health checking backends every {}s", check.interval);
+
+    std::thread::spawn(move || {
+        let runtime = tokio::runtime::Builder::new()
+            .basic_scheduler()
+            .enable_all()
+            .build();
+        match runtime {
+            Ok(mut runtime) => runtime.block_on(check_all(router, Arc::new(check))),
+            Err(e) => log::error!("could not start health checking: {}", e),
+        }
+    })
+}
+
+async fn check_all(router: Arc<Router>, check: Arc<HealthCheck>) {
+    let mut ticks = tokio::time::interval(Duration::from_secs_f64(check.interval));
+    loop {
+        ticks.tick().await;
+
+        let probes: Vec<_> = (0..router.backends().count())
+            .map(|i| {
+                let router = router.clone();
+                let check = check.clone();
+                tokio::spawn(async move {
+                    if let Some(backend) = router.backends().nth(i) {
+                        check_backend(backend, &check).await;
+                    }
+                })
+            })
+            .collect();
+
+        // so a backend is never probed twice at once, even when probing takes longer than the
+        // interval
+        for probe in probes {
+            let _ = probe.await;
+        }
+    }
+}
+
+async fn check_backend(backend: &Backend, check: &HealthCheck) {
+    let result = probe(backend.address(), check).await;
+    let ok = result.is_ok();
+
+    if backend.report(ok, check.rise, check.fall) {
+        match result {
+            Ok(()) => log::info!("backend {} is up", backend.address()),
+            Err(e) => log::warn!("backend {} is down: {}", backend.address(), e),
+        }
+    } else if let Err(e) = result {
+        log::debug!("health check of {} failed: {}", backend.address(), e);
+    }
+}
+
+async fn probe(address: &str, check: &HealthCheck) -> std::io::Result<()> {
+    let timeout = Duration::from_secs_f64(check.timeout);
+    match tokio::time::timeout(timeout, exchange(address, check)).await {
+        Ok(result) => result,
+        Err(_) => Err(std::io::Error::new(
+            std::io::ErrorKind::TimedOut,
+            format!("no healthy answer within {:?}", timeout),
+        )),
+    }
+}
+
+// Connects, sends the payload and waits for the expected response, as far as the check has them.
+async fn exchange(address: &str, check: &HealthCheck) -> std::io::Result<()> {
+    let mut stream = TcpStream::connect(address).await?;
+
+    if let Some(send) = &check.send {
+        stream.write_all(send.as_bytes()).await?;
+    }
+
+    if let Some(expect) = &check.expect {
+        let expect = expect.as_bytes();
+        let mut response = Vec::new();
+        let mut buf = [0; 512];
+
+        while !contains(&response, expect) {
+            let n = stream.read(&mut buf).await?;
+            if n == 0 {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "closed before the expected response",
+                ));
+            }
+            response.extend_from_slice(&buf[..n]);
+        }
+    }
+
+    Ok(())
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    needle.is_empty() || haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    fn check(send: Option<&str>, expect: Option<&str>) -> HealthCheck {
+        let mut check = HealthCheck::new(1.0);
+        check.timeout = 0.5;
+        check.send = send.map(|s| s.to_string());
+        check.expect = expect.map(|s| s.to_string());
+        check
+    }
+
+    fn echo_once(listener: TcpListener) {
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0; 512];
+            let n = stream.read(&mut buf).unwrap();
+            stream.write_all(&buf[..n]).unwrap();
+        });
+    }
+
+    #[tokio::test]
+    async fn connect_only() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        probe(&address, &check(None, None)).await.expect("healthy");
+
+        drop(listener);
+        probe(&address, &check(None, None))
+            .await
+            .expect_err("nothing listening");
+    }
+
+    #[tokio::test]
+    async fn send_and_expect() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        echo_once(listener);
+
+        probe(&address, &check(Some("PING\n"), Some("PING")))
+            .await
+            .expect("healthy");
+    }
+
+    #[tokio::test]
+    async fn unexpected_response() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        echo_once(listener);
+
+        probe(&address, &check(Some("PING\n"), Some("PONG")))
+            .await
+            .expect_err("unhealthy");
+    }
+
+    #[tokio::test]
+    async fn times_out_silent_backends() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        let started = std::time::Instant::now();
+        let e = probe(&address, &check(None, Some("PONG")))
+            .await
+            .expect_err("no answer");
+        assert_eq!(std::io::ErrorKind::TimedOut, e.kind());
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
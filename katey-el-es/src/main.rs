@@ -1,13 +1,35 @@
+extern crate certutils;
+extern crate chrono;
 extern crate clap;
 extern crate io_copy;
+extern crate katey_client;
 extern crate log;
+extern crate proxy_protocol;
+extern crate rand;
+extern crate serde;
+extern crate serde_json;
 extern crate simple_logger;
+extern crate string_error;
+extern crate tcp_server;
 extern crate tls_server;
+extern crate toml;
 
-use io_copy::proxy;
-use std::str::FromStr;
-use tokio::io::split;
-use tokio::net::TcpStream;
+mod access;
+mod balancer;
+mod config;
+mod connect;
+mod header;
+mod health;
+mod policy;
+mod router;
+
+use access::{AccessLog, Entry};
+use config::{AcceptProxy, BackendTls, Config, Listener, OverLimit};
+use connect::{BackendMetrics, Retry};
+use io_copy::{transfer, Ending, Limits, Transfer};
+use router::Router;
+use std::sync::Arc;
+use tokio::io::{split, AsyncRead, AsyncWrite, AsyncWriteExt};
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
@@ -26,17 +48,230 @@ fn main() -> Result<()> {
                 .help("enable multi-threaded server")
                 .long("threads"),
         )
+        .arg(
+            clap::Arg::with_name("drain_timeout")
+                .help("seconds to wait for active connections to finish on shutdown")
+                .long("drain-timeout")
+                .takes_value(true)
+        )
+        .arg(
+            clap::Arg::with_name("metrics_port")
+                .help("serve Prometheus metrics over plain HTTP at /metrics on this port, announced after the listener")
+                .long("metrics-port")
+                .takes_value(true)
+        )
+        .arg(
+            clap::Arg::with_name("metrics_address")
+                .help("the address to serve metrics on, 127.0.0.1 by default, 0.0.0.0 for everywhere")
+                .long("metrics-address")
+                .takes_value(true)
+                .requires("metrics_port")
+        )
+        .arg(
+            clap::Arg::with_name("access_log")
+                .help("append a JSON record of every finished connection to this file")
+                .long("access-log")
+                .takes_value(true)
+        )
+        .arg(
+            clap::Arg::with_name("ready_file")
+                .help("write the LISTEN lines to this file once all listeners are up, rather than to stdout")
+                .long("ready-file")
+                .takes_value(true)
+        )
+        .arg(
+            clap::Arg::with_name("config")
+                .help("path to a configuration file, in .toml format, declaring the listeners")
+                .long("config")
+                .takes_value(true)
+                .conflicts_with_all(&["listen", "forward", "strategy", "failover", "connect_timeout", "retries", "backoff", "handshake_timeout", "idle_timeout", "max_lifetime", "max_connections", "max_connections_per_source", "over_limit", "rate_limit", "rate_burst", "ban_after", "ban_window", "ban_duration", "health_interval", "health_send", "health_expect", "accept_proxy", "trusted_proxy", "send_proxy", "backend_root", "backend_cert", "backend_key", "backend_server_name", "reload_interval", "drain_timeout", "metrics_port", "metrics_address", "access_log", "ready_file", "cert", "key", "ocsp", "client_auth", "crl", "allow_client", "deny_client"])
+        )
         .arg(
             clap::Arg::with_name("listen")
-                .help("port to listen on")
+                .help("port to listen on, 0 picks a free one")
                 .index(1)
-                .required(true)
+                .required_unless("config")
         )
         .arg(
             clap::Arg::with_name("forward")
-                .help("address to forward to, i.e. localhost:1729")
+                .help("address(es) to forward to, i.e. localhost:1729")
                 .index(2)
-                .required(true)
+                .multiple(true)
+                .required_unless("config")
+        )
+        .arg(
+            clap::Arg::with_name("strategy")
+                .help("how to spread connections over multiple forward addresses")
+                .long("strategy")
+                .takes_value(true)
+                .possible_values(&["round-robin", "random", "least-connections", "weighted"])
+        )
+        .arg(
+            clap::Arg::with_name("failover")
+                .help("address(es) to forward to when none of the others can be connected to")
+                .long("failover")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+        )
+        .arg(
+            clap::Arg::with_name("connect_timeout")
+                .help("seconds to wait for a connection to a forward address")
+                .long("connect-timeout")
+                .takes_value(true)
+        )
+        .arg(
+            clap::Arg::with_name("retries")
+                .help("times to retry connecting when all forward addresses failed")
+                .long("retries")
+                .takes_value(true)
+        )
+        .arg(
+            clap::Arg::with_name("backoff")
+                .help("seconds to wait before the first retry, doubling on every next one")
+                .long("backoff")
+                .takes_value(true)
+        )
+        .arg(
+            clap::Arg::with_name("handshake_timeout")
+                .help("seconds a client gets to send the proxy header and finish the tls handshake")
+                .long("handshake-timeout")
+                .takes_value(true)
+        )
+        .arg(
+            clap::Arg::with_name("idle_timeout")
+                .help("close connections when no bytes went either way for this many seconds")
+                .long("idle-timeout")
+                .takes_value(true)
+        )
+        .arg(
+            clap::Arg::with_name("max_lifetime")
+                .help("close connections after this many seconds, no matter what")
+                .long("max-lifetime")
+                .takes_value(true)
+        )
+        .arg(
+            clap::Arg::with_name("max_connections")
+                .help("handle at most this many connections at once")
+                .long("max-connections")
+                .takes_value(true)
+        )
+        .arg(
+            clap::Arg::with_name("max_connections_per_source")
+                .help("handle at most this many connections at once from a single address, more are closed right away")
+                .long("max-connections-per-source")
+                .takes_value(true)
+        )
+        .arg(
+            clap::Arg::with_name("over_limit")
+                .help("what to do with new connections at the maximum, pause accepting or close them")
+                .long("over-limit")
+                .takes_value(true)
+                .possible_values(&["pause", "close"])
+        )
+        .arg(
+            clap::Arg::with_name("rate_limit")
+                .help("close new connections from an address connecting more often than this many times per second")
+                .long("rate-limit")
+                .takes_value(true)
+        )
+        .arg(
+            clap::Arg::with_name("rate_burst")
+                .help("connections an address can make at once before the rate limit applies")
+                .long("rate-burst")
+                .takes_value(true)
+                .requires("rate_limit")
+        )
+        .arg(
+            clap::Arg::with_name("ban_after")
+                .help("ban addresses failing this many handshakes, including failed authentication, within the ban window")
+                .long("ban-after")
+                .takes_value(true)
+        )
+        .arg(
+            clap::Arg::with_name("ban_window")
+                .help("seconds in which failed handshakes count towards a ban")
+                .long("ban-window")
+                .takes_value(true)
+                .requires("ban_after")
+        )
+        .arg(
+            clap::Arg::with_name("ban_duration")
+                .help("seconds an address stays banned")
+                .long("ban-duration")
+                .takes_value(true)
+                .requires("ban_after")
+        )
+        .arg(
+            clap::Arg::with_name("health_interval")
+                .help("check the forward addresses every this many seconds, skipping the ones that are down")
+                .long("health-interval")
+                .takes_value(true)
+        )
+        .arg(
+            clap::Arg::with_name("health_send")
+                .help("payload to send when health checking")
+                .long("health-send")
+                .takes_value(true)
+                .requires("health_interval")
+        )
+        .arg(
+            clap::Arg::with_name("health_expect")
+                .help("response expected when health checking")
+                .long("health-expect")
+                .takes_value(true)
+                .requires("health_interval")
+        )
+        .arg(
+            clap::Arg::with_name("accept_proxy")
+                .help("expect a PROXY protocol header from a load balancer in front before the handshake")
+                .long("accept-proxy")
+                .takes_value(true)
+                .possible_values(&["optional", "required"])
+                .requires("trusted_proxy")
+        )
+        .arg(
+            clap::Arg::with_name("trusted_proxy")
+                .help("only take PROXY protocol headers from this address, anyone else could claim any source")
+                .long("trusted-proxy")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .requires("accept_proxy")
+        )
+        .arg(
+            clap::Arg::with_name("send_proxy")
+                .help("prepend a PROXY protocol header with the client address to every forwarded connection")
+                .long("send-proxy")
+                .takes_value(true)
+                .possible_values(&["v1", "v2"])
+        )
+        .arg(
+            clap::Arg::with_name("backend_root")
+                .help("connect to the backends over tls, takes the path to the root certificates to verify them against, in .pem format")
+                .long("backend-root")
+                .takes_value(true)
+        )
+        .arg(
+            clap::Arg::with_name("backend_cert")
+                .help("path to the file containing the certificate to authenticate to the backends with, in .pem format")
+                .long("backend-cert")
+                .takes_value(true)
+                .requires_all(&["backend_root", "backend_key"])
+        )
+        .arg(
+            clap::Arg::with_name("backend_key")
+                .help("path to the file containing the private key to authenticate to the backends with, in .pem format")
+                .long("backend-key")
+                .takes_value(true)
+                .requires_all(&["backend_root", "backend_cert"])
+        )
+        .arg(
+            clap::Arg::with_name("backend_server_name")
+                .help("name to verify the backend certificates against, the host part of the forward address by default")
+                .long("backend-server-name")
+                .takes_value(true)
+                .requires("backend_root")
         )
         .arg(
             clap::Arg::with_name("cert")
@@ -44,7 +279,7 @@ fn main() -> Result<()> {
                 .short("c")
                 .long("cert")
                 .takes_value(true)
-                .required(true)
+                .required_unless("config")
         )
         .arg(
             clap::Arg::with_name("key")
@@ -52,7 +287,20 @@ fn main() -> Result<()> {
                 .short("k")
                 .long("key")
                 .takes_value(true)
-                .required(true)
+                .required_unless("config")
+        )
+        .arg(
+            clap::Arg::with_name("ocsp")
+                .help("path to the file containing an OCSP response to staple to the certificate, in .der format, read again every minute, the certificate file must hold the issuer too")
+                .long("ocsp")
+                .takes_value(true)
+                .requires("cert")
+        )
+        .arg(
+            clap::Arg::with_name("reload_interval")
+                .help("check the certificate, key and root files for changes every this many seconds, they are always reloaded on SIGHUP")
+                .long("reload-interval")
+                .takes_value(true)
         )
         .arg(
             clap::Arg::with_name("client_auth")
@@ -61,6 +309,33 @@ fn main() -> Result<()> {
                 .long("authenticate")
                 .takes_value(true)
         )
+        .arg(
+            clap::Arg::with_name("crl")
+                .help("reject client certificates revoked by the lists in this file, in .pem or .der format, reloaded along with the certificates")
+                .long("crl")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .requires("client_auth")
+        )
+        .arg(
+            clap::Arg::with_name("allow_client")
+                .help("only let in authenticated clients matching one of these rules, i.e. cn:alice, ou:payments, dns:*.example.com, uri:spiffe://example.com/*, email:alice@example.com or sha256:<fingerprint>")
+                .long("allow-client")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .requires("client_auth")
+        )
+        .arg(
+            clap::Arg::with_name("deny_client")
+                .help("keep out authenticated clients matching any of these rules, in the same format as --allow-client")
+                .long("deny-client")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .requires("client_auth")
+        )
         .get_matches();
 
     let level = if args.is_present("debug") {
@@ -69,59 +344,390 @@ fn main() -> Result<()> {
         log::Level::Info
     };
 
-    simple_logger::init_with_level(level)?;
+    // local timestamps can not be determined safely once there are multiple threads
+    simple_logger::SimpleLogger::new()
+        .with_level(level.to_level_filter())
+        .with_utc_timestamps()
+        .init()?;
 
     log::debug!("arguments are config file is {:?}", args);
 
-    let listen_port: u16 = args.value_of("listen").unwrap().parse().expect("a number");
-    let forward_address = args.value_of("forward").unwrap();
-    log::info!(
-        "setting up to listen at {} and forward to {}",
-        listen_port,
-        forward_address
-    );
-
-    let mut config = tls_server::Config::new(listen_port);
-    config.with_threading(args.is_present("threads"));
-    config.with_certificate_and_key_files(
-        args.value_of("cert").unwrap(),
-        args.value_of("key").unwrap(),
-    )?;
-    if let Some(root) = args.value_of("client_auth") {
+    let mut config = match args.value_of("config") {
+        Some(filename) => Config::from_file(filename)?,
+        None => Config::from_args(&args)?,
+    };
+    config.threads |= args.is_present("threads");
+
+    log::debug!("configuration is {:?}", config);
+
+    run(config)
+}
+
+fn run(config: Config) -> Result<()> {
+    let metrics = tls_server::Metrics::default();
+    let access_log = match &config.access_log {
+        Some(path) => Some(AccessLog::open(path)?),
+        None => None,
+    };
+
+    // set up all servers before starting any, so configuration errors are reported up front
+    let servers = config
+        .listeners
+        .iter()
+        .map(|listener| {
+            let server = make_server(listener, &config, &metrics, access_log.as_ref())?;
+            let connector = match &listener.backend_tls {
+                Some(tls) => Some(make_connector(tls)?),
+                None => None,
+            };
+            Ok((listener.clone(), server, connector))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut running = servers
+        .into_iter()
+        .map(|(listener, server, connector)| {
+            serve(
+                listener,
+                server,
+                connector,
+                metrics.clone(),
+                access_log.clone(),
+            )
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if let Some(port) = config.metrics_port {
+        running.push(serve_metrics(config.metrics_address, port, metrics)?);
+    }
+
+    // in the order of the configuration, so listeners on port 0 can be told apart
+    let announcement: String = running
+        .iter()
+        .map(|r| format!("LISTEN {}\n", r.address()))
+        .collect();
+    announce(&announcement, config.ready_file.as_deref())?;
+
+    let mut result = Ok(());
+    for r in running {
+        if let Err(e) = r.join() {
+            log::error!("listener failed: {}", e);
+            result = Err(e);
+        }
+    }
+    if let Some(log) = access_log {
+        log.close();
+    }
+    result
+}
+
+// Tell whoever started the process where the listeners are. The ready file appears whole, so
+// whoever waits for it never reads half of it.
+fn announce(announcement: &str, ready_file: Option<&str>) -> Result<()> {
+    match ready_file {
+        Some(path) => {
+            let partial = format!("{}.partial", path);
+            std::fs::write(&partial, announcement)?;
+            std::fs::rename(&partial, path)?;
+        }
+        None => {
+            print!("{}", announcement);
+            std::io::Write::flush(&mut std::io::stdout())?;
+        }
+    }
+    Ok(())
+}
+
+fn make_server(
+    listener: &Listener,
+    global: &Config,
+    metrics: &tls_server::Metrics,
+    access_log: Option<&AccessLog>,
+) -> Result<tls_server::Server> {
+    match &listener.forward {
+        Some(forward) => log::info!(
+            "setting up to listen at {} and forward to {} ({:?})",
+            listener.port,
+            forward,
+            listener.strategy
+        ),
+        None => log::info!("setting up to listen at {}", listener.port),
+    }
+
+    let mut config = tls_server::Config::new(listener.port);
+    config
+        .with_threading(global.threads)
+        .with_drain_timeout(std::time::Duration::from_secs_f64(global.drain_timeout))
+        .with_handshake_timeout(std::time::Duration::from_secs_f64(
+            listener.handshake_timeout,
+        ))
+        .with_metrics(metrics.clone());
+    if let Some(lifetime) = listener.max_lifetime {
+        config.with_max_lifetime(std::time::Duration::from_secs_f64(lifetime));
+    }
+    if let Some(max) = listener.max_connections {
+        config.with_max_connections(max);
+    }
+    if let Some(max) = listener.max_connections_per_source {
+        config.with_max_connections_per_source(max);
+    }
+    if let Some(limit) = &listener.rate_limit {
+        let mut rate_limit = tls_server::RateLimit::new(limit.rate, limit.burst() as f64);
+        rate_limit.ipv4_prefix = limit.ipv4_prefix;
+        rate_limit.ipv6_prefix = limit.ipv6_prefix;
+        config.with_rate_limit(rate_limit);
+    }
+    if let Some(ban) = &listener.ban {
+        config.with_bans(tls_server::BanPolicy {
+            max_failures: ban.failures,
+            window: std::time::Duration::from_secs_f64(ban.window),
+            duration: std::time::Duration::from_secs_f64(ban.duration),
+        });
+    }
+    match listener.over_limit {
+        OverLimit::Pause => config.with_over_limit(tls_server::OverLimit::Pause),
+        OverLimit::Close => config.with_over_limit(tls_server::OverLimit::Close),
+    };
+    if let (Some(cert), Some(key)) = (&listener.cert, &listener.key) {
+        config.with_certificate_and_key_files(cert, key)?;
+    }
+    if let Some(ocsp) = &listener.ocsp {
+        config.with_ocsp_response_file(ocsp)?;
+    }
+    for route in listener.routes.iter() {
+        log::info!(
+            "routing {} to {} on port {}",
+            route.name(),
+            route.forward,
+            listener.port
+        );
+        if let (Some(sni), Some(cert), Some(key)) = (&route.sni, &route.cert, &route.key) {
+            config.with_sni_certificate_and_key_files(sni, cert, key)?;
+        }
+    }
+    if !listener.alpn.is_empty() {
+        let protocols: Vec<&str> = listener.alpn.iter().map(|p| p.as_str()).collect();
+        config.with_alpn_protocols(&protocols);
+    }
+    if let Some(root) = &listener.authenticate {
         config.with_client_authentication(root)?;
     }
+    for crl in listener.crl.iter() {
+        config.with_client_revocation_list(crl)?;
+    }
+    if let Some(policy) = &listener.clients {
+        let policy = policy.clone();
+        config.with_client_authorization(move |client| policy.check(client));
+    }
+    if let Some(interval) = listener.reload_interval {
+        config.with_reload_interval(std::time::Duration::from_secs_f64(interval));
+    }
+    match listener.accept_proxy {
+        Some(AcceptProxy::Optional) => {
+            config.with_proxy_protocol(tls_server::ProxyProtocol::Optional);
+        }
+        Some(AcceptProxy::Required) => {
+            config.with_proxy_protocol(tls_server::ProxyProtocol::Required);
+        }
+        None => (),
+    }
+    config.with_trusted_proxies(&listener.trusted_proxies);
+    if let Some(log) = access_log {
+        let log = log.clone();
+        config.with_rejection_handler(move |context, rejection| log.rejected(context, rejection));
+    }
+
+    tls_server::Server::new(config)
+}
+
+fn make_connector(tls: &BackendTls) -> Result<katey_client::Connector> {
+    let mut connector = katey_client::Connector::new();
+    connector.with_root(&tls.root)?;
+    if let (Some(cert), Some(key)) = (&tls.cert, &tls.key) {
+        connector.with_certificate_and_key_files(cert, key)?;
+    }
+    if let Some(name) = &tls.server_name {
+        connector.with_server_name(name)?;
+    }
+    Ok(connector)
+}
+
+// Everything a connection on a listener is forwarded with.
+#[derive(Clone)]
+struct Forwarder {
+    router: Arc<Router>,
+    connector: Option<katey_client::Connector>,
+    retry: Retry,
+    send_proxy: Option<proxy_protocol::Version>,
+    idle_timeout: Option<std::time::Duration>,
+    metrics: tls_server::Metrics,
+    access_log: Option<AccessLog>,
+}
+
+fn serve(
+    listener: Listener,
+    server: tls_server::Server,
+    connector: Option<katey_client::Connector>,
+    metrics: tls_server::Metrics,
+    access_log: Option<AccessLog>,
+) -> Result<tls_server::Handle> {
+    let forwarder = Forwarder {
+        router: Arc::new(Router::new(&listener)),
+        connector,
+        retry: Retry::new(&listener),
+        send_proxy: listener.send_proxy,
+        idle_timeout: listener
+            .idle_timeout
+            .map(std::time::Duration::from_secs_f64),
+        metrics,
+        access_log,
+    };
+
+    if let Some(check) = listener.health {
+        health::spawn(forwarder.router.clone(), check);
+    }
+
+    server.spawn(move |stream, context| forward(stream, context, forwarder.clone()))
+}
 
-    let mut server = tls_server::Server::new(config)?;
+fn serve_metrics(
+    address: std::net::IpAddr,
+    port: u16,
+    metrics: tls_server::Metrics,
+) -> Result<tls_server::Handle> {
+    log::info!("serving metrics at {}:{}", address, port);
 
-    set_forward_address(forward_address)?;
-    server.run(move |stream| async move {
-        match TcpStream::connect(get_forward_address()).await {
-            Ok(forward) => handle(stream, forward).await,
-            Err(e) => {
-                log::error!("could not forward: {}", e);
+    let config = tcp_server::Config::new(port).with_address(address);
+    tcp_server::Server::new(config)?.spawn(move |stream, context| {
+        let metrics = metrics.clone();
+        async move {
+            // scrapers send their request right away, don't let anyone else hang around
+            let timeout = std::time::Duration::from_secs(10);
+            if let Err(e) = metrics.respond(stream, timeout).await {
+                log::warn!("could not serve metrics to {}: {}", context.peer_addr(), e);
             }
-        };
+        }
     })
 }
 
-async fn handle(from_stream: tls_server::Stream, to_stream: TcpStream) {
-    let from_stream = split(from_stream);
-    let to_stream = split(to_stream);
+async fn forward(
+    stream: tls_server::Stream,
+    context: tls_server::ConnectionContext,
+    forwarder: Forwarder,
+) {
+    let session = tls_server::Session::new(&stream);
+    let mut entry = Entry::new(forwarder.access_log.clone(), &context, &session);
+    if let Some(client) = session.peer_certificate() {
+        log::info!(
+            "connection {} authenticated as {} (serial {})",
+            context.id(),
+            client.subject,
+            client.serial
+        );
+    }
 
-    let _ = proxy(from_stream, to_stream).await;
-}
+    let sni = session.server_name.as_deref();
+    let alpn = session.alpn_protocol.as_deref();
+    let pool = match forwarder.router.route(sni, alpn) {
+        Some(pool) => pool,
+        None => {
+            log::warn!(
+                "no route for server name {:?} and protocol {:?}, rejecting connection {}",
+                sni,
+                alpn.map(String::from_utf8_lossy),
+                context.id()
+            );
+            entry.failed("no route");
+            return;
+        }
+    };
 
-// TODO: figure out the safe and proper way to get a String with proper lifetime into the handler
+    // by the port actually listened on, not the one a proxy in front says the client connected to
+    let (connection, _) = stream.get_ref();
+    let listener = match connection.get_ref().local_addr() {
+        Ok(address) => address.port().to_string(),
+        Err(_) => "unknown".to_string(),
+    };
+    let backend_metrics = BackendMetrics::new(&forwarder.metrics, &listener);
+
+    match connect::connect(pool, &forwarder.retry, &backend_metrics).await {
+        Some((backend, mut forward)) => {
+            log::info!(
+                "forwarding connection {} from {} to backend {}",
+                context.id(),
+                context.peer_addr(),
+                backend.address()
+            );
+            entry.backend(backend.address());
+
+            if let Some(version) = forwarder.send_proxy {
+                let sent = match header::make(&stream, &session) {
+                    Ok(h) => forward.write_all(&h.encode(version)).await,
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = sent {
+                    log::error!(
+                        "could not send proxy header to backend {}: {}",
+                        backend.address(),
+                        e
+                    );
+                    entry.failed(&format!("could not send proxy header: {}", e));
+                    return;
+                }
+            }
 
-static mut FORWARD_ADDRESS: String = String::new();
+            let limits = Limits {
+                idle_timeout: forwarder.idle_timeout,
+                deadline: context.deadline(),
+            };
+            let transferred = match forwarder.connector {
+                Some(connector) => match connector.connect(backend.address(), forward).await {
+                    Ok(forward) => handle(stream, forward, limits).await,
+                    Err(e) => {
+                        log::error!(
+                            "could not set up tls with backend {}: {}",
+                            backend.address(),
+                            e
+                        );
+                        entry.failed(&format!("could not set up tls with backend: {}", e));
+                        return;
+                    }
+                },
+                None => handle(stream, forward, limits).await,
+            };
 
-fn get_forward_address() -> String {
-    unsafe { FORWARD_ADDRESS.clone() }
+            match transferred.ending {
+                Ending::Idle(idle) => log::info!(
+                    "closing connection {} from {}, it was idle for {:?}",
+                    context.id(),
+                    context.peer_addr(),
+                    idle
+                ),
+                Ending::Expired => log::info!(
+                    "closing connection {} from {}, it reached its maximum lifetime",
+                    context.id(),
+                    context.peer_addr()
+                ),
+                _ => (),
+            }
+            entry.transferred(transferred);
+        }
+        None => {
+            log::error!(
+                "could not forward connection {} from {}, giving up",
+                context.id(),
+                context.peer_addr()
+            );
+            entry.failed("could not connect to a backend");
+        }
+    };
 }
 
-fn set_forward_address(s: &str) -> Result<()> {
-    unsafe {
-        FORWARD_ADDRESS = String::from_str(s)?;
-    }
-    Ok(())
+async fn handle<S>(from_stream: tls_server::Stream, to_stream: S, limits: Limits) -> Transfer
+where
+    S: AsyncRead + AsyncWrite,
+{
+    let from_stream = split(from_stream);
+    let to_stream = split(to_stream);
+
+    transfer(from_stream, to_stream, limits).await
 }
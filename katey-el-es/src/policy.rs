@@ -0,0 +1,291 @@
+use certutils::CertificateInfo;
+use serde::Deserialize;
+
+// Which authenticated clients a listener lets in. A client matching any deny rule is rejected,
+// when there are allow rules a client has to match at least one of them.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClientPolicy {
+    #[serde(default)]
+    pub allow: Vec<Rule>,
+
+    #[serde(default)]
+    pub deny: Vec<Rule>,
+}
+
+// A field of the client certificate and the pattern it should match, written as "cn:alice".
+// Patterns can have * wildcards, except for fingerprints.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Rule {
+    field: Field,
+    pattern: String,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Field {
+    CommonName,
+    OrganizationalUnit,
+    Dns,
+    Uri,
+    Email,
+    Fingerprint,
+}
+
+impl ClientPolicy {
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    pub fn check(&self, client: &CertificateInfo) -> std::result::Result<(), String> {
+        if let Some(rule) = self.deny.iter().find(|r| r.matches(client)) {
+            return Err(format!("denied by {}", rule));
+        }
+        if !self.allow.is_empty() && !self.allow.iter().any(|r| r.matches(client)) {
+            return Err("not allowed by any rule".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl Rule {
+    pub fn matches(&self, client: &CertificateInfo) -> bool {
+        let (values, ignore_case): (Vec<&str>, bool) = match self.field {
+            Field::CommonName => (
+                client.common_name.iter().map(|s| s.as_str()).collect(),
+                false,
+            ),
+            Field::OrganizationalUnit => (
+                client
+                    .organizational_units
+                    .iter()
+                    .map(|s| s.as_str())
+                    .collect(),
+                false,
+            ),
+            Field::Dns => (client.dns_names().collect(), true),
+            Field::Uri => (client.uris().collect(), false),
+            Field::Email => (client.emails().collect(), true),
+            Field::Fingerprint => {
+                return normalize(&self.pattern) == normalize(&client.fingerprint)
+            }
+        };
+
+        values.iter().any(|v| {
+            if ignore_case {
+                glob(&self.pattern.to_lowercase(), &v.to_lowercase())
+            } else {
+                glob(&self.pattern, v)
+            }
+        })
+    }
+}
+
+impl std::str::FromStr for Rule {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Rule, String> {
+        let (field, pattern) = match s.find(':') {
+            Some(i) => (&s[..i], &s[i + 1..]),
+            None => return Err(format!("client rule {} should look like field:pattern", s)),
+        };
+
+        let field = match field {
+            "cn" => Field::CommonName,
+            "ou" => Field::OrganizationalUnit,
+            "dns" => Field::Dns,
+            "uri" => Field::Uri,
+            "email" => Field::Email,
+            "sha256" => Field::Fingerprint,
+            _ => {
+                return Err(format!(
+                    "unknown field {} in client rule, use cn, ou, dns, uri, email or sha256",
+                    field
+                ))
+            }
+        };
+
+        if pattern.is_empty() {
+            return Err(format!("client rule {} has an empty pattern", s));
+        }
+        if field == Field::Fingerprint
+            && (normalize(pattern).len() != 64
+                || !normalize(pattern).chars().all(|c| c.is_ascii_hexdigit()))
+        {
+            return Err(format!("{} is not a SHA-256 fingerprint", pattern));
+        }
+
+        Ok(Rule {
+            field,
+            pattern: pattern.to_string(),
+        })
+    }
+}
+
+impl std::convert::TryFrom<String> for Rule {
+    type Error = String;
+
+    fn try_from(s: String) -> std::result::Result<Rule, String> {
+        s.parse()
+    }
+}
+
+impl std::fmt::Display for Rule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let field = match self.field {
+            Field::CommonName => "cn",
+            Field::OrganizationalUnit => "ou",
+            Field::Dns => "dns",
+            Field::Uri => "uri",
+            Field::Email => "email",
+            Field::Fingerprint => "sha256",
+        };
+        write!(f, "{}:{}", field, self.pattern)
+    }
+}
+
+// Whether the text matches the pattern, where * matches any run of characters.
+fn glob(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    if !text.starts_with(first) {
+        return false;
+    }
+
+    let mut rest = &text[first.len()..];
+    let parts: Vec<&str> = parts.collect();
+    match parts.split_last() {
+        None => rest.is_empty(),
+        Some((last, middle)) => {
+            for part in middle {
+                match rest.find(part) {
+                    Some(i) => rest = &rest[i + part.len()..],
+                    None => return false,
+                }
+            }
+            rest.len() >= last.len() && rest.ends_with(last)
+        }
+    }
+}
+
+fn normalize(fingerprint: &str) -> String {
+    fingerprint
+        .chars()
+        .filter(|c| *c != ':')
+        .collect::<String>()
+        .to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use certutils::SubjectAltName;
+
+    const FINGERPRINT: &str = "AB:CD:EF:01:23:45:67:89:AB:CD:EF:01:23:45:67:89:AB:CD:EF:01:23:45:67:89:AB:CD:EF:01:23:45:67:89";
+
+    fn client() -> CertificateInfo {
+        CertificateInfo {
+            subject: "CN=alice, OU=payments".to_string(),
+            common_name: Some("alice".to_string()),
+            organizational_units: vec!["payments".to_string()],
+            subject_alt_names: vec![
+                SubjectAltName::Dns("alice.example.com".to_string()),
+                SubjectAltName::Uri("spiffe://example.com/ns/payments/sa/alice".to_string()),
+                SubjectAltName::Email("alice@example.com".to_string()),
+            ],
+            issuer: "CN=root".to_string(),
+            serial: "01".to_string(),
+            not_before: std::time::SystemTime::UNIX_EPOCH,
+            not_after: std::time::SystemTime::UNIX_EPOCH,
+            fingerprint: FINGERPRINT.to_string(),
+        }
+    }
+
+    fn policy(allow: &[&str], deny: &[&str]) -> ClientPolicy {
+        ClientPolicy {
+            allow: allow.iter().map(|r| r.parse().unwrap()).collect(),
+            deny: deny.iter().map(|r| r.parse().unwrap()).collect(),
+        }
+    }
+
+    #[test]
+    fn empty_policy_allows_everyone() {
+        assert!(policy(&[], &[]).check(&client()).is_ok());
+    }
+
+    #[test]
+    fn matches_every_field() {
+        for rule in &[
+            "cn:alice",
+            "ou:payments",
+            "dns:alice.example.com",
+            "uri:spiffe://example.com/ns/payments/sa/alice",
+            "email:alice@example.com",
+        ] {
+            assert!(policy(&[rule], &[]).check(&client()).is_ok(), "{}", rule);
+            assert!(policy(&[], &[rule]).check(&client()).is_err(), "{}", rule);
+        }
+    }
+
+    #[test]
+    fn requires_an_allow_rule_to_match() {
+        let result = policy(&["cn:bob", "ou:billing"], &[]).check(&client());
+        assert_eq!(Err("not allowed by any rule".to_string()), result);
+    }
+
+    #[test]
+    fn deny_goes_before_allow() {
+        let result = policy(&["cn:alice"], &["ou:payments"]).check(&client());
+        assert_eq!(Err("denied by ou:payments".to_string()), result);
+    }
+
+    #[test]
+    fn matches_wildcards() {
+        assert!(policy(&["uri:spiffe://example.com/ns/payments/*"], &[])
+            .check(&client())
+            .is_ok());
+        assert!(policy(&["dns:*.example.com"], &[]).check(&client()).is_ok());
+        assert!(policy(&["cn:a*e"], &[]).check(&client()).is_ok());
+        assert!(policy(&["uri:spiffe://example.com/ns/billing/*"], &[])
+            .check(&client())
+            .is_err());
+    }
+
+    #[test]
+    fn dns_and_email_ignore_case() {
+        assert!(policy(&["dns:ALICE.example.com"], &[])
+            .check(&client())
+            .is_ok());
+        assert!(policy(&["cn:ALICE"], &[]).check(&client()).is_err());
+    }
+
+    #[test]
+    fn matches_fingerprints_with_or_without_colons() {
+        let plain = FINGERPRINT.replace(":", "").to_lowercase();
+        assert!(policy(&[&format!("sha256:{}", plain)], &[])
+            .check(&client())
+            .is_ok());
+        assert!(policy(&[&format!("sha256:{}", FINGERPRINT)], &[])
+            .check(&client())
+            .is_ok());
+    }
+
+    #[test]
+    fn rejects_bad_rules() {
+        assert!("alice".parse::<Rule>().is_err());
+        assert!("name:alice".parse::<Rule>().is_err());
+        assert!("cn:".parse::<Rule>().is_err());
+        assert!("sha256:abcd".parse::<Rule>().is_err());
+    }
+
+    #[test]
+    fn globs() {
+        assert!(glob("abc", "abc"));
+        assert!(!glob("abc", "abcd"));
+        assert!(glob("a*", "abcd"));
+        assert!(glob("*d", "abcd"));
+        assert!(glob("a*c*", "abcd"));
+        assert!(!glob("ab*bc", "abc"));
+        assert!(glob("*", ""));
+    }
+}
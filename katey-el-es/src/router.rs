@@ -0,0 +1,223 @@
+use super::balancer::{Backend, Pool};
+use super::config::Listener;
+
+struct Route {
+    sni: Option<String>,
+    alpn: Option<Vec<u8>>,
+    forward: Pool,
+}
+
+// Chooses the backends for a connection by the server name the client sent and the
+// protocol that was negotiated.
+pub struct Router {
+    routes: Vec<Route>,
+    default: Option<Pool>,
+}
+
+impl Router {
+    pub fn new(listener: &Listener) -> Router {
+        let routes = listener
+            .routes
+            .iter()
+            .map(|r| Route {
+                sni: r.sni.as_ref().map(|s| s.to_lowercase()),
+                alpn: r.alpn.as_ref().map(|s| s.as_bytes().to_vec()),
+                forward: Pool::new(
+                    &r.forward.0,
+                    r.failover.as_ref().map(|f| &f.0[..]).unwrap_or(&[]),
+                    listener.strategy,
+                ),
+            })
+            .collect();
+
+        Router {
+            routes,
+            default: listener.forward.as_ref().map(|f| {
+                Pool::new(
+                    &f.0,
+                    listener.failover.as_ref().map(|f| &f.0[..]).unwrap_or(&[]),
+                    listener.strategy,
+                )
+            }),
+        }
+    }
+
+    pub fn backends(&self) -> impl Iterator<Item = &Backend> {
+        self.routes
+            .iter()
+            .map(|r| &r.forward)
+            .chain(self.default.iter())
+            .flat_map(|pool| pool.backends())
+    }
+
+    pub fn route(&self, sni: Option<&str>, alpn: Option<&[u8]>) -> Option<&Pool> {
+        let sni = sni.map(|s| s.to_lowercase());
+
+        self.routes
+            .iter()
+            .filter_map(|r| Router::score(r, sni.as_deref(), alpn).map(|score| (score, r)))
+            .max_by_key(|(score, _)| *score)
+            .map(|(_, r)| &r.forward)
+            .or(self.default.as_ref())
+    }
+
+    // None if the route does not match, otherwise how specific the match is
+    fn score(route: &Route, sni: Option<&str>, alpn: Option<&[u8]>) -> Option<u8> {
+        let mut score = 0;
+
+        if let Some(name) = &route.sni {
+            if sni != Some(name.as_str()) {
+                return None;
+            }
+            score += 2;
+        }
+
+        if let Some(protocol) = &route.alpn {
+            if alpn != Some(protocol.as_slice()) {
+                return None;
+            }
+            score += 1;
+        }
+
+        Some(score)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Forward, Route};
+
+    fn route(sni: Option<&str>, alpn: Option<&str>, forward: &str) -> Route {
+        Route {
+            sni: sni.map(|s| s.to_string()),
+            alpn: alpn.map(|s| s.to_string()),
+            forward: Forward::from_addresses(&[forward]),
+            failover: None,
+            cert: None,
+            key: None,
+        }
+    }
+
+    fn listener(forward: Option<&str>, routes: Vec<Route>) -> Listener {
+        Listener {
+            port: 5000,
+            forward: forward.map(|s| Forward::from_addresses(&[s])),
+            strategy: Default::default(),
+            failover: None,
+            connect_timeout: 5.0,
+            retries: 0,
+            backoff: 0.1,
+            handshake_timeout: 10.0,
+            idle_timeout: None,
+            max_lifetime: None,
+            max_connections: None,
+            max_connections_per_source: None,
+            over_limit: Default::default(),
+            accept_proxy: None,
+            trusted_proxies: vec![],
+            send_proxy: None,
+            reload_interval: None,
+            backend_tls: None,
+            cert: Some("server-cert.pem".to_string()),
+            key: Some("server-key.pem".to_string()),
+            ocsp: None,
+            authenticate: None,
+            clients: None,
+            crl: vec![],
+            alpn: vec![],
+            health: None,
+            rate_limit: None,
+            ban: None,
+            routes,
+        }
+    }
+
+    fn forward<'a>(router: &'a Router, sni: Option<&str>, alpn: Option<&[u8]>) -> Option<&'a str> {
+        router
+            .route(sni, alpn)
+            .map(|pool| pool.candidates()[0].address())
+    }
+
+    fn sni_routes() -> Vec<Route> {
+        vec![
+            route(Some("echo"), None, "localhost:4000"),
+            route(Some("Fib.Example.Com"), None, "localhost:4001"),
+        ]
+    }
+
+    #[test]
+    fn routes_by_name() {
+        let router = Router::new(&listener(None, sni_routes()));
+
+        assert_eq!(Some("localhost:4000"), forward(&router, Some("echo"), None));
+        assert_eq!(
+            Some("localhost:4001"),
+            forward(&router, Some("fib.example.com"), None)
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_without_default() {
+        let router = Router::new(&listener(None, sni_routes()));
+
+        assert_eq!(None, forward(&router, Some("other"), None));
+        assert_eq!(None, forward(&router, None, None));
+    }
+
+    #[test]
+    fn unknown_goes_to_default() {
+        let router = Router::new(&listener(Some("localhost:4002"), sni_routes()));
+
+        assert_eq!(Some("localhost:4000"), forward(&router, Some("echo"), None));
+        assert_eq!(
+            Some("localhost:4002"),
+            forward(&router, Some("other"), None)
+        );
+        assert_eq!(Some("localhost:4002"), forward(&router, None, None));
+    }
+
+    #[test]
+    fn routes_by_protocol() {
+        let router = Router::new(&listener(
+            None,
+            vec![
+                route(None, Some("h2"), "localhost:4000"),
+                route(None, Some("line"), "localhost:4001"),
+            ],
+        ));
+
+        assert_eq!(Some("localhost:4000"), forward(&router, None, Some(b"h2")));
+        assert_eq!(
+            Some("localhost:4001"),
+            forward(&router, Some("echo"), Some(b"line"))
+        );
+        assert_eq!(None, forward(&router, None, None));
+    }
+
+    #[test]
+    fn most_specific_route_wins() {
+        let router = Router::new(&listener(
+            Some("localhost:4000"),
+            vec![
+                route(None, Some("h2"), "localhost:4001"),
+                route(Some("echo"), None, "localhost:4002"),
+                route(Some("echo"), Some("h2"), "localhost:4003"),
+            ],
+        ));
+
+        assert_eq!(
+            Some("localhost:4003"),
+            forward(&router, Some("echo"), Some(b"h2"))
+        );
+        assert_eq!(
+            Some("localhost:4002"),
+            forward(&router, Some("echo"), Some(b"line"))
+        );
+        assert_eq!(
+            Some("localhost:4001"),
+            forward(&router, Some("fib"), Some(b"h2"))
+        );
+        assert_eq!(Some("localhost:4000"), forward(&router, Some("fib"), None));
+    }
+}
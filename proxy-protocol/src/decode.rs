@@ -0,0 +1,310 @@
+use super::*;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+const V1_PREFIX: &[u8; 6] = b"PROXY ";
+const V1_MAX_LENGTH: usize = 107;
+
+// Whether buf, which may be shorter than a complete header, looks like the start of one.
+pub fn starts_header(buf: &[u8]) -> bool {
+    let matches = |prefix: &[u8]| {
+        let n = buf.len().min(prefix.len());
+        n > 0 && buf[..n] == prefix[..n]
+    };
+    matches(V1_PREFIX) || matches(V2_SIGNATURE)
+}
+
+// Read exactly one header from the reader, leaving whatever follows it unread. Headers that do
+// not carry addresses (v1 UNKNOWN, v2 LOCAL) give None.
+pub async fn read<R: AsyncRead + Unpin>(reader: &mut R) -> std::io::Result<Option<Header>> {
+    // read byte by byte up to the v2 length, so it fails early on anything that is not a header
+    // instead of waiting for more data the other side will never send
+    let mut buf = Vec::with_capacity(16);
+    while buf.len() < 16 && !(buf.starts_with(V1_PREFIX) && buf.ends_with(b"\r\n")) {
+        let mut byte = [0u8; 1];
+        reader.read_exact(&mut byte).await?;
+        buf.push(byte[0]);
+        if !starts_header(&buf) {
+            return Err(invalid("not a proxy header".to_string()));
+        }
+    }
+
+    if buf.starts_with(V2_SIGNATURE) {
+        let length = u16::from_be_bytes([buf[14], buf[15]]) as usize;
+        buf.resize(16 + length, 0);
+        reader.read_exact(&mut buf[16..]).await?;
+    } else {
+        // v1 is a single line, nothing past it may be consumed
+        while !buf.ends_with(b"\r\n") {
+            if buf.len() >= V1_MAX_LENGTH {
+                return Err(invalid("proxy header line too long".to_string()));
+            }
+            let mut byte = [0u8; 1];
+            reader.read_exact(&mut byte).await?;
+            buf.push(byte[0]);
+        }
+    }
+
+    decode(&buf).map_err(invalid)
+}
+
+// Decode a complete header.
+pub fn decode(buf: &[u8]) -> std::result::Result<Option<Header>, String> {
+    if buf.starts_with(V1_PREFIX) {
+        decode_v1(buf)
+    } else if buf.starts_with(V2_SIGNATURE) {
+        decode_v2(buf)
+    } else {
+        Err("not a proxy header".to_string())
+    }
+}
+
+fn decode_v1(buf: &[u8]) -> std::result::Result<Option<Header>, String> {
+    let line = std::str::from_utf8(buf)
+        .ok()
+        .and_then(|l| l.strip_suffix("\r\n"))
+        .ok_or_else(|| "malformed proxy header line".to_string())?;
+
+    let fields: Vec<&str> = line.split(' ').collect();
+    match fields.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", family, source, destination, source_port, destination_port] => {
+            let address = |ip: &str, port: &str| -> std::result::Result<SocketAddr, String> {
+                let ip: IpAddr = ip
+                    .parse()
+                    .map_err(|_| format!("invalid address {} in proxy header", ip))?;
+                let port: u16 = port
+                    .parse()
+                    .map_err(|_| format!("invalid port {} in proxy header", port))?;
+                match (*family, ip) {
+                    ("TCP4", IpAddr::V4(_)) | ("TCP6", IpAddr::V6(_)) => {
+                        Ok(SocketAddr::new(ip, port))
+                    }
+                    _ => Err(format!("address {} does not match {}", ip, family)),
+                }
+            };
+
+            Ok(Some(Header::new(
+                address(source, source_port)?,
+                address(destination, destination_port)?,
+            )))
+        }
+        _ => Err(format!("malformed proxy header {:?}", line)),
+    }
+}
+
+fn decode_v2(buf: &[u8]) -> std::result::Result<Option<Header>, String> {
+    if buf.len() < 16 {
+        return Err("proxy header too short".to_string());
+    }
+
+    let version = buf[12] >> 4;
+    let command = buf[12] & 0x0f;
+    let family = buf[13] >> 4;
+    let length = u16::from_be_bytes([buf[14], buf[15]]) as usize;
+    let body = &buf[16..];
+
+    if version != 2 {
+        return Err(format!("unsupported proxy protocol version {}", version));
+    }
+    if body.len() != length {
+        return Err("proxy header length does not match".to_string());
+    }
+
+    match command {
+        0x0 => return Ok(None),
+        0x1 => (),
+        _ => return Err(format!("unknown proxy command {}", command)),
+    }
+
+    let (mut header, tlvs) = match family {
+        0x1 if body.len() >= 12 => {
+            let ip = |b: &[u8]| IpAddr::V4(Ipv4Addr::new(b[0], b[1], b[2], b[3]));
+            let header = Header::new(
+                SocketAddr::new(ip(&body[0..4]), port(&body[8..10])),
+                SocketAddr::new(ip(&body[4..8]), port(&body[10..12])),
+            );
+            (header, &body[12..])
+        }
+        0x2 if body.len() >= 36 => {
+            let ip = |b: &[u8]| {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(b);
+                IpAddr::V6(Ipv6Addr::from(octets))
+            };
+            let header = Header::new(
+                SocketAddr::new(ip(&body[0..16]), port(&body[32..34])),
+                SocketAddr::new(ip(&body[16..32]), port(&body[34..36])),
+            );
+            (header, &body[36..])
+        }
+        0x1 | 0x2 => return Err("proxy header addresses too short".to_string()),
+        // unspecified or unix sockets, nothing useful to report
+        _ => return Ok(None),
+    };
+
+    for (kind, value) in split_tlvs(tlvs)? {
+        match kind {
+            PP2_TYPE_ALPN => header.alpn = Some(value.to_vec()),
+            PP2_TYPE_AUTHORITY => header.authority = Some(text(value)?),
+            PP2_TYPE_SSL if value.len() >= 5 => {
+                let mut tls = Tls {
+                    client_certificate: value[0] & PP2_CLIENT_CERT_CONN != 0,
+                    ..Tls::default()
+                };
+                for (kind, value) in split_tlvs(&value[5..])? {
+                    match kind {
+                        PP2_SUBTYPE_SSL_VERSION => tls.version = Some(text(value)?),
+                        PP2_SUBTYPE_SSL_CN => tls.common_name = Some(text(value)?),
+                        PP2_SUBTYPE_SSL_CIPHER => tls.cipher = Some(text(value)?),
+                        _ => (),
+                    }
+                }
+                header.tls = Some(tls);
+            }
+            PP2_TYPE_SSL => return Err("proxy header ssl value too short".to_string()),
+            // other types are allowed, and ignored
+            _ => (),
+        }
+    }
+
+    Ok(Some(header))
+}
+
+fn split_tlvs(mut buf: &[u8]) -> std::result::Result<Vec<(u8, &[u8])>, String> {
+    let mut tlvs = Vec::new();
+    while !buf.is_empty() {
+        if buf.len() < 3 {
+            return Err("truncated proxy header tlv".to_string());
+        }
+        let length = u16::from_be_bytes([buf[1], buf[2]]) as usize;
+        if buf.len() < 3 + length {
+            return Err("truncated proxy header tlv".to_string());
+        }
+        tlvs.push((buf[0], &buf[3..3 + length]));
+        buf = &buf[3 + length..];
+    }
+    Ok(tlvs)
+}
+
+fn port(b: &[u8]) -> u16 {
+    u16::from_be_bytes([b[0], b[1]])
+}
+
+fn text(b: &[u8]) -> std::result::Result<String, String> {
+    String::from_utf8(b.to_vec()).map_err(|_| "invalid text in proxy header tlv".to_string())
+}
+
+fn invalid(msg: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(source: &str, destination: &str) -> Header {
+        Header::new(source.parse().unwrap(), destination.parse().unwrap())
+    }
+
+    #[test]
+    fn decodes_v1() {
+        assert_eq!(
+            Ok(Some(header("192.168.0.1:56324", "10.0.0.2:443"))),
+            decode(b"PROXY TCP4 192.168.0.1 10.0.0.2 56324 443\r\n")
+        );
+        assert_eq!(
+            Ok(Some(header("[::1]:56324", "[2001:db8::1]:443"))),
+            decode(b"PROXY TCP6 ::1 2001:db8::1 56324 443\r\n")
+        );
+        assert_eq!(Ok(None), decode(b"PROXY UNKNOWN\r\n"));
+        assert_eq!(
+            Ok(None),
+            decode(b"PROXY UNKNOWN ffff:f...f:ffff ffff:f...f:ffff 65535 65535\r\n")
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_v1() {
+        decode(b"PROXY TCP4 192.168.0.1 10.0.0.2 56324\r\n").unwrap_err();
+        decode(b"PROXY TCP4 192.168.0.1 10.0.0.2 56324 443").unwrap_err();
+        decode(b"PROXY TCP6 192.168.0.1 10.0.0.2 56324 443\r\n").unwrap_err();
+        decode(b"PROXY TCP4 192.168.0.1 10.0.0.2 99999 443\r\n").unwrap_err();
+        decode(b"PROXY UDP4 192.168.0.1 10.0.0.2 56324 443\r\n").unwrap_err();
+        decode(b"\x16\x03\x01").unwrap_err();
+    }
+
+    #[test]
+    fn decodes_what_is_encoded() {
+        let mut h = header("[2001:db8::2]:1234", "[::1]:443");
+        h.authority = Some("echo".to_string());
+        h.alpn = Some(b"h2".to_vec());
+        h.tls = Some(Tls {
+            version: Some("TLSv1.3".to_string()),
+            cipher: Some("TLS13_AES_256_GCM_SHA384".to_string()),
+            client_certificate: true,
+            common_name: Some("me".to_string()),
+        });
+
+        assert_eq!(Ok(Some(h.clone())), decode(&h.encode(Version::V2)));
+
+        let h = header("127.0.0.1:1", "127.0.0.1:2");
+        assert_eq!(Ok(Some(h.clone())), decode(&h.encode(Version::V2)));
+        assert_eq!(Ok(Some(h.clone())), decode(&h.encode(Version::V1)));
+    }
+
+    #[test]
+    fn decodes_v2_local() {
+        let mut buf = V2_SIGNATURE.to_vec();
+        buf.extend_from_slice(&[0x20, 0x00, 0, 0]);
+        assert_eq!(Ok(None), decode(&buf));
+    }
+
+    #[test]
+    fn rejects_malformed_v2() {
+        let good = header("127.0.0.1:1", "127.0.0.1:2").encode(Version::V2);
+
+        let mut bad_version = good.clone();
+        bad_version[12] = 0x11;
+        decode(&bad_version).unwrap_err();
+
+        let mut bad_length = good.clone();
+        bad_length[15] += 1;
+        decode(&bad_length).unwrap_err();
+
+        let mut bad_tlv = good.clone();
+        bad_tlv[15] += 2;
+        bad_tlv.extend_from_slice(&[PP2_TYPE_AUTHORITY, 0]);
+        decode(&bad_tlv).unwrap_err();
+    }
+
+    #[test]
+    fn recognizes_start_of_header() {
+        assert!(starts_header(b"P"));
+        assert!(starts_header(b"PROXY TCP4"));
+        assert!(starts_header(b"\r\n\r\n"));
+        assert!(!starts_header(b""));
+        assert!(!starts_header(b"\x16\x03\x01"));
+        assert!(!starts_header(b"PROXI"));
+    }
+
+    #[tokio::test]
+    async fn reads_only_the_header() {
+        let h = header("127.0.0.1:1", "127.0.0.1:2");
+        for version in [Version::V1, Version::V2] {
+            let mut buf = h.encode(version);
+            buf.extend_from_slice(b"rest");
+            let mut reader = buf.as_slice();
+
+            assert_eq!(Some(h.clone()), read(&mut reader).await.unwrap());
+            assert_eq!(b"rest", reader);
+        }
+    }
+
+    #[tokio::test]
+    async fn fails_early_on_anything_else() {
+        let mut reader: &[u8] = b"\x16\x03\x01\x02\x00";
+        read(&mut reader).await.unwrap_err();
+        assert_eq!(b"\x03\x01\x02\x00", reader);
+    }
+}
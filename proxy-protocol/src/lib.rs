@@ -0,0 +1,264 @@
+// The HAProxy PROXY protocol, see https://www.haproxy.org/download/2.0/doc/proxy-protocol.txt
+
+mod decode;
+
+use serde::Deserialize;
+use std::net::{IpAddr, SocketAddr};
+
+pub use decode::{decode, read, starts_header};
+
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+
+const PP2_TYPE_ALPN: u8 = 0x01;
+const PP2_TYPE_AUTHORITY: u8 = 0x02;
+const PP2_TYPE_SSL: u8 = 0x20;
+const PP2_SUBTYPE_SSL_VERSION: u8 = 0x21;
+const PP2_SUBTYPE_SSL_CN: u8 = 0x22;
+const PP2_SUBTYPE_SSL_CIPHER: u8 = 0x23;
+
+const PP2_CLIENT_SSL: u8 = 0x01;
+const PP2_CLIENT_CERT_CONN: u8 = 0x02;
+const PP2_CLIENT_CERT_SESS: u8 = 0x04;
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Version {
+    V1,
+    V2,
+}
+
+impl std::str::FromStr for Version {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Version, String> {
+        match s {
+            "v1" => Ok(Version::V1),
+            "v2" => Ok(Version::V2),
+            _ => Err(format!("unknown proxy protocol version {}", s)),
+        }
+    }
+}
+
+// What is known about the TLS session of the original connection, only sent with v2.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Tls {
+    pub version: Option<String>,
+    pub cipher: Option<String>,
+    pub client_certificate: bool,
+    pub common_name: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Header {
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+    pub authority: Option<String>,
+    pub alpn: Option<Vec<u8>>,
+    pub tls: Option<Tls>,
+}
+
+impl Header {
+    pub fn new(source: SocketAddr, destination: SocketAddr) -> Header {
+        Header {
+            source,
+            destination,
+            authority: None,
+            alpn: None,
+            tls: None,
+        }
+    }
+
+    pub fn encode(&self, version: Version) -> Vec<u8> {
+        match version {
+            Version::V1 => self.encode_v1(),
+            Version::V2 => self.encode_v2(),
+        }
+    }
+
+    fn encode_v1(&self) -> Vec<u8> {
+        let family = match (self.source.ip(), self.destination.ip()) {
+            (IpAddr::V4(_), IpAddr::V4(_)) => "TCP4",
+            (IpAddr::V6(_), IpAddr::V6(_)) => "TCP6",
+            _ => return b"PROXY UNKNOWN\r\n".to_vec(),
+        };
+
+        format!(
+            "PROXY {} {} {} {} {}\r\n",
+            family,
+            self.source.ip(),
+            self.destination.ip(),
+            self.source.port(),
+            self.destination.port()
+        )
+        .into_bytes()
+    }
+
+    fn encode_v2(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+
+        // version 2, PROXY command
+        let family = match (self.source.ip(), self.destination.ip()) {
+            (IpAddr::V4(source), IpAddr::V4(destination)) => {
+                body.extend_from_slice(&source.octets());
+                body.extend_from_slice(&destination.octets());
+                0x11
+            }
+            (IpAddr::V6(source), IpAddr::V6(destination)) => {
+                body.extend_from_slice(&source.octets());
+                body.extend_from_slice(&destination.octets());
+                0x21
+            }
+            (source, destination) => {
+                // mixed families, map both to v6
+                body.extend_from_slice(&to_v6(source).octets());
+                body.extend_from_slice(&to_v6(destination).octets());
+                0x21
+            }
+        };
+        body.extend_from_slice(&self.source.port().to_be_bytes());
+        body.extend_from_slice(&self.destination.port().to_be_bytes());
+
+        if let Some(alpn) = &self.alpn {
+            push_tlv(&mut body, PP2_TYPE_ALPN, alpn);
+        }
+
+        if let Some(authority) = &self.authority {
+            push_tlv(&mut body, PP2_TYPE_AUTHORITY, authority.as_bytes());
+        }
+
+        if let Some(tls) = &self.tls {
+            let mut client = PP2_CLIENT_SSL;
+            if tls.client_certificate {
+                client |= PP2_CLIENT_CERT_CONN | PP2_CLIENT_CERT_SESS;
+            }
+
+            let mut value = vec![client];
+            // verify, 0 means the client certificate (if any) was verified
+            value.extend_from_slice(&0u32.to_be_bytes());
+
+            if let Some(version) = &tls.version {
+                push_tlv(&mut value, PP2_SUBTYPE_SSL_VERSION, version.as_bytes());
+            }
+            if let Some(common_name) = &tls.common_name {
+                push_tlv(&mut value, PP2_SUBTYPE_SSL_CN, common_name.as_bytes());
+            }
+            if let Some(cipher) = &tls.cipher {
+                push_tlv(&mut value, PP2_SUBTYPE_SSL_CIPHER, cipher.as_bytes());
+            }
+
+            push_tlv(&mut body, PP2_TYPE_SSL, &value);
+        }
+
+        let mut header = V2_SIGNATURE.to_vec();
+        header.push(0x21);
+        header.push(family);
+        header.extend_from_slice(&(body.len() as u16).to_be_bytes());
+        header.extend(body);
+        header
+    }
+}
+
+fn push_tlv(buf: &mut Vec<u8>, kind: u8, value: &[u8]) {
+    buf.push(kind);
+    buf.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    buf.extend_from_slice(value);
+}
+
+fn to_v6(ip: IpAddr) -> std::net::Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(source: &str, destination: &str) -> Header {
+        Header::new(source.parse().unwrap(), destination.parse().unwrap())
+    }
+
+    #[test]
+    fn v1_tcp4() {
+        let h = header("192.168.0.1:56324", "10.0.0.2:443");
+        assert_eq!(
+            b"PROXY TCP4 192.168.0.1 10.0.0.2 56324 443\r\n".to_vec(),
+            h.encode(Version::V1)
+        );
+    }
+
+    #[test]
+    fn v1_tcp6() {
+        let h = header("[::1]:56324", "[2001:db8::1]:443");
+        assert_eq!(
+            b"PROXY TCP6 ::1 2001:db8::1 56324 443\r\n".to_vec(),
+            h.encode(Version::V1)
+        );
+    }
+
+    #[test]
+    fn v1_mixed() {
+        let h = header("127.0.0.1:56324", "[::1]:443");
+        assert_eq!(b"PROXY UNKNOWN\r\n".to_vec(), h.encode(Version::V1));
+    }
+
+    #[test]
+    fn v2_tcp4() {
+        let h = header("192.168.0.1:56324", "10.0.0.2:443");
+
+        let mut expect = V2_SIGNATURE.to_vec();
+        expect.extend_from_slice(&[0x21, 0x11, 0, 12]);
+        expect.extend_from_slice(&[192, 168, 0, 1, 10, 0, 0, 2]);
+        expect.extend_from_slice(&[0xdc, 0x04, 0x01, 0xbb]);
+
+        assert_eq!(expect, h.encode(Version::V2));
+    }
+
+    #[test]
+    fn v2_tcp6() {
+        let h = header("[::1]:1", "[::2]:2");
+        let encoded = h.encode(Version::V2);
+
+        assert_eq!(&[0x21, 0x21, 0, 36], &encoded[12..16]);
+        assert_eq!(16 + 36, encoded.len());
+        assert_eq!(1, encoded[16 + 15]);
+        assert_eq!(2, encoded[16 + 31]);
+    }
+
+    #[test]
+    fn v2_tls() {
+        let mut h = header("127.0.0.1:1", "127.0.0.1:2");
+        h.authority = Some("echo".to_string());
+        h.tls = Some(Tls {
+            version: Some("TLSv1.3".to_string()),
+            cipher: None,
+            client_certificate: true,
+            common_name: Some("me".to_string()),
+        });
+
+        let encoded = h.encode(Version::V2);
+        let tlvs = &encoded[16 + 12..];
+
+        let mut expect = vec![PP2_TYPE_AUTHORITY, 0, 4];
+        expect.extend_from_slice(b"echo");
+        expect.extend_from_slice(&[PP2_TYPE_SSL, 0, 5 + 10 + 5]);
+        expect.extend_from_slice(&[0x07, 0, 0, 0, 0]);
+        expect.extend_from_slice(&[PP2_SUBTYPE_SSL_VERSION, 0, 7]);
+        expect.extend_from_slice(b"TLSv1.3");
+        expect.extend_from_slice(&[PP2_SUBTYPE_SSL_CN, 0, 2]);
+        expect.extend_from_slice(b"me");
+
+        assert_eq!(expect, tlvs);
+        assert_eq!(
+            (12 + expect.len()) as u16,
+            u16::from_be_bytes([encoded[14], encoded[15]])
+        );
+    }
+
+    #[test]
+    fn parse_version() {
+        assert_eq!(Ok(Version::V2), "v2".parse());
+        assert!("v3".parse::<Version>().is_err());
+    }
+}
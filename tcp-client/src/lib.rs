@@ -17,7 +17,7 @@ pub struct Config<'a> {
 }
 
 impl Config<'_> {
-    pub fn new(address: &str) -> Config {
+    pub fn new(address: &str) -> Config<'_> {
         Config {
             address,
             threaded: false,
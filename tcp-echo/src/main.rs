@@ -28,7 +28,7 @@ fn main() -> Result<()> {
         )
         .arg(
             clap::Arg::with_name("port")
-                .help("port to bind to")
+                .help("port to bind to, 0 picks a free one")
                 .short("p")
                 .long("port")
                 .default_value("1729"),
@@ -41,7 +41,11 @@ fn main() -> Result<()> {
         log::Level::Info
     };
 
-    simple_logger::init_with_level(level)?;
+    // the server runs on its own thread, where local timestamps can not be determined safely
+    simple_logger::SimpleLogger::new()
+        .with_level(level.to_level_filter())
+        .with_utc_timestamps()
+        .init()?;
 
     log::debug!("arguments are config file is {:?}", args);
 
@@ -49,12 +53,15 @@ fn main() -> Result<()> {
     let config = tcp_server::Config::new(port)
         .with_public(args.is_present("public"))
         .with_threading(args.is_present("threads"));
-    let mut server = tcp_server::Server::new(config)?;
+    let server = tcp_server::Server::new(config)?;
 
-    server.run(handle)
+    let running = server.spawn(handle)?;
+    // tell whoever started us where to connect, the port may have been picked by the system
+    println!("LISTEN {}", running.address());
+    running.join()
 }
 
-async fn handle(mut stream: tcp_server::Stream) {
+async fn handle(mut stream: tcp_server::Stream, _context: tcp_server::ConnectionContext) {
     let (rx, tx) = stream.split();
     if let Err(e) = io_copy::copy(rx, tx).await {
         log::error!("copy error: {}", e);
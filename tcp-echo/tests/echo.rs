@@ -14,7 +14,7 @@ impl Echo {
             .run()
             .expect("cargo run")
             .command()
-            .args(&["--port", port.as_str()])
+            .args(["--port", port.as_str()])
             .stdout(Stdio::null())
             .stderr(Stdio::null())
             .spawn()
@@ -22,6 +22,13 @@ impl Echo {
 
         Echo { proc }
     }
+
+    fn interrupt(&self) {
+        std::process::Command::new("kill")
+            .args(["-INT", self.proc.id().to_string().as_str()])
+            .status()
+            .expect("kill");
+    }
 }
 
 impl Drop for Echo {
@@ -81,3 +88,20 @@ fn interleaved() {
     assert_eq!("baz".to_string(), client1.communicate("baz"));
     assert_eq!("baz".to_string(), client2.communicate("baz"));
 }
+
+#[test]
+fn drains_on_interrupt() {
+    let mut e = Echo::new(3459);
+    let mut client = Client::new(3459);
+    assert_eq!("foo".to_string(), client.communicate("foo"));
+
+    e.interrupt();
+    std::thread::sleep(std::time::Duration::from_millis(100));
+
+    assert_eq!("bar".to_string(), client.communicate("bar"));
+    std::net::TcpStream::connect("127.0.0.1:3459").expect_err("no longer accepting");
+
+    drop(client);
+    let status = e.proc.wait().expect("exit");
+    assert!(status.success());
+}
@@ -33,7 +33,7 @@ fn main() -> Result<()> {
         )
         .arg(
             clap::Arg::with_name("port")
-                .help("port to bind to")
+                .help("port to bind to, 0 picks a free one")
                 .short("p")
                 .long("port")
                 .default_value("1730"),
@@ -62,7 +62,11 @@ fn main() -> Result<()> {
         log::Level::Info
     };
 
-    simple_logger::init_with_level(level)?;
+    // the server runs on its own thread, where local timestamps can not be determined safely
+    simple_logger::SimpleLogger::new()
+        .with_level(level.to_level_filter())
+        .with_utc_timestamps()
+        .init()?;
 
     log::debug!("arguments are {:?}", args);
 
@@ -74,13 +78,15 @@ fn main() -> Result<()> {
     let config = tcp_server::Config::new(port)
         .with_public(args.is_present("public"))
         .with_threading(args.is_present("threads"));
-    let mut server = tcp_server::Server::new(config)?;
+    let server = tcp_server::Server::new(config)?;
 
-    server.run(move |stream| async move {
+    let running = server.spawn(move |stream, _context| async move {
         if let Err(e) = handle(stream, n, delay).await {
             log::error!("handle error: {}", e);
         }
-    })
+    })?;
+    println!("LISTEN {}", running.address());
+    running.join()
 }
 
 async fn handle(mut stream: Stream, n: u32, delay: Duration) -> std::io::Result<()> {
@@ -15,7 +15,7 @@ impl Fib {
             .run()
             .expect("cargo run")
             .command()
-            .args(&[
+            .args([
                 "--port",
                 port.as_str(),
                 "-n",
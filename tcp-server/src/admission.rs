@@ -0,0 +1,477 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::sync::Notify;
+
+use super::bans::{BanPolicy, Bans};
+use super::metrics::{Counter, Gauge, Metrics};
+use super::ratelimit::{Buckets, RateLimit};
+
+// How many connections a listener takes at once, in total and from a single source address, how
+// fast sources can connect, and when to ban them.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct ConnectionLimits {
+    pub max_connections: Option<usize>,
+    pub max_per_source: Option<usize>,
+    pub over_limit: OverLimit,
+    pub rate_limit: Option<RateLimit>,
+    pub bans: Option<BanPolicy>,
+}
+
+// What to do with new connections once the total limit is reached. A source over its own limit
+// always has its connections closed, pausing for it would keep everyone else out too.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum OverLimit {
+    // stop accepting until a connection finishes, new ones wait in the listen backlog
+    #[default]
+    Pause,
+    // accept and close right away
+    Close,
+}
+
+// Why a connection was not let in.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Refusal {
+    MaxConnections,
+    MaxPerSource,
+    RateLimited,
+    Banned,
+}
+
+impl Refusal {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Refusal::MaxConnections => "max_connections",
+            Refusal::MaxPerSource => "max_per_source",
+            Refusal::RateLimited => "rate_limited",
+            Refusal::Banned => "banned",
+        }
+    }
+}
+
+impl std::fmt::Display for Refusal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Refusal::MaxConnections => write!(f, "too many connections"),
+            Refusal::MaxPerSource => write!(f, "too many connections from this source"),
+            Refusal::RateLimited => write!(f, "connecting too often"),
+            Refusal::Banned => write!(f, "banned"),
+        }
+    }
+}
+
+// Lets connections in within the limits, and counts them by source address for as long as their
+// ticket lives.
+#[derive(Clone)]
+pub struct Admission {
+    limits: ConnectionLimits,
+    inner: Arc<Inner>,
+    sources: Gauge,
+    refused: [Counter; 4],
+    bans: Counter,
+    banned: Gauge,
+}
+
+struct Inner {
+    counts: Mutex<Counts>,
+    released: Notify,
+}
+
+#[derive(Default)]
+struct Counts {
+    total: usize,
+    by_source: HashMap<IpAddr, usize>,
+    buckets: Option<Buckets>,
+    bans: Option<Bans>,
+}
+
+// Counts as an admitted connection until dropped, and towards its source once that is admitted.
+pub struct Ticket {
+    admission: Admission,
+    source: Option<IpAddr>,
+}
+
+impl Admission {
+    pub fn new(limits: ConnectionLimits, metrics: &Metrics, listener: &str) -> Admission {
+        let refused = |reason: Refusal| {
+            metrics.counter(
+                "connections_refused_total",
+                "Connections closed right away for being over a limit, by limit.",
+                &[("listener", listener), ("reason", reason.as_str())],
+            )
+        };
+        Admission {
+            limits,
+            inner: Arc::new(Inner {
+                counts: Mutex::new(Counts {
+                    buckets: limits.rate_limit.map(Buckets::new),
+                    bans: limits.bans.map(Bans::new),
+                    ..Default::default()
+                }),
+                released: Notify::new(),
+            }),
+            sources: metrics.gauge(
+                "connection_sources",
+                "Distinct source addresses with connections being handled.",
+                &[("listener", listener)],
+            ),
+            refused: [
+                refused(Refusal::MaxConnections),
+                refused(Refusal::MaxPerSource),
+                refused(Refusal::RateLimited),
+                refused(Refusal::Banned),
+            ],
+            bans: metrics.counter(
+                "source_bans_total",
+                "Source addresses banned for failing too often.",
+                &[("listener", listener)],
+            ),
+            banned: metrics.gauge(
+                "sources_banned",
+                "Source addresses banned at the moment.",
+                &[("listener", listener)],
+            ),
+        }
+    }
+
+    // Resolves once a new connection can be accepted, which is right away unless pausing at the
+    // total limit.
+    pub async fn ready(&self) {
+        let max = match (self.limits.over_limit, self.limits.max_connections) {
+            (OverLimit::Pause, Some(max)) => max,
+            _ => return,
+        };
+
+        let mut paused = false;
+        while self.active() >= max {
+            if !paused {
+                log::warn!("reached {} connections, pausing accepting", max);
+                paused = true;
+            }
+            self.inner.released.notified().await;
+        }
+        if paused {
+            log::info!("below {} connections, accepting again", max);
+        }
+    }
+
+    // Lets a connection from this source in, if within the limits.
+    pub fn admit(&self, source: IpAddr) -> std::result::Result<Ticket, Refusal> {
+        let mut ticket = self.reserve()?;
+        ticket.admit_from(source)?;
+        Ok(ticket)
+    }
+
+    // Lets a connection in within the total limit before knowing its source, as when a proxy in
+    // front is yet to tell it. The source still has to be admitted through the ticket.
+    pub fn reserve(&self) -> std::result::Result<Ticket, Refusal> {
+        let mut counts = self.inner.counts.lock().unwrap();
+        if self
+            .limits
+            .max_connections
+            .is_some_and(|max| counts.total >= max)
+        {
+            return Err(self.refuse(Refusal::MaxConnections));
+        }
+
+        counts.total += 1;
+        Ok(Ticket {
+            admission: self.clone(),
+            source: None,
+        })
+    }
+
+    fn admit_source(&self, source: IpAddr) -> std::result::Result<(), Refusal> {
+        let mut counts = self.inner.counts.lock().unwrap();
+        let now = Instant::now();
+
+        let banned = match counts.bans.as_mut() {
+            Some(bans) => {
+                let banned = bans.is_banned(source, now);
+                self.banned.set(bans.banned(now) as i64);
+                banned
+            }
+            None => false,
+        };
+        if banned {
+            return Err(self.refuse(Refusal::Banned));
+        }
+        if counts
+            .buckets
+            .as_mut()
+            .is_some_and(|buckets| !buckets.take(source, now))
+        {
+            return Err(self.refuse(Refusal::RateLimited));
+        }
+        if self
+            .limits
+            .max_per_source
+            .is_some_and(|max| counts.by_source.get(&source).cloned().unwrap_or(0) >= max)
+        {
+            return Err(self.refuse(Refusal::MaxPerSource));
+        }
+
+        let from_source = counts.by_source.entry(source).or_insert(0);
+        *from_source += 1;
+        if *from_source == 1 {
+            self.sources.inc();
+        }
+        Ok(())
+    }
+
+    fn refuse(&self, refusal: Refusal) -> Refusal {
+        self.refused[refusal as usize].inc();
+        refusal
+    }
+
+    // Counts a failure against the source, which may get it banned.
+    pub fn failed(&self, source: IpAddr) {
+        let mut counts = self.inner.counts.lock().unwrap();
+        if let Some(bans) = counts.bans.as_mut() {
+            let now = Instant::now();
+            if bans.fail(source, now) {
+                self.bans.inc();
+            }
+            self.banned.set(bans.banned(now) as i64);
+        }
+    }
+
+    // The number of admitted connections.
+    pub fn active(&self) -> usize {
+        self.inner.counts.lock().unwrap().total
+    }
+
+    // The number of admitted connections from this source.
+    pub fn active_from(&self, source: IpAddr) -> usize {
+        let counts = self.inner.counts.lock().unwrap();
+        counts.by_source.get(&source).cloned().unwrap_or(0)
+    }
+}
+
+impl Ticket {
+    // Lets the connection in from this source, if the source is within its limits. Only for
+    // reserved tickets, admitted ones have their source already.
+    pub fn admit_from(&mut self, source: IpAddr) -> std::result::Result<(), Refusal> {
+        if self.source.is_none() {
+            self.admission.admit_source(source)?;
+            self.source = Some(source);
+        }
+        Ok(())
+    }
+
+    // Counts a failure against the source of the connection, once admitted.
+    pub fn failed(&self) {
+        if let Some(source) = self.source {
+            self.admission.failed(source);
+        }
+    }
+}
+
+impl Drop for Ticket {
+    fn drop(&mut self) {
+        let mut counts = self.admission.inner.counts.lock().unwrap();
+        counts.total -= 1;
+        if let Some(source) = self.source {
+            if let Some(n) = counts.by_source.get_mut(&source) {
+                *n -= 1;
+                if *n == 0 {
+                    counts.by_source.remove(&source);
+                    self.admission.sources.dec();
+                }
+            }
+        }
+        drop(counts);
+        self.admission.inner.released.notify();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn admission(limits: ConnectionLimits) -> Admission {
+        Admission::new(limits, &Metrics::default(), "5000")
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn admits_without_limits() {
+        let admission = admission(ConnectionLimits::default());
+        let tickets: Vec<Ticket> = (0..100)
+            .map(|_| admission.admit(ip("10.0.0.1")).unwrap())
+            .collect();
+
+        assert_eq!(100, admission.active());
+        drop(tickets);
+        assert_eq!(0, admission.active());
+    }
+
+    #[test]
+    fn refuses_over_the_total_limit() {
+        let admission = admission(ConnectionLimits {
+            max_connections: Some(2),
+            ..Default::default()
+        });
+
+        let first = admission.admit(ip("10.0.0.1")).unwrap();
+        let _second = admission.admit(ip("10.0.0.2")).unwrap();
+        assert_eq!(
+            Some(Refusal::MaxConnections),
+            admission.admit(ip("10.0.0.3")).err()
+        );
+
+        drop(first);
+        assert!(admission.admit(ip("10.0.0.3")).is_ok());
+    }
+
+    #[test]
+    fn refuses_over_the_source_limit() {
+        let admission = admission(ConnectionLimits {
+            max_per_source: Some(1),
+            ..Default::default()
+        });
+
+        let _first = admission.admit(ip("10.0.0.1")).unwrap();
+        assert_eq!(
+            Some(Refusal::MaxPerSource),
+            admission.admit(ip("10.0.0.1")).err()
+        );
+        assert!(admission.admit(ip("10.0.0.2")).is_ok());
+        assert_eq!(1, admission.active_from(ip("10.0.0.1")));
+    }
+
+    #[test]
+    fn counts_sources_and_refusals() {
+        let metrics = Metrics::default();
+        let limits = ConnectionLimits {
+            max_per_source: Some(1),
+            ..Default::default()
+        };
+        let admission = Admission::new(limits, &metrics, "5000");
+
+        let first = admission.admit(ip("10.0.0.1")).unwrap();
+        let _second = admission.admit(ip("10.0.0.2")).unwrap();
+        let _ = admission.admit(ip("10.0.0.1"));
+
+        let sources = metrics.gauge("connection_sources", "", &[("listener", "5000")]);
+        assert_eq!(2, sources.get());
+        drop(first);
+        assert_eq!(1, sources.get());
+        assert_eq!(
+            1,
+            metrics
+                .counter(
+                    "connections_refused_total",
+                    "",
+                    &[("listener", "5000"), ("reason", "max_per_source")]
+                )
+                .get()
+        );
+    }
+
+    #[test]
+    fn refuses_sources_connecting_too_often() {
+        let admission = admission(ConnectionLimits {
+            rate_limit: Some(RateLimit::new(0.001, 2.0)),
+            ..Default::default()
+        });
+
+        let _first = admission.admit(ip("10.0.0.1")).unwrap();
+        let _second = admission.admit(ip("10.0.0.1")).unwrap();
+        assert_eq!(
+            Some(Refusal::RateLimited),
+            admission.admit(ip("10.0.0.1")).err()
+        );
+        assert!(admission.admit(ip("10.0.0.2")).is_ok());
+    }
+
+    #[test]
+    fn bans_sources_failing_too_often() {
+        let metrics = Metrics::default();
+        let limits = ConnectionLimits {
+            bans: Some(BanPolicy {
+                max_failures: 2,
+                window: Duration::from_secs(60),
+                duration: Duration::from_secs(600),
+            }),
+            ..Default::default()
+        };
+        let admission = Admission::new(limits, &metrics, "5000");
+
+        for _ in 0..2 {
+            admission.admit(ip("10.0.0.1")).unwrap().failed();
+        }
+        assert_eq!(Some(Refusal::Banned), admission.admit(ip("10.0.0.1")).err());
+        assert!(admission.admit(ip("10.0.0.2")).is_ok());
+
+        let labels = [("listener", "5000")];
+        assert_eq!(1, metrics.counter("source_bans_total", "", &labels).get());
+        assert_eq!(1, metrics.gauge("sources_banned", "", &labels).get());
+    }
+
+    #[test]
+    fn admits_reserved_tickets_by_source() {
+        let admission = admission(ConnectionLimits {
+            max_connections: Some(2),
+            max_per_source: Some(1),
+            ..Default::default()
+        });
+
+        let mut first = admission.reserve().unwrap();
+        let mut second = admission.reserve().unwrap();
+        assert_eq!(Some(Refusal::MaxConnections), admission.reserve().err());
+        assert_eq!(0, admission.active_from(ip("10.0.0.1")));
+
+        first.admit_from(ip("10.0.0.1")).unwrap();
+        assert_eq!(1, admission.active_from(ip("10.0.0.1")));
+        assert_eq!(
+            Some(Refusal::MaxPerSource),
+            second.admit_from(ip("10.0.0.1")).err()
+        );
+        drop(second);
+
+        assert_eq!(1, admission.active());
+        drop(first);
+        assert_eq!(0, admission.active_from(ip("10.0.0.1")));
+    }
+
+    #[tokio::test]
+    async fn pauses_at_the_total_limit() {
+        let admission = admission(ConnectionLimits {
+            max_connections: Some(1),
+            over_limit: OverLimit::Pause,
+            ..Default::default()
+        });
+
+        let ticket = admission.admit(ip("10.0.0.1")).unwrap();
+        let paused = tokio::time::timeout(Duration::from_millis(10), admission.ready()).await;
+        assert!(paused.is_err());
+
+        tokio::spawn(async move {
+            tokio::time::delay_for(Duration::from_millis(10)).await;
+            drop(ticket);
+        });
+        tokio::time::timeout(Duration::from_secs(10), admission.ready())
+            .await
+            .expect("ready once the connection is gone");
+    }
+
+    #[tokio::test]
+    async fn does_not_pause_when_closing() {
+        let admission = admission(ConnectionLimits {
+            max_connections: Some(1),
+            over_limit: OverLimit::Close,
+            ..Default::default()
+        });
+
+        let _ticket = admission.admit(ip("10.0.0.1")).unwrap();
+        tokio::time::timeout(Duration::from_secs(10), admission.ready())
+            .await
+            .expect("ready right away");
+    }
+}
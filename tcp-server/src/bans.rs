@@ -0,0 +1,156 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+// Ban a source address for a while once its connections failed too often within a window.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BanPolicy {
+    pub max_failures: u32,
+    pub window: Duration,
+    pub duration: Duration,
+}
+
+pub(crate) struct Bans {
+    policy: BanPolicy,
+    failures: HashMap<IpAddr, VecDeque<Instant>>,
+    banned: HashMap<IpAddr, Instant>,
+    pruned: Instant,
+}
+
+impl Bans {
+    pub fn new(policy: BanPolicy) -> Bans {
+        Bans {
+            policy,
+            failures: HashMap::new(),
+            banned: HashMap::new(),
+            pruned: Instant::now(),
+        }
+    }
+
+    pub fn is_banned(&mut self, address: IpAddr, now: Instant) -> bool {
+        match self.banned.get(&address) {
+            Some(until) if *until > now => true,
+            Some(_) => {
+                log::info!("lifting the ban on {}", address);
+                self.banned.remove(&address);
+                false
+            }
+            None => false,
+        }
+    }
+
+    // Counts a failure against the address, tells whether that got it banned.
+    pub fn fail(&mut self, address: IpAddr, now: Instant) -> bool {
+        self.prune(now);
+        if self.is_banned(address, now) {
+            return false;
+        }
+
+        let window = self.policy.window;
+        let failures = self.failures.entry(address).or_default();
+        failures.push_back(now);
+        while let Some(first) = failures.front() {
+            if now.saturating_duration_since(*first) <= window {
+                break;
+            }
+            failures.pop_front();
+        }
+
+        if failures.len() < self.policy.max_failures as usize {
+            return false;
+        }
+
+        log::warn!(
+            "banning {} for {:?}, it failed {} times within {:?}",
+            address,
+            self.policy.duration,
+            failures.len(),
+            window
+        );
+        self.failures.remove(&address);
+        self.banned.insert(address, now + self.policy.duration);
+        true
+    }
+
+    // The number of addresses banned at the moment.
+    pub fn banned(&mut self, now: Instant) -> usize {
+        self.banned.retain(|address, until| {
+            if *until > now {
+                return true;
+            }
+            log::info!("lifting the ban on {}", address);
+            false
+        });
+        self.banned.len()
+    }
+
+    // Forget failures that fell out of the window, every now and then.
+    fn prune(&mut self, now: Instant) {
+        if now.saturating_duration_since(self.pruned) < self.policy.window {
+            return;
+        }
+        let window = self.policy.window;
+        self.failures.retain(|_, failures| {
+            failures
+                .back()
+                .is_some_and(|last| now.saturating_duration_since(*last) <= window)
+        });
+        self.pruned = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> BanPolicy {
+        BanPolicy {
+            max_failures: 3,
+            window: Duration::from_secs(60),
+            duration: Duration::from_secs(600),
+        }
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn bans_after_too_many_failures() {
+        let mut bans = Bans::new(policy());
+        let now = Instant::now();
+
+        assert!(!bans.fail(ip("10.0.0.1"), now));
+        assert!(!bans.fail(ip("10.0.0.1"), now + Duration::from_secs(1)));
+        assert!(!bans.is_banned(ip("10.0.0.1"), now));
+        assert!(bans.fail(ip("10.0.0.1"), now + Duration::from_secs(2)));
+
+        assert!(bans.is_banned(ip("10.0.0.1"), now + Duration::from_secs(3)));
+        assert!(!bans.is_banned(ip("10.0.0.2"), now + Duration::from_secs(3)));
+        assert_eq!(1, bans.banned(now + Duration::from_secs(3)));
+    }
+
+    #[test]
+    fn only_counts_failures_within_the_window() {
+        let mut bans = Bans::new(policy());
+        let now = Instant::now();
+
+        bans.fail(ip("10.0.0.1"), now);
+        bans.fail(ip("10.0.0.1"), now + Duration::from_secs(30));
+        assert!(!bans.fail(ip("10.0.0.1"), now + Duration::from_secs(61)));
+        assert!(!bans.is_banned(ip("10.0.0.1"), now + Duration::from_secs(61)));
+    }
+
+    #[test]
+    fn lifts_bans() {
+        let mut bans = Bans::new(policy());
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            bans.fail(ip("10.0.0.1"), now);
+        }
+        assert!(bans.is_banned(ip("10.0.0.1"), now + Duration::from_secs(599)));
+        assert!(!bans.is_banned(ip("10.0.0.1"), now + Duration::from_secs(600)));
+        assert_eq!(0, bans.banned(now + Duration::from_secs(600)));
+    }
+}
@@ -0,0 +1,145 @@
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+
+use super::Shutdown;
+
+// shared by all servers in the process, so ids in the logs never clash between listeners
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+// What a handler knows about the connection it serves, besides the stream itself.
+#[derive(Clone)]
+pub struct ConnectionContext {
+    id: u64,
+    peer_addr: SocketAddr,
+    local_addr: SocketAddr,
+    accepted_at: SystemTime,
+    accepted: Instant,
+    deadline: Option<Instant>,
+    shutdown: Shutdown,
+}
+
+impl ConnectionContext {
+    // A context for a connection accepted just now, with a fresh id.
+    pub fn new(peer_addr: SocketAddr, local_addr: SocketAddr, shutdown: Shutdown) -> Self {
+        ConnectionContext {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            peer_addr,
+            local_addr,
+            accepted_at: SystemTime::now(),
+            accepted: Instant::now(),
+            deadline: None,
+            shutdown,
+        }
+    }
+
+    // Replace the addresses, for connections relayed by a proxy that told the original ones.
+    pub fn with_addresses(mut self, peer_addr: SocketAddr, local_addr: SocketAddr) -> Self {
+        self.peer_addr = peer_addr;
+        self.local_addr = local_addr;
+        self
+    }
+
+    // The connection is cut once it has been open this long.
+    pub fn with_max_lifetime(mut self, lifetime: Duration) -> Self {
+        self.deadline = Some(self.accepted + lifetime);
+        self
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer_addr
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    pub fn accepted_at(&self) -> SystemTime {
+        self.accepted_at
+    }
+
+    // Time since the connection was accepted.
+    pub fn elapsed(&self) -> Duration {
+        self.accepted.elapsed()
+    }
+
+    // When the connection will be cut, if it has a maximum lifetime. Handlers can use it to wrap
+    // up in time.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    // Resolves once the server stops accepting connections, handlers that can wrap up early
+    // should do so.
+    pub fn shutdown(&self) -> Shutdown {
+        self.shutdown.clone()
+    }
+}
+
+impl std::fmt::Debug for ConnectionContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ConnectionContext")
+            .field("id", &self.id)
+            .field("peer_addr", &self.peer_addr)
+            .field("local_addr", &self.local_addr)
+            .field("accepted_at", &self.accepted_at)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn ids_are_unique() {
+        let (_trigger, shutdown) = Shutdown::new();
+        let a = ConnectionContext::new(address(1), address(2), shutdown.clone());
+        let b = ConnectionContext::new(address(1), address(2), shutdown);
+
+        assert_ne!(a.id(), b.id());
+    }
+
+    #[test]
+    fn with_addresses_keeps_the_id() {
+        let (_trigger, shutdown) = Shutdown::new();
+        let context = ConnectionContext::new(address(1), address(2), shutdown);
+        let id = context.id();
+        let context = context.with_addresses(address(3), address(4));
+
+        assert_eq!(id, context.id());
+        assert_eq!(address(3), context.peer_addr());
+        assert_eq!(address(4), context.local_addr());
+    }
+
+    #[test]
+    fn has_a_deadline_with_a_max_lifetime() {
+        let (_trigger, shutdown) = Shutdown::new();
+        let context = ConnectionContext::new(address(1), address(2), shutdown);
+        assert_eq!(None, context.deadline());
+
+        let context = context.with_max_lifetime(Duration::from_secs(60));
+        assert_eq!(
+            Some(context.accepted + Duration::from_secs(60)),
+            context.deadline()
+        );
+    }
+
+    #[test]
+    fn shuts_down_with_the_server() {
+        let (trigger, shutdown) = Shutdown::new();
+        let context = ConnectionContext::new(address(1), address(2), shutdown);
+
+        assert!(!context.shutdown().is_requested());
+        trigger.broadcast(true).unwrap();
+        assert!(context.shutdown().is_requested());
+    }
+}
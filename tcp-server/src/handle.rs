@@ -0,0 +1,89 @@
+use std::net::SocketAddr;
+use std::thread::JoinHandle;
+use tokio::sync::watch;
+
+use super::Result;
+
+// Controls a server running in the background, see Server::spawn. Dropping the handle shuts the
+// server down and waits for it.
+pub struct Handle {
+    address: SocketAddr,
+    trigger: watch::Sender<bool>,
+    thread: Option<JoinHandle<std::result::Result<(), String>>>,
+}
+
+// Resolves once the server is asked to shut down, either through its handle or because the
+// handle is gone.
+#[derive(Clone)]
+pub struct Shutdown {
+    receiver: watch::Receiver<bool>,
+}
+
+impl Handle {
+    // Runs a server on its own thread, the server is expected to stop once the shutdown it is
+    // given resolves.
+    pub fn spawn<F>(address: SocketAddr, run: F) -> Handle
+    where
+        F: FnOnce(Shutdown) -> Result<()> + Send + 'static,
+    {
+        let (trigger, shutdown) = Shutdown::new();
+        let thread = std::thread::spawn(move || run(shutdown).map_err(|e| format!("{}", e)));
+
+        Handle {
+            address,
+            trigger,
+            thread: Some(thread),
+        }
+    }
+
+    // The address the server is bound to.
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    // Stop accepting new connections and drain the active ones, does not wait for it.
+    pub fn shutdown(&self) {
+        let _ = self.trigger.broadcast(true);
+    }
+
+    // Wait for the server to stop, after a shutdown or a signal.
+    pub fn join(mut self) -> Result<()> {
+        self.wait()
+    }
+
+    fn wait(&mut self) -> Result<()> {
+        match self.thread.take().map(|t| t.join()) {
+            Some(Ok(Ok(()))) | None => Ok(()),
+            Some(Ok(Err(e))) => Err(string_error::into_err(e)),
+            Some(Err(_)) => Err(string_error::static_err("server panicked")),
+        }
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        self.shutdown();
+        if let Err(e) = self.wait() {
+            log::error!("server failed: {}", e);
+        }
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> (watch::Sender<bool>, Shutdown) {
+        let (trigger, receiver) = watch::channel(false);
+        (trigger, Shutdown { receiver })
+    }
+
+    pub fn is_requested(&self) -> bool {
+        *self.receiver.borrow()
+    }
+
+    pub async fn wait(&mut self) {
+        while let Some(requested) = self.receiver.recv().await {
+            if requested {
+                return;
+            }
+        }
+    }
+}
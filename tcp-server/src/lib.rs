@@ -3,51 +3,128 @@ extern crate log;
 extern crate string_error;
 extern crate tokio;
 
+mod admission;
+mod bans;
+mod context;
+mod handle;
+mod metrics;
+mod ratelimit;
+mod tracker;
+
 use futures::future::Future;
 use std::marker::{Send, Sync};
 use tokio::net::TcpListener;
 use tokio::signal::unix::{signal, SignalKind};
 
+pub use admission::{Admission, ConnectionLimits, OverLimit, Refusal, Ticket};
+pub use bans::BanPolicy;
+pub use context::ConnectionContext;
+pub use handle::{Handle, Shutdown};
+pub use metrics::{ConnectionMetrics, Counter, Gauge, Histogram, Metrics, OpenConnection};
+pub use ratelimit::RateLimit;
 pub use tokio::net::TcpStream as Stream;
+pub use tracker::{ConnectionGuard, ConnectionTracker};
 
 pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Clone)]
 pub struct Config {
     port: u16,
-    public: bool,
+    address: std::net::IpAddr,
     threaded: bool,
     shutdown_timeout: std::time::Duration,
+    drain_timeout: std::time::Duration,
+    limits: ConnectionLimits,
+    metrics: Metrics,
 }
 
 impl Config {
     pub fn new(port: u16) -> Config {
         Config {
             port,
-            public: false,
+            address: std::net::Ipv4Addr::LOCALHOST.into(),
             threaded: false,
             shutdown_timeout: std::time::Duration::from_secs(1),
+            drain_timeout: std::time::Duration::from_secs(5),
+            limits: ConnectionLimits::default(),
+            metrics: Metrics::default(),
         }
     }
 
     pub fn with_public(&mut self, public: bool) -> Self {
-        self.public = public;
-        *self
+        self.address = if public {
+            std::net::Ipv4Addr::UNSPECIFIED.into()
+        } else {
+            std::net::Ipv4Addr::LOCALHOST.into()
+        };
+        self.clone()
+    }
+
+    // Bind to this address rather than to 127.0.0.1, or to 0.0.0.0 when public.
+    pub fn with_address(&mut self, address: std::net::IpAddr) -> Self {
+        self.address = address;
+        self.clone()
     }
 
     pub fn with_threading(&mut self, threaded: bool) -> Self {
         self.threaded = threaded;
-        *self
+        self.clone()
     }
 
     pub fn with_shutdown_timeout(&mut self, timeout: std::time::Duration) -> Self {
         self.shutdown_timeout = timeout;
-        *self
+        self.clone()
+    }
+
+    // How long to wait for active connections to finish after stopping accepting new ones.
+    pub fn with_drain_timeout(&mut self, timeout: std::time::Duration) -> Self {
+        self.drain_timeout = timeout;
+        self.clone()
+    }
+
+    // Handle at most this many connections at once, what happens to more depends on the over
+    // limit policy.
+    pub fn with_max_connections(&mut self, max: usize) -> Self {
+        self.limits.max_connections = Some(max);
+        self.clone()
+    }
+
+    // Handle at most this many connections at once from a single source address, more are closed
+    // right away.
+    pub fn with_max_connections_per_source(&mut self, max: usize) -> Self {
+        self.limits.max_per_source = Some(max);
+        self.clone()
+    }
+
+    // Pause accepting at the maximum number of connections, the default, or accept and close.
+    pub fn with_over_limit(&mut self, over_limit: OverLimit) -> Self {
+        self.limits.over_limit = over_limit;
+        self.clone()
+    }
+
+    // Close new connections from sources, or prefixes of them, that connect faster than this.
+    pub fn with_rate_limit(&mut self, limit: RateLimit) -> Self {
+        self.limits.rate_limit = Some(limit);
+        self.clone()
+    }
+
+    // Close new connections from sources that failed too often, for a while. What counts as a
+    // failure is up to the handler, through its connection's ticket.
+    pub fn with_bans(&mut self, policy: BanPolicy) -> Self {
+        self.limits.bans = Some(policy);
+        self.clone()
+    }
+
+    // Count connections into these metrics, labeled with the port listened on.
+    pub fn with_metrics(&mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self.clone()
     }
 }
 
 pub struct Server {
     config: Config,
+    connections: ConnectionTracker,
     runtime: Option<tokio::runtime::Runtime>,
 }
 
@@ -69,18 +146,79 @@ impl Server {
 
         Ok(Server {
             config,
+            connections: ConnectionTracker::default(),
             runtime: Some(runtime),
         })
     }
 
+    // Serve until interrupted or terminated by a signal. Every connection gets its own clone
+    // of the handler, wrap state it should share in an Arc.
     pub fn run<F, R>(&mut self, handler: F) -> Result<()>
     where
-        F: Fn(Stream) -> R + Send + Sync + Copy + 'static,
+        F: Fn(Stream, ConnectionContext) -> R + Send + Sync + Clone + 'static,
+        R: Future + Send,
+    {
+        let listener = self.bind()?;
+        let (_trigger, shutdown) = Shutdown::new();
+        self.run_until(listener, handler, shutdown)
+    }
+
+    // Serve on a thread of its own, until shut down through the handle or by a signal.
+    pub fn spawn<F, R>(mut self, handler: F) -> Result<Handle>
+    where
+        F: Fn(Stream, ConnectionContext) -> R + Send + Sync + Clone + 'static,
+        R: Future + Send,
+    {
+        let listener = self.bind()?;
+        let address = listener.local_addr()?;
+        Ok(Handle::spawn(address, move |shutdown| {
+            self.run_until(listener, handler, shutdown)
+        }))
+    }
+
+    fn bind(&self) -> Result<std::net::TcpListener> {
+        let address = std::net::SocketAddr::new(self.config.address, self.config.port);
+        if address.ip().is_loopback() {
+            log::info!(
+                "binding port {} locally to {}",
+                address.port(),
+                address.ip()
+            );
+        } else {
+            log::warn!(
+                "binding port {} publicly to {}",
+                address.port(),
+                address.ip()
+            );
+        }
+
+        let listener = std::net::TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        Ok(listener)
+    }
+
+    fn run_until<F, R>(
+        &mut self,
+        listener: std::net::TcpListener,
+        handler: F,
+        shutdown: Shutdown,
+    ) -> Result<()>
+    where
+        F: Fn(Stream, ConnectionContext) -> R + Send + Sync + Clone + 'static,
         R: Future + Send,
     {
         match self.runtime.take() {
             Some(mut rt) => {
-                let res = rt.block_on(async { self.serve_with_graceful_shutdown(handler).await });
+                // tells the handlers the server stopped accepting, through their context
+                let (stop, stopped) = Shutdown::new();
+                let res = rt.block_on(async {
+                    let res = self
+                        .serve_with_graceful_shutdown(listener, handler, shutdown, stopped)
+                        .await;
+                    let _ = stop.broadcast(true);
+                    self.drain().await;
+                    res
+                });
                 self.wait(rt);
                 res.map_err(|e| e.into())
             }
@@ -96,40 +234,98 @@ impl Server {
         rt.shutdown_timeout(self.config.shutdown_timeout);
     }
 
-    async fn serve_with_graceful_shutdown<F, R>(&self, handler: F) -> std::io::Result<()>
+    async fn drain(&self) {
+        let active = self.connections.active();
+        if active == 0 {
+            return;
+        }
+
+        log::info!(
+            "waiting up to {:?} for {} active connections to finish",
+            self.config.drain_timeout,
+            active
+        );
+
+        // a second signal cuts the wait short
+        let cut = tokio::select! {
+            n = self.connections.drain(self.config.drain_timeout) => n,
+            _ = self.wait_for_signal(SignalKind::interrupt()) => self.connections.active(),
+            _ = self.wait_for_signal(SignalKind::terminate()) => self.connections.active(),
+        };
+
+        if cut > 0 {
+            log::warn!("cutting {} connections that did not finish", cut);
+        } else {
+            log::info!("all connections finished");
+        }
+    }
+
+    async fn serve_with_graceful_shutdown<F, R>(
+        &self,
+        listener: std::net::TcpListener,
+        handler: F,
+        mut shutdown: Shutdown,
+        stopped: Shutdown,
+    ) -> std::io::Result<()>
     where
-        F: Fn(Stream) -> R + Send + Sync + Copy + 'static,
+        F: Fn(Stream, ConnectionContext) -> R + Send + Sync + Clone + 'static,
         R: Future + Send,
     {
         tokio::select! {
-            x = self.serve(handler) => x,
+            x = self.serve(listener, handler, stopped) => x,
+            _ = shutdown.wait() => {
+                log::info!("shutting down");
+                Ok(())
+            }
             x = self.wait_for_signal(SignalKind::interrupt()) => x,
             x = self.wait_for_signal(SignalKind::terminate()) => x,
         }
     }
 
-    async fn serve<F, R>(&self, handler: F) -> std::io::Result<()>
+    async fn serve<F, R>(
+        &self,
+        listener: std::net::TcpListener,
+        handler: F,
+        stopped: Shutdown,
+    ) -> std::io::Result<()>
     where
-        F: Fn(Stream) -> R + Send + Sync + Copy + 'static,
+        F: Fn(Stream, ConnectionContext) -> R + Send + Sync + Clone + 'static,
         R: Future + Send,
     {
-        let address = if self.config.public {
-            log::warn!("binding port {} publicly to 0.0.0.0", self.config.port);
-            format!("0.0.0.0:{}", self.config.port)
-        } else {
-            log::info!("binding port {} locally to 127.0.0.1", self.config.port);
-            format!("127.0.0.1:{}", self.config.port)
-        };
-
-        let mut listener = TcpListener::bind(address).await?;
+        let mut listener = TcpListener::from_std(listener)?;
+        let local_address = listener.local_addr()?;
+        log::info!("listening on {}", local_address);
+        let port = local_address.port().to_string();
+        let metrics = ConnectionMetrics::new(&self.config.metrics, &port);
+        let admission = Admission::new(self.config.limits, &self.config.metrics, &port);
 
         loop {
+            admission.ready().await;
             let (stream, remote_address) = listener.accept().await?;
-            log::info!("accepted connection from {}", remote_address);
+            let ticket = match admission.admit(remote_address.ip()) {
+                Ok(ticket) => ticket,
+                Err(refusal) => {
+                    log::warn!("closing connection from {}: {}", remote_address, refusal);
+                    continue;
+                }
+            };
+            let context = ConnectionContext::new(remote_address, local_address, stopped.clone());
+            log::info!(
+                "accepted connection {} from {}",
+                context.id(),
+                remote_address
+            );
 
+            let guard = self.connections.track();
+            let open = metrics.accept();
+            let handler = handler.clone();
             tokio::spawn(async move {
-                handler(stream).await;
-                log::info!("closing connection from {}", remote_address);
+                let _ticket = ticket;
+                let _guard = guard;
+                let _open = open;
+                let id = context.id();
+                handler(stream, context).await;
+                log::info!("closing connection {} from {}", id, remote_address);
             });
         }
     }
@@ -0,0 +1,425 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use super::Stream;
+
+// Counters, gauges and histograms by name and labels, to be scraped by Prometheus. Clones share
+// the same registry, so servers can count into one that is served over HTTP.
+#[derive(Clone, Default)]
+pub struct Metrics {
+    families: Arc<Mutex<BTreeMap<String, Family>>>,
+}
+
+struct Family {
+    help: String,
+    series: BTreeMap<Labels, Series>,
+}
+
+type Labels = Vec<(String, String)>;
+
+#[derive(Clone)]
+enum Series {
+    Counter(Counter),
+    Gauge(Gauge),
+    Histogram(Histogram),
+}
+
+// Only ever goes up. A default counter is not registered anywhere.
+#[derive(Clone, Default)]
+pub struct Counter(Arc<AtomicU64>);
+
+#[derive(Clone, Default)]
+pub struct Gauge(Arc<AtomicI64>);
+
+// Counts observations into buckets by their upper bound, in seconds.
+#[derive(Clone)]
+pub struct Histogram(Arc<Mutex<Buckets>>);
+
+struct Buckets {
+    bounds: Vec<f64>,
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+const LATENCY_BUCKETS: &[f64] = &[
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+impl Metrics {
+    // The counter with this name and labels, created the first time it is asked for.
+    pub fn counter(&self, name: &str, help: &str, labels: &[(&str, &str)]) -> Counter {
+        match self.series(name, help, labels, || Series::Counter(Counter::default())) {
+            Series::Counter(c) => c,
+            _ => panic!("metric {} is not a counter", name),
+        }
+    }
+
+    pub fn gauge(&self, name: &str, help: &str, labels: &[(&str, &str)]) -> Gauge {
+        match self.series(name, help, labels, || Series::Gauge(Gauge::default())) {
+            Series::Gauge(g) => g,
+            _ => panic!("metric {} is not a gauge", name),
+        }
+    }
+
+    // A histogram with buckets suited to latencies, from a millisecond to ten seconds.
+    pub fn histogram(&self, name: &str, help: &str, labels: &[(&str, &str)]) -> Histogram {
+        match self.series(name, help, labels, || {
+            Series::Histogram(Histogram::new(LATENCY_BUCKETS))
+        }) {
+            Series::Histogram(h) => h,
+            _ => panic!("metric {} is not a histogram", name),
+        }
+    }
+
+    fn series<F>(&self, name: &str, help: &str, labels: &[(&str, &str)], make: F) -> Series
+    where
+        F: FnOnce() -> Series,
+    {
+        let labels = labels
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+
+        let mut families = self.families.lock().unwrap();
+        let family = families.entry(name.to_string()).or_insert_with(|| Family {
+            help: help.to_string(),
+            series: BTreeMap::new(),
+        });
+        family.series.entry(labels).or_insert_with(make).clone()
+    }
+
+    // All metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for (name, family) in self.families.lock().unwrap().iter() {
+            let kind = match family.series.values().next() {
+                Some(Series::Counter(_)) => "counter",
+                Some(Series::Gauge(_)) => "gauge",
+                Some(Series::Histogram(_)) => "histogram",
+                None => continue,
+            };
+            let _ = writeln!(out, "# HELP {} {}", name, family.help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+
+            for (labels, series) in family.series.iter() {
+                match series {
+                    Series::Counter(c) => {
+                        let _ = writeln!(out, "{}{} {}", name, format_labels(labels), c.get());
+                    }
+                    Series::Gauge(g) => {
+                        let _ = writeln!(out, "{}{} {}", name, format_labels(labels), g.get());
+                    }
+                    Series::Histogram(h) => h.render(&mut out, name, labels),
+                }
+            }
+        }
+        out
+    }
+
+    // Answer a single HTTP request on the stream with the metrics, for GET /metrics, or with
+    // not found for anything else. The client gets the timeout to send its request.
+    pub async fn respond(self, mut stream: Stream, timeout: Duration) -> std::io::Result<()> {
+        let request = match tokio::time::timeout(timeout, read_request(&mut stream)).await {
+            Ok(request) => request?,
+            Err(_) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    "no request in time",
+                ))
+            }
+        };
+        if request.is_empty() {
+            return Ok(());
+        }
+
+        let request_line = String::from_utf8_lossy(&request);
+        let mut parts = request_line.split_whitespace();
+        let (status, body) = match (parts.next(), parts.next()) {
+            (Some("GET"), Some("/metrics")) => ("200 OK", self.render()),
+            _ => ("404 Not Found", "not found\n".to_string()),
+        };
+
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        );
+        stream.write_all(response.as_bytes()).await?;
+        stream.shutdown(std::net::Shutdown::Write)
+    }
+}
+
+impl Counter {
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+impl Gauge {
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dec(&self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn set(&self, value: i64) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+impl Histogram {
+    fn new(bounds: &[f64]) -> Histogram {
+        Histogram(Arc::new(Mutex::new(Buckets {
+            bounds: bounds.to_vec(),
+            counts: vec![0; bounds.len()],
+            sum: 0.0,
+            count: 0,
+        })))
+    }
+
+    pub fn observe(&self, value: f64) {
+        let mut buckets = self.0.lock().unwrap();
+        if let Some(i) = buckets.bounds.iter().position(|b| value <= *b) {
+            buckets.counts[i] += 1;
+        }
+        buckets.sum += value;
+        buckets.count += 1;
+    }
+
+    pub fn observe_duration(&self, duration: Duration) {
+        self.observe(duration.as_secs_f64());
+    }
+
+    // The number of observations.
+    pub fn count(&self) -> u64 {
+        self.0.lock().unwrap().count
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &Labels) {
+        let buckets = self.0.lock().unwrap();
+
+        // buckets are cumulative, each counts everything up to its bound
+        let mut cumulative = 0;
+        for (bound, count) in buckets.bounds.iter().zip(buckets.counts.iter()) {
+            cumulative += count;
+            let _ = writeln!(
+                out,
+                "{}_bucket{} {}",
+                name,
+                format_labels(&with_le(labels, &bound.to_string())),
+                cumulative
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{}_bucket{} {}",
+            name,
+            format_labels(&with_le(labels, "+Inf")),
+            buckets.count
+        );
+        let _ = writeln!(out, "{}_sum{} {}", name, format_labels(labels), buckets.sum);
+        let _ = writeln!(
+            out,
+            "{}_count{} {}",
+            name,
+            format_labels(labels),
+            buckets.count
+        );
+    }
+}
+
+// Counts the connections a server accepted, has active and closed, by listener.
+#[derive(Clone)]
+pub struct ConnectionMetrics {
+    accepted: Counter,
+    active: Gauge,
+    closed: Counter,
+}
+
+// Counts as an active connection until dropped, then as a closed one.
+pub struct OpenConnection {
+    metrics: ConnectionMetrics,
+}
+
+impl ConnectionMetrics {
+    pub fn new(metrics: &Metrics, listener: &str) -> ConnectionMetrics {
+        let labels = [("listener", listener)];
+        ConnectionMetrics {
+            accepted: metrics.counter(
+                "connections_accepted_total",
+                "Connections accepted.",
+                &labels,
+            ),
+            active: metrics.gauge("connections_active", "Connections being handled.", &labels),
+            closed: metrics.counter(
+                "connections_closed_total",
+                "Connections closed, including those that failed.",
+                &labels,
+            ),
+        }
+    }
+
+    pub fn accept(&self) -> OpenConnection {
+        self.accepted.inc();
+        self.active.inc();
+        OpenConnection {
+            metrics: self.clone(),
+        }
+    }
+}
+
+impl Drop for OpenConnection {
+    fn drop(&mut self) {
+        self.metrics.active.dec();
+        self.metrics.closed.inc();
+    }
+}
+
+impl std::fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Metrics").finish()
+    }
+}
+
+// Reads up to the end of the request headers, or nothing if the client stops before that.
+async fn read_request(stream: &mut Stream) -> std::io::Result<Vec<u8>> {
+    let mut request = Vec::new();
+    let mut buf = [0u8; 512];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 || request.len() > 8192 {
+            return Ok(Vec::new());
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+    Ok(request)
+}
+
+fn with_le(labels: &Labels, le: &str) -> Labels {
+    let mut labels = labels.clone();
+    labels.push(("le".to_string(), le.to_string()));
+    labels
+}
+
+fn format_labels(labels: &Labels) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let labels: Vec<String> = labels
+        .iter()
+        .map(|(k, v)| {
+            let v = v
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{}=\"{}\"", k, v)
+        })
+        .collect();
+    format!("{{{}}}", labels.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_counters_and_gauges() {
+        let metrics = Metrics::default();
+        metrics
+            .counter("hits_total", "Hits.", &[("path", "/a")])
+            .add(3);
+        metrics
+            .counter("hits_total", "Hits.", &[("path", "/b")])
+            .inc();
+        metrics.gauge("busy", "Busy.", &[]).inc();
+
+        assert_eq!(
+            "# HELP busy Busy.\n\
+             # TYPE busy gauge\n\
+             busy 1\n\
+             # HELP hits_total Hits.\n\
+             # TYPE hits_total counter\n\
+             hits_total{path=\"/a\"} 3\n\
+             hits_total{path=\"/b\"} 1\n",
+            metrics.render()
+        );
+    }
+
+    #[test]
+    fn shares_series_by_name_and_labels() {
+        let metrics = Metrics::default();
+        metrics
+            .counter("hits_total", "Hits.", &[("path", "/a")])
+            .inc();
+        metrics
+            .clone()
+            .counter("hits_total", "Hits.", &[("path", "/a")])
+            .inc();
+
+        assert_eq!(
+            2,
+            metrics
+                .counter("hits_total", "Hits.", &[("path", "/a")])
+                .get()
+        );
+    }
+
+    #[test]
+    fn renders_cumulative_histograms() {
+        let histogram = Histogram::new(&[0.1, 1.0]);
+        histogram.observe(0.05);
+        histogram.observe(0.5);
+        histogram.observe(5.0);
+
+        let mut out = String::new();
+        histogram.render(&mut out, "latency", &vec![]);
+        assert_eq!(
+            "latency_bucket{le=\"0.1\"} 1\n\
+             latency_bucket{le=\"1\"} 2\n\
+             latency_bucket{le=\"+Inf\"} 3\n\
+             latency_sum 5.55\n\
+             latency_count 3\n",
+            out
+        );
+    }
+
+    #[test]
+    fn escapes_label_values() {
+        let labels = vec![("name".to_string(), "a \"b\"\\".to_string())];
+        assert_eq!("{name=\"a \\\"b\\\"\\\\\"}", format_labels(&labels));
+    }
+
+    #[test]
+    fn counts_open_connections() {
+        let metrics = Metrics::default();
+        let connections = ConnectionMetrics::new(&metrics, "5000");
+
+        let open = connections.accept();
+        assert_eq!(1, connections.active.get());
+        drop(open);
+
+        assert_eq!(1, connections.accepted.get());
+        assert_eq!(0, connections.active.get());
+        assert_eq!(1, connections.closed.get());
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+// A token bucket on new connections per source, where sources in the same prefix count as one.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RateLimit {
+    // connections per second, on average
+    pub rate: f64,
+    // connections at once before the rate kicks in
+    pub burst: f64,
+    pub ipv4_prefix: u8,
+    pub ipv6_prefix: u8,
+}
+
+impl RateLimit {
+    // Limits single IPv4 addresses, and IPv6 addresses by /64 as those usually go together.
+    pub fn new(rate: f64, burst: f64) -> RateLimit {
+        RateLimit {
+            rate,
+            burst,
+            ipv4_prefix: 32,
+            ipv6_prefix: 64,
+        }
+    }
+
+    fn source(&self, address: IpAddr) -> IpAddr {
+        match address {
+            IpAddr::V4(a) => {
+                let mask = u32::MAX.checked_shl(32 - self.ipv4_prefix.min(32) as u32);
+                IpAddr::V4((u32::from(a) & mask.unwrap_or(0)).into())
+            }
+            IpAddr::V6(a) => {
+                let mask = u128::MAX.checked_shl(128 - self.ipv6_prefix.min(128) as u32);
+                IpAddr::V6((u128::from(a) & mask.unwrap_or(0)).into())
+            }
+        }
+    }
+}
+
+pub(crate) struct Buckets {
+    limit: RateLimit,
+    buckets: HashMap<IpAddr, Bucket>,
+    pruned: Instant,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Buckets {
+    pub fn new(limit: RateLimit) -> Buckets {
+        Buckets {
+            limit,
+            buckets: HashMap::new(),
+            pruned: Instant::now(),
+        }
+    }
+
+    // Takes a token for a new connection from the address, if there is one.
+    pub fn take(&mut self, address: IpAddr, now: Instant) -> bool {
+        self.prune(now);
+
+        let limit = self.limit;
+        let bucket = self.buckets.entry(limit.source(address)).or_insert(Bucket {
+            tokens: limit.burst,
+            updated: now,
+        });
+        bucket.refill(&limit, now);
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    // Forget sources whose buckets filled up again, every now and then.
+    fn prune(&mut self, now: Instant) {
+        if now.saturating_duration_since(self.pruned) < Duration::from_secs(60) {
+            return;
+        }
+        let limit = self.limit;
+        self.buckets.retain(|_, bucket| {
+            bucket.refill(&limit, now);
+            bucket.tokens < limit.burst
+        });
+        self.pruned = now;
+    }
+}
+
+impl Bucket {
+    fn refill(&mut self, limit: &RateLimit, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.rate).min(limit.burst);
+        self.updated = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn allows_bursts() {
+        let mut buckets = Buckets::new(RateLimit::new(1.0, 3.0));
+        let now = Instant::now();
+
+        assert!(buckets.take(ip("10.0.0.1"), now));
+        assert!(buckets.take(ip("10.0.0.1"), now));
+        assert!(buckets.take(ip("10.0.0.1"), now));
+        assert!(!buckets.take(ip("10.0.0.1"), now));
+        assert!(buckets.take(ip("10.0.0.2"), now));
+    }
+
+    #[test]
+    fn refills_at_the_rate() {
+        let mut buckets = Buckets::new(RateLimit::new(2.0, 1.0));
+        let now = Instant::now();
+
+        assert!(buckets.take(ip("10.0.0.1"), now));
+        assert!(!buckets.take(ip("10.0.0.1"), now + Duration::from_millis(100)));
+        assert!(buckets.take(ip("10.0.0.1"), now + Duration::from_millis(600)));
+    }
+
+    #[test]
+    fn limits_by_prefix() {
+        let mut limit = RateLimit::new(1.0, 1.0);
+        limit.ipv4_prefix = 24;
+        let mut buckets = Buckets::new(limit);
+        let now = Instant::now();
+
+        assert!(buckets.take(ip("10.0.0.1"), now));
+        assert!(!buckets.take(ip("10.0.0.2"), now));
+        assert!(buckets.take(ip("10.0.1.1"), now));
+
+        assert!(buckets.take(ip("2001:db8::1"), now));
+        assert!(!buckets.take(ip("2001:db8::2"), now));
+        assert!(buckets.take(ip("2001:db8:0:1::1"), now));
+    }
+
+    #[test]
+    fn masks_sources() {
+        let mut limit = RateLimit::new(1.0, 1.0);
+        assert_eq!(ip("10.1.2.3"), limit.source(ip("10.1.2.3")));
+        limit.ipv4_prefix = 16;
+        assert_eq!(ip("10.1.0.0"), limit.source(ip("10.1.2.3")));
+        limit.ipv4_prefix = 0;
+        assert_eq!(ip("0.0.0.0"), limit.source(ip("10.1.2.3")));
+        assert_eq!(ip("2001:db8::"), limit.source(ip("2001:db8::1:2:3:4")));
+    }
+
+    #[test]
+    fn forgets_full_buckets() {
+        let mut buckets = Buckets::new(RateLimit::new(1.0, 1.0));
+        let now = Instant::now();
+
+        buckets.take(ip("10.0.0.1"), now);
+        assert_eq!(1, buckets.buckets.len());
+        buckets.take(ip("10.0.0.2"), now + Duration::from_secs(61));
+        assert_eq!(1, buckets.buckets.len());
+    }
+}
@@ -0,0 +1,95 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+// Counts the connections that are being handled, so shutdown can wait for them to finish.
+#[derive(Clone, Default)]
+pub struct ConnectionTracker {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    active: AtomicUsize,
+    idle: Notify,
+}
+
+// Counts as an active connection until dropped.
+pub struct ConnectionGuard {
+    inner: Arc<Inner>,
+}
+
+impl ConnectionTracker {
+    pub fn track(&self) -> ConnectionGuard {
+        self.inner.active.fetch_add(1, Ordering::SeqCst);
+        ConnectionGuard {
+            inner: self.inner.clone(),
+        }
+    }
+
+    pub fn active(&self) -> usize {
+        self.inner.active.load(Ordering::SeqCst)
+    }
+
+    // Waits until no connections are active or the timeout passes, gives the number of
+    // connections still active.
+    pub async fn drain(&self, timeout: Duration) -> usize {
+        let idle = async {
+            while self.active() > 0 {
+                self.inner.idle.notified().await;
+            }
+        };
+        let _ = tokio::time::timeout(timeout, idle).await;
+        self.active()
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        if self.inner.active.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.inner.idle.notify();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn counts_active_connections() {
+        let tracker = ConnectionTracker::default();
+        assert_eq!(0, tracker.active());
+
+        let first = tracker.track();
+        let second = tracker.track();
+        assert_eq!(2, tracker.active());
+
+        drop(first);
+        assert_eq!(1, tracker.active());
+        drop(second);
+        assert_eq!(0, tracker.active());
+    }
+
+    #[tokio::test]
+    async fn drains_when_connections_finish() {
+        let tracker = ConnectionTracker::default();
+        let guard = tracker.track();
+
+        tokio::spawn(async move {
+            tokio::time::delay_for(Duration::from_millis(10)).await;
+            drop(guard);
+        });
+
+        assert_eq!(0, tracker.drain(Duration::from_secs(10)).await);
+    }
+
+    #[tokio::test]
+    async fn gives_up_draining_after_timeout() {
+        let tracker = ConnectionTracker::default();
+        let _guard = tracker.track();
+
+        assert_eq!(1, tracker.drain(Duration::from_millis(10)).await);
+    }
+}
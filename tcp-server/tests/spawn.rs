@@ -0,0 +1,318 @@
+extern crate tcp_server;
+extern crate tokio;
+
+use std::io::{Read, Write};
+use tokio::io::AsyncWriteExt;
+
+async fn greet(mut stream: tcp_server::Stream, _context: tcp_server::ConnectionContext) {
+    let _ = stream.write_all(b"hello").await;
+}
+
+fn greeting(address: std::net::SocketAddr) -> String {
+    let mut stream = std::net::TcpStream::connect(address).expect("connect");
+    let mut buf = String::new();
+    stream.read_to_string(&mut buf).expect("read");
+    buf
+}
+
+#[test]
+fn serves_in_the_background() {
+    let server = tcp_server::Server::new(tcp_server::Config::new(0)).expect("server");
+    let handle = server.spawn(greet).expect("spawn");
+
+    assert_ne!(0, handle.address().port());
+    assert_eq!("hello", greeting(handle.address()));
+    assert_eq!("hello", greeting(handle.address()));
+}
+
+#[test]
+fn shares_state_between_connections() {
+    let counter = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let server = tcp_server::Server::new(tcp_server::Config::new(0)).expect("server");
+    let handle = server.spawn(move |mut stream: tcp_server::Stream, _| {
+        let counter = counter.clone();
+        async move {
+            let n = counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
+            let _ = stream.write_all(n.to_string().as_bytes()).await;
+        }
+    });
+    let handle = handle.expect("spawn");
+
+    assert_eq!("1", greeting(handle.address()));
+    assert_eq!("2", greeting(handle.address()));
+}
+
+#[test]
+fn tells_the_handler_about_the_connection() {
+    let server = tcp_server::Server::new(tcp_server::Config::new(0)).expect("server");
+    let handle = server.spawn(
+        |mut stream: tcp_server::Stream, context: tcp_server::ConnectionContext| async move {
+            let about = format!("{} {}", context.peer_addr(), context.local_addr());
+            let _ = stream.write_all(about.as_bytes()).await;
+        },
+    );
+    let handle = handle.expect("spawn");
+
+    let mut stream = std::net::TcpStream::connect(handle.address()).expect("connect");
+    let mut about = String::new();
+    stream.read_to_string(&mut about).expect("read");
+
+    let expected = format!(
+        "{} {}",
+        stream.local_addr().unwrap(),
+        stream.peer_addr().unwrap()
+    );
+    assert_eq!(expected, about);
+}
+
+#[test]
+fn tells_the_handler_about_shutting_down() {
+    let server = tcp_server::Server::new(tcp_server::Config::new(0)).expect("server");
+    let handle = server.spawn(
+        |mut stream: tcp_server::Stream, context: tcp_server::ConnectionContext| async move {
+            let _ = stream.write_all(b"hello").await;
+            context.shutdown().wait().await;
+            let _ = stream.write_all(b", bye").await;
+        },
+    );
+    let handle = handle.expect("spawn");
+
+    let mut stream = std::net::TcpStream::connect(handle.address()).expect("connect");
+    let mut buf = [0u8; 5];
+    stream.read_exact(&mut buf).expect("read");
+
+    handle.shutdown();
+
+    let mut rest = String::new();
+    stream.read_to_string(&mut rest).expect("read");
+    assert_eq!(", bye", rest);
+    handle.join().expect("clean shutdown");
+}
+
+#[test]
+fn shuts_down_through_the_handle() {
+    let server = tcp_server::Server::new(tcp_server::Config::new(0)).expect("server");
+    let handle = server.spawn(greet).expect("spawn");
+    let address = handle.address();
+
+    handle.shutdown();
+    handle.join().expect("clean shutdown");
+
+    std::net::TcpStream::connect(address).expect_err("no longer accepting");
+}
+
+#[test]
+fn drains_when_shutting_down() {
+    let config = tcp_server::Config::new(0).with_drain_timeout(std::time::Duration::from_secs(5));
+    let server = tcp_server::Server::new(config).expect("server");
+    let handle = server.spawn(|mut stream: tcp_server::Stream, _| async move {
+        let (mut rx, mut tx) = stream.split();
+        let _ = tokio::io::copy(&mut rx, &mut tx).await;
+    });
+    let handle = handle.expect("spawn");
+
+    let mut client = std::net::TcpStream::connect(handle.address()).expect("connect");
+    client.write_all(b"ping").expect("write");
+    let mut buf = [0u8; 4];
+    client.read_exact(&mut buf).expect("read");
+
+    handle.shutdown();
+
+    client.write_all(b"pong").expect("write");
+    client
+        .read_exact(&mut buf)
+        .expect("still served while draining");
+    assert_eq!(b"pong", &buf);
+
+    drop(client);
+    handle.join().expect("clean shutdown");
+}
+
+#[test]
+fn counts_connections() {
+    let metrics = tcp_server::Metrics::default();
+    let config = tcp_server::Config::new(0).with_metrics(metrics.clone());
+    let server = tcp_server::Server::new(config).expect("server");
+    let handle = server.spawn(greet).expect("spawn");
+    let port = handle.address().port().to_string();
+
+    greeting(handle.address());
+    greeting(handle.address());
+    drop(handle);
+
+    let labels = [("listener", port.as_str())];
+    let counter = |name| metrics.counter(name, "", &labels).get();
+    assert_eq!(2, counter("connections_accepted_total"));
+    assert_eq!(2, counter("connections_closed_total"));
+    assert_eq!(0, metrics.gauge("connections_active", "", &labels).get());
+}
+
+#[test]
+fn serves_metrics_over_http() {
+    let metrics = tcp_server::Metrics::default();
+    metrics.counter("hits_total", "Hits.", &[]).inc();
+
+    let server = tcp_server::Server::new(tcp_server::Config::new(0)).expect("server");
+    let handle = server
+        .spawn(move |stream, _| {
+            metrics
+                .clone()
+                .respond(stream, std::time::Duration::from_secs(5))
+        })
+        .expect("spawn");
+
+    let get = |path: &str| {
+        let mut stream = std::net::TcpStream::connect(handle.address()).expect("connect");
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).expect("write");
+        let mut buf = String::new();
+        stream.read_to_string(&mut buf).expect("read");
+        buf
+    };
+
+    let found = get("/metrics");
+    assert!(found.starts_with("HTTP/1.1 200 OK\r\n"), "{}", found);
+    assert!(found.ends_with("# TYPE hits_total counter\nhits_total 1\n"));
+
+    let not_found = get("/");
+    assert!(not_found.starts_with("HTTP/1.1 404 Not Found\r\n"));
+}
+
+#[test]
+fn closes_metrics_connections_without_a_request() {
+    let metrics = tcp_server::Metrics::default();
+    let server = tcp_server::Server::new(tcp_server::Config::new(0)).expect("server");
+    let handle = server
+        .spawn(move |stream, _| {
+            metrics
+                .clone()
+                .respond(stream, std::time::Duration::from_millis(50))
+        })
+        .expect("spawn");
+
+    let mut stream = std::net::TcpStream::connect(handle.address()).expect("connect");
+    stream
+        .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+        .expect("timeout");
+    let mut buf = String::new();
+    stream
+        .read_to_string(&mut buf)
+        .expect("closed by the server");
+    assert!(buf.is_empty());
+}
+
+#[test]
+fn binds_to_the_given_address() {
+    let config = tcp_server::Config::new(0).with_address("127.0.0.2".parse().unwrap());
+    let server = tcp_server::Server::new(config).expect("server");
+    let handle = server.spawn(greet).expect("spawn");
+
+    assert_eq!("127.0.0.2", handle.address().ip().to_string());
+    assert_eq!("hello", greeting(handle.address()));
+}
+
+// Greets, then holds on to the connection until the client closes it.
+async fn greet_and_hold(mut stream: tcp_server::Stream, _context: tcp_server::ConnectionContext) {
+    use tokio::io::AsyncReadExt;
+
+    let _ = stream.write_all(b"hello").await;
+    let mut buf = Vec::new();
+    let _ = stream.read_to_end(&mut buf).await;
+}
+
+fn held_greeting(address: std::net::SocketAddr) -> (std::net::TcpStream, String) {
+    let mut stream = std::net::TcpStream::connect(address).expect("connect");
+    stream
+        .set_read_timeout(Some(std::time::Duration::from_millis(200)))
+        .expect("timeout");
+    let mut buf = [0u8; 5];
+    let greeting = match stream.read_exact(&mut buf) {
+        Ok(()) => String::from_utf8_lossy(&buf).to_string(),
+        Err(_) => String::new(),
+    };
+    (stream, greeting)
+}
+
+#[test]
+fn closes_connections_over_the_limit() {
+    let config = tcp_server::Config::new(0)
+        .with_max_connections(1)
+        .with_over_limit(tcp_server::OverLimit::Close);
+    let server = tcp_server::Server::new(config).expect("server");
+    let handle = server.spawn(greet_and_hold).expect("spawn");
+
+    let (first, greeting) = held_greeting(handle.address());
+    assert_eq!("hello", greeting);
+
+    let mut second = std::net::TcpStream::connect(handle.address()).expect("connect");
+    let mut buf = Vec::new();
+    let _ = second.read_to_end(&mut buf);
+    assert!(buf.is_empty());
+
+    // let in again once the first one is gone, which the server notices a bit later
+    drop(first);
+    let admitted = (0..100).any(|_| {
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        held_greeting(handle.address()).1 == "hello"
+    });
+    assert!(admitted);
+}
+
+#[test]
+fn pauses_accepting_at_the_limit() {
+    let config = tcp_server::Config::new(0)
+        .with_max_connections(1)
+        .with_over_limit(tcp_server::OverLimit::Pause);
+    let server = tcp_server::Server::new(config).expect("server");
+    let handle = server.spawn(greet_and_hold).expect("spawn");
+
+    let (first, greeting) = held_greeting(handle.address());
+    assert_eq!("hello", greeting);
+
+    // waits in the backlog until the first one is gone
+    let (mut second, greeting) = held_greeting(handle.address());
+    assert_eq!("", greeting);
+
+    drop(first);
+    second
+        .set_read_timeout(Some(std::time::Duration::from_secs(10)))
+        .expect("timeout");
+    let mut buf = [0u8; 5];
+    second.read_exact(&mut buf).expect("accepted");
+    assert_eq!(b"hello", &buf);
+}
+
+#[test]
+fn limits_connections_per_source() {
+    let metrics = tcp_server::Metrics::default();
+    let config = tcp_server::Config::new(0)
+        .with_max_connections_per_source(1)
+        .with_metrics(metrics.clone());
+    let server = tcp_server::Server::new(config).expect("server");
+    let handle = server.spawn(greet_and_hold).expect("spawn");
+    let port = handle.address().port().to_string();
+
+    let (_first, greeting) = held_greeting(handle.address());
+    assert_eq!("hello", greeting);
+    let (_second, greeting) = held_greeting(handle.address());
+    assert_eq!("", greeting);
+
+    let refused = metrics.counter(
+        "connections_refused_total",
+        "",
+        &[("listener", &port), ("reason", "max_per_source")],
+    );
+    assert_eq!(1, refused.get());
+    let sources = metrics.gauge("connection_sources", "", &[("listener", &port)]);
+    assert_eq!(1, sources.get());
+}
+
+#[test]
+fn rate_limits_new_connections() {
+    let config = tcp_server::Config::new(0).with_rate_limit(tcp_server::RateLimit::new(0.001, 2.0));
+    let server = tcp_server::Server::new(config).expect("server");
+    let handle = server.spawn(greet).expect("spawn");
+
+    assert_eq!("hello", greeting(handle.address()));
+    assert_eq!("hello", greeting(handle.address()));
+    assert_eq!("", greeting(handle.address()));
+}
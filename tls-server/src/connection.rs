@@ -0,0 +1,107 @@
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tcp_server::Counter;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+
+// The accepted TCP connection, with the addresses of the original client when it arrived
+// through a proxy that sent a PROXY protocol header.
+pub struct Connection {
+    stream: TcpStream,
+    proxied: Option<proxy_protocol::Header>,
+    received: Counter,
+    sent: Counter,
+    handshake_duration: Option<Duration>,
+}
+
+impl Connection {
+    pub(crate) fn new(stream: TcpStream, proxied: Option<proxy_protocol::Header>) -> Connection {
+        Connection {
+            stream,
+            proxied,
+            received: Counter::default(),
+            sent: Counter::default(),
+            handshake_duration: None,
+        }
+    }
+
+    // Count the bytes going through in these counters.
+    pub(crate) fn with_counters(mut self, received: Counter, sent: Counter) -> Connection {
+        self.received = received;
+        self.sent = sent;
+        self
+    }
+
+    // The client address, as told by the proxy if there is one.
+    pub fn peer_addr(&self) -> std::io::Result<SocketAddr> {
+        match &self.proxied {
+            Some(h) => Ok(h.source),
+            None => self.stream.peer_addr(),
+        }
+    }
+
+    // The address the client connected to, as told by the proxy if there is one.
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        match &self.proxied {
+            Some(h) => Ok(h.destination),
+            None => self.stream.local_addr(),
+        }
+    }
+
+    // How long the TLS handshake took, once it completed.
+    pub fn handshake_duration(&self) -> Option<Duration> {
+        self.handshake_duration
+    }
+
+    pub(crate) fn set_handshake_duration(&mut self, duration: Duration) {
+        self.handshake_duration = Some(duration);
+    }
+
+    pub fn proxy_header(&self) -> Option<&proxy_protocol::Header> {
+        self.proxied.as_ref()
+    }
+
+    pub fn get_ref(&self) -> &TcpStream {
+        &self.stream
+    }
+}
+
+impl AsyncRead for Connection {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.stream).poll_read(cx, buf);
+        if let Poll::Ready(Ok(n)) = result {
+            this.received.add(n as u64);
+        }
+        result
+    }
+}
+
+impl AsyncWrite for Connection {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.stream).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = result {
+            this.sent.add(n as u64);
+        }
+        result
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+    }
+}
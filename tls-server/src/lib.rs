@@ -1,19 +1,45 @@
 extern crate certutils;
 extern crate futures;
 extern crate log;
+extern crate proxy_protocol;
 extern crate rustls;
 extern crate string_error;
+extern crate tcp_server;
 extern crate tokio;
 extern crate tokio_rustls;
+extern crate webpki;
+
+mod connection;
+mod metrics;
+mod rejection;
+mod reload;
+mod resolver;
+mod session;
+mod verifier;
 
 use futures::future::Future;
+use metrics::{failure_reason, ListenerMetrics};
+use rejection::{reject, OnRejected};
 use std::marker::{Send, Sync};
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
+use tcp_server::ConnectionTracker;
+use verifier::{Authorize, ClientVerifier};
+
+use tcp_server::{Admission, ConnectionLimits};
+pub use tcp_server::{
+    BanPolicy, ConnectionContext, Handle, Metrics, OverLimit, RateLimit, Refusal, Shutdown,
+};
 use tokio::net::TcpListener;
 use tokio::signal::unix::{signal, SignalKind};
+use tokio::time::timeout_at;
 use tokio_rustls::TlsAcceptor;
 
-pub type Stream = tokio_rustls::server::TlsStream<tokio::net::TcpStream>;
+pub use connection::Connection;
+pub use rejection::Rejection;
+pub use resolver::CertificateResolver;
+pub use session::Session;
+
+pub type Stream = tokio_rustls::server::TlsStream<Connection>;
 
 pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
@@ -22,7 +48,28 @@ pub struct Config {
     port: u16,
     threaded: bool,
     shutdown_timeout: std::time::Duration,
+    drain_timeout: std::time::Duration,
+    handshake_timeout: std::time::Duration,
+    max_lifetime: Option<std::time::Duration>,
+    limits: ConnectionLimits,
     tls: rustls::ServerConfig,
+    certificates: CertificateResolver,
+    client_auth_root: Option<String>,
+    client_crls: Vec<String>,
+    authorize: Option<Authorize>,
+    on_rejected: Option<OnRejected>,
+    proxy_protocol: Option<ProxyProtocol>,
+    trusted_proxies: Vec<std::net::IpAddr>,
+    reload_interval: Option<std::time::Duration>,
+    ocsp_refresh_interval: std::time::Duration,
+    metrics: Metrics,
+}
+
+// Whether connections start with a PROXY protocol header, as sent by a load balancer in front.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ProxyProtocol {
+    Optional,
+    Required,
 }
 
 impl Config {
@@ -31,7 +78,21 @@ impl Config {
             port,
             threaded: false,
             shutdown_timeout: std::time::Duration::from_secs(1),
+            drain_timeout: std::time::Duration::from_secs(5),
+            handshake_timeout: std::time::Duration::from_secs(10),
+            max_lifetime: None,
+            limits: ConnectionLimits::default(),
             tls: rustls::ServerConfig::new(rustls::NoClientAuth::new()),
+            certificates: CertificateResolver::default(),
+            client_auth_root: None,
+            client_crls: vec![],
+            authorize: None,
+            on_rejected: None,
+            proxy_protocol: None,
+            trusted_proxies: vec![],
+            reload_interval: None,
+            ocsp_refresh_interval: std::time::Duration::from_secs(60),
+            metrics: Metrics::default(),
         }
     }
 
@@ -45,30 +106,232 @@ impl Config {
         self
     }
 
+    // How long to wait for active connections to finish after stopping accepting new ones.
+    pub fn with_drain_timeout(&mut self, timeout: std::time::Duration) -> &mut Self {
+        self.drain_timeout = timeout;
+        self
+    }
+
+    // How long a client gets to send the PROXY protocol header, if expected, and complete the
+    // handshake, ten seconds by default.
+    pub fn with_handshake_timeout(&mut self, timeout: std::time::Duration) -> &mut Self {
+        self.handshake_timeout = timeout;
+        self
+    }
+
+    // Cut connections that have been open this long, however busy they are.
+    pub fn with_max_lifetime(&mut self, lifetime: std::time::Duration) -> &mut Self {
+        self.max_lifetime = Some(lifetime);
+        self
+    }
+
+    // Handle at most this many connections at once, what happens to more depends on the over
+    // limit policy.
+    pub fn with_max_connections(&mut self, max: usize) -> &mut Self {
+        self.limits.max_connections = Some(max);
+        self
+    }
+
+    // Handle at most this many connections at once from a single source address, more are closed
+    // right away. Behind a proxy, the source is the one its PROXY protocol header tells.
+    pub fn with_max_connections_per_source(&mut self, max: usize) -> &mut Self {
+        self.limits.max_per_source = Some(max);
+        self
+    }
+
+    // Pause accepting at the maximum number of connections, the default, or accept and close.
+    pub fn with_over_limit(&mut self, over_limit: OverLimit) -> &mut Self {
+        self.limits.over_limit = over_limit;
+        self
+    }
+
+    // Close new connections from sources, or prefixes of them, that connect faster than this.
+    pub fn with_rate_limit(&mut self, limit: RateLimit) -> &mut Self {
+        self.limits.rate_limit = Some(limit);
+        self
+    }
+
+    // Close new connections from sources that failed too often, for a while. Failing is not
+    // completing the handshake in time or at all, which includes clients that do not pass
+    // authentication. Like the rate limit, this goes by the source a PROXY header tells, except
+    // for failing to send a header, which counts against the proxy itself.
+    pub fn with_bans(&mut self, policy: BanPolicy) -> &mut Self {
+        self.limits.bans = Some(policy);
+        self
+    }
+
     pub fn with_certificate_and_key_files(
         &mut self,
         certfile: &str,
         keyfile: &str,
     ) -> Result<&mut Self> {
-        let cert = certutils::read_certs(certfile)?;
-        let key = certutils::read_key(keyfile)?;
-        self.tls.set_single_cert(cert, key)?;
+        self.certificates.set_default(certfile, keyfile)?;
+        Ok(self)
+    }
+
+    // Staple the OCSP response in this file, in DER format, to the default certificate. The file
+    // is read again every OCSP refresh interval, a response that has expired, is not valid yet or
+    // is not about the certificate is not stapled. The certificate file must hold the issuer after
+    // the certificate, to tell the latter.
+    pub fn with_ocsp_response_file(&mut self, ocspfile: &str) -> Result<&mut Self> {
+        // read once to report a bad file right away
+        certutils::read_ocsp_response(ocspfile)?;
+        self.certificates.set_default_ocsp_response_file(ocspfile);
+        Ok(self)
+    }
+
+    // Serve a different certificate to clients asking for this server name through SNI.
+    pub fn with_sni_certificate_and_key_files(
+        &mut self,
+        name: &str,
+        certfile: &str,
+        keyfile: &str,
+    ) -> Result<&mut Self> {
+        self.certificates.add(name, certfile, keyfile)?;
         Ok(self)
     }
 
+    // Protocols to offer through ALPN, in order of preference.
+    pub fn with_alpn_protocols(&mut self, protocols: &[&str]) -> &mut Self {
+        let protocols: Vec<Vec<u8>> = protocols.iter().map(|p| p.as_bytes().to_vec()).collect();
+        self.tls.set_protocols(&protocols);
+        self
+    }
+
+    pub fn with_proxy_protocol(&mut self, mode: ProxyProtocol) -> &mut Self {
+        self.proxy_protocol = Some(mode);
+        self
+    }
+
+    // Only take PROXY protocol headers from these addresses, as anyone else could claim to be
+    // connecting from anywhere. Connections from others are refused when the header is required,
+    // and taken as not proxied when it is optional.
+    pub fn with_trusted_proxies(&mut self, addresses: &[std::net::IpAddr]) -> &mut Self {
+        self.trusted_proxies = addresses.to_vec();
+        self
+    }
+
     pub fn with_client_authentication(&mut self, root_certfile: &str) -> Result<&mut Self> {
+        // load once to report a bad file right away
+        Config::client_roots(root_certfile)?;
+        self.client_auth_root = Some(root_certfile.to_string());
+        Ok(self)
+    }
+
+    // Reject client certificates revoked by the lists in this file, in DER or PEM format. Can be
+    // given more than once, the files are reloaded along with the certificates.
+    pub fn with_client_revocation_list(&mut self, crlfile: &str) -> Result<&mut Self> {
+        // load once to report a bad file right away
+        Config::revocation_lists(crlfile)?;
+        self.client_crls.push(crlfile.to_string());
+        Ok(self)
+    }
+
+    // On top of chaining to the client authentication root, the client certificate has to pass
+    // this check. Rejected clients get a handshake failure alert, the reason is logged for audit.
+    pub fn with_client_authorization<F>(&mut self, authorize: F) -> &mut Self
+    where
+        F: Fn(&certutils::CertificateInfo) -> std::result::Result<(), String>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.authorize = Some(Arc::new(authorize));
+        self
+    }
+
+    // Tell this about every connection that does not make it to the handler, because it is over a
+    // limit, fails or times out the PROXY header or the handshake, or fails authorization.
+    pub fn with_rejection_handler<F>(&mut self, on_rejected: F) -> &mut Self
+    where
+        F: Fn(&ConnectionContext, &Rejection) + Send + Sync + 'static,
+    {
+        self.on_rejected = Some(Arc::new(on_rejected));
+        self
+    }
+
+    // Besides reloading on SIGHUP, check the certificate, key and root files for changes every
+    // interval and reload when any changed.
+    pub fn with_reload_interval(&mut self, interval: std::time::Duration) -> &mut Self {
+        self.reload_interval = Some(interval);
+        self
+    }
+
+    // Count connections, handshakes and bytes into these metrics, labeled with the port listened
+    // on.
+    pub fn with_metrics(&mut self, metrics: Metrics) -> &mut Self {
+        self.metrics = metrics;
+        self
+    }
+
+    // How often to read the OCSP response files again, every minute by default.
+    pub fn with_ocsp_refresh_interval(&mut self, interval: std::time::Duration) -> &mut Self {
+        self.ocsp_refresh_interval = interval;
+        self
+    }
+
+    // The rustls configuration, with all certificates, keys and roots read from their files.
+    fn load(&self) -> Result<rustls::ServerConfig> {
+        let mut tls = self.tls.clone();
+        if let Some(root) = &self.client_auth_root {
+            let roots = Config::client_roots(root)?;
+            let crls = self
+                .client_crls
+                .iter()
+                .map(|f| Config::revocation_lists(f))
+                .collect::<Result<Vec<_>>>()?
+                .concat();
+            let verifier = ClientVerifier::new(roots, crls, self.authorize.clone());
+            tls.set_client_certificate_verifier(Arc::new(verifier));
+
+            // resuming a session skips verifying the client, so sessions from before a reload
+            // should not be, a client revoked since has to be checked again
+            tls.set_persistence(rustls::ServerSessionMemoryCache::new(256));
+        }
+        tls.cert_resolver = Arc::new(self.certificates.reload()?);
+        Ok(tls)
+    }
+
+    fn files(&self) -> Vec<&str> {
+        self.certificates
+            .files()
+            .chain(self.client_auth_root.iter().map(|r| r.as_str()))
+            .chain(self.client_crls.iter().map(|c| c.as_str()))
+            .collect()
+    }
+
+    fn revocation_lists(crlfile: &str) -> Result<Vec<certutils::RevocationList>> {
+        let crls = certutils::read_crls(crlfile)?;
+        for crl in crls.iter() {
+            log::info!(
+                "revocation list from {} revokes {} certificates",
+                crl.issuer(),
+                crl.len()
+            );
+            if crl.is_outdated() {
+                log::warn!(
+                    "revocation list from {} in {} is past its next update",
+                    crl.issuer(),
+                    crlfile
+                );
+            }
+        }
+        Ok(crls)
+    }
+
+    fn client_roots(root_certfile: &str) -> Result<rustls::RootCertStore> {
         let mut store = rustls::RootCertStore { roots: vec![] };
         certutils::read_certs(root_certfile)?
             .iter()
             .try_for_each(|c| store.add(c))?;
-        let auth = rustls::AllowAnyAuthenticatedClient::new(store);
-        self.tls.set_client_certificate_verifier(auth);
-        Ok(self)
+        Ok(store)
     }
 }
 
 pub struct Server {
     config: Config,
+    tls: RwLock<Arc<rustls::ServerConfig>>,
+    connections: ConnectionTracker,
     runtime: Option<tokio::runtime::Runtime>,
 }
 
@@ -76,6 +339,28 @@ impl Server {
     pub fn new(config: Config) -> Result<Server> {
         log::info!("creating server");
 
+        if config.certificates.is_empty() {
+            return Err(string_error::static_err("server needs a certificate"));
+        }
+        if config.client_auth_root.is_none() {
+            if config.authorize.is_some() {
+                return Err(string_error::static_err(
+                    "client authorization needs client authentication",
+                ));
+            }
+            if !config.client_crls.is_empty() {
+                return Err(string_error::static_err(
+                    "revocation lists need client authentication",
+                ));
+            }
+        }
+        if config.proxy_protocol.is_some() && config.trusted_proxies.is_empty() {
+            return Err(string_error::static_err(
+                "proxy protocol needs trusted proxies",
+            ));
+        }
+        let tls = RwLock::new(Arc::new(config.load()?));
+
         let mut runtime = tokio::runtime::Builder::new();
 
         if config.threaded {
@@ -90,18 +375,65 @@ impl Server {
 
         Ok(Server {
             config,
+            tls,
+            connections: ConnectionTracker::default(),
             runtime: Some(runtime),
         })
     }
 
+    // Serve until interrupted or terminated by a signal. Every connection gets its own clone
+    // of the handler, wrap state it should share in an Arc.
     pub fn run<F, R>(&mut self, handler: F) -> Result<()>
     where
-        F: Fn(Stream) -> R + Send + Sync + Copy + 'static,
+        F: Fn(Stream, ConnectionContext) -> R + Send + Sync + Clone + 'static,
+        R: Future + Send,
+    {
+        let listener = self.bind()?;
+        let (_trigger, shutdown) = Shutdown::new();
+        self.run_until(listener, handler, shutdown)
+    }
+
+    // Serve on a thread of its own, until shut down through the handle or by a signal.
+    pub fn spawn<F, R>(mut self, handler: F) -> Result<Handle>
+    where
+        F: Fn(Stream, ConnectionContext) -> R + Send + Sync + Clone + 'static,
+        R: Future + Send,
+    {
+        let listener = self.bind()?;
+        let address = listener.local_addr()?;
+        Ok(Handle::spawn(address, move |shutdown| {
+            self.run_until(listener, handler, shutdown)
+        }))
+    }
+
+    fn bind(&self) -> Result<std::net::TcpListener> {
+        let listener = std::net::TcpListener::bind(format!("0.0.0.0:{}", self.config.port))?;
+        listener.set_nonblocking(true)?;
+        Ok(listener)
+    }
+
+    fn run_until<F, R>(
+        &mut self,
+        listener: std::net::TcpListener,
+        handler: F,
+        shutdown: Shutdown,
+    ) -> Result<()>
+    where
+        F: Fn(Stream, ConnectionContext) -> R + Send + Sync + Clone + 'static,
         R: Future + Send,
     {
         match self.runtime.take() {
             Some(mut rt) => {
-                let res = rt.block_on(async { self.serve_with_graceful_shutdown(handler).await });
+                // tells the handlers the server stopped accepting, through their context
+                let (stop, stopped) = Shutdown::new();
+                let res = rt.block_on(async {
+                    let res = self
+                        .serve_with_graceful_shutdown(listener, handler, shutdown, stopped)
+                        .await;
+                    let _ = stop.broadcast(true);
+                    self.drain().await;
+                    res
+                });
                 self.wait(rt);
                 res.map_err(|e| e.into())
             }
@@ -109,6 +441,62 @@ impl Server {
         }
     }
 
+    // Replace the tls configuration for new connections, existing ones keep the one they
+    // started with. On failure the current configuration stays.
+    fn reload(&self) {
+        match self.config.load() {
+            Ok(tls) => {
+                *self.tls.write().unwrap() = Arc::new(tls);
+                log::info!("reloaded certificates");
+            }
+            Err(e) => {
+                log::error!(
+                    "could not reload certificates, keeping the current ones: {}",
+                    e
+                );
+            }
+        }
+    }
+
+    async fn reload_on_change(&self) -> std::io::Result<()> {
+        let mut hangup = signal(SignalKind::hangup())?;
+        let files = self.config.files();
+        let mut modified = reload::modified(&files);
+
+        loop {
+            match self.config.reload_interval {
+                Some(interval) => {
+                    tokio::select! {
+                        _ = hangup.recv() => {
+                            log::info!("received signal {:?}", SignalKind::hangup());
+                        }
+                        _ = tokio::time::delay_for(interval) => {
+                            if reload::modified(&files) == modified {
+                                continue;
+                            }
+                            log::info!("certificate files changed");
+                        }
+                    }
+                }
+                None => {
+                    hangup.recv().await;
+                    log::info!("received signal {:?}", SignalKind::hangup());
+                }
+            }
+
+            modified = reload::modified(&files);
+            self.reload();
+        }
+    }
+
+    // The resolvers of every reloaded configuration share their staples with the configured one.
+    async fn refresh_staples(&self) -> std::io::Result<()> {
+        loop {
+            tokio::time::delay_for(self.config.ocsp_refresh_interval).await;
+            self.config.certificates.refresh_staples();
+        }
+    }
+
     fn wait(&self, rt: tokio::runtime::Runtime) {
         log::debug!(
             "waiting for {:?} to shut down",
@@ -117,45 +505,201 @@ impl Server {
         rt.shutdown_timeout(self.config.shutdown_timeout);
     }
 
-    async fn serve_with_graceful_shutdown<F, R>(&self, handler: F) -> std::io::Result<()>
+    async fn drain(&self) {
+        let active = self.connections.active();
+        if active == 0 {
+            return;
+        }
+
+        log::info!(
+            "waiting up to {:?} for {} active connections to finish",
+            self.config.drain_timeout,
+            active
+        );
+
+        // a second signal cuts the wait short
+        let cut = tokio::select! {
+            n = self.connections.drain(self.config.drain_timeout) => n,
+            _ = self.wait_for_signal(SignalKind::interrupt()) => self.connections.active(),
+            _ = self.wait_for_signal(SignalKind::terminate()) => self.connections.active(),
+        };
+
+        if cut > 0 {
+            log::warn!("cutting {} connections that did not finish", cut);
+        } else {
+            log::info!("all connections finished");
+        }
+    }
+
+    async fn serve_with_graceful_shutdown<F, R>(
+        &self,
+        listener: std::net::TcpListener,
+        handler: F,
+        mut shutdown: Shutdown,
+        stopped: Shutdown,
+    ) -> std::io::Result<()>
     where
-        F: Fn(Stream) -> R + Send + Sync + Copy + 'static,
+        F: Fn(Stream, ConnectionContext) -> R + Send + Sync + Clone + 'static,
         R: Future + Send,
     {
         tokio::select! {
-            x = self.serve(handler) => x,
+            x = self.serve(listener, handler, stopped) => x,
+            x = self.reload_on_change() => x,
+            x = self.refresh_staples() => x,
+            _ = shutdown.wait() => {
+                log::info!("shutting down");
+                Ok(())
+            }
             x = self.wait_for_signal(SignalKind::interrupt()) => x,
             x = self.wait_for_signal(SignalKind::terminate()) => x,
         }
     }
 
-    async fn serve<F, R>(&self, handler: F) -> std::io::Result<()>
+    async fn serve<F, R>(
+        &self,
+        listener: std::net::TcpListener,
+        handler: F,
+        stopped: Shutdown,
+    ) -> std::io::Result<()>
     where
-        F: Fn(Stream) -> R + Send + Sync + Copy + 'static,
+        F: Fn(Stream, ConnectionContext) -> R + Send + Sync + Clone + 'static,
         R: Future + Send,
     {
-        let listen_address = format!("0.0.0.0:{}", self.config.port);
-
-        let cfg = self.config.tls.clone();
-        let acceptor = TlsAcceptor::from(Arc::new(cfg));
-
-        log::info!("listening on {:?}", listen_address);
-        let mut listener = TcpListener::bind(listen_address).await?;
+        let mut listener = TcpListener::from_std(listener)?;
+        let local_address = listener.local_addr()?;
+        log::info!("listening on {}", local_address);
+        let port = local_address.port().to_string();
+        let metrics = ListenerMetrics::new(&self.config.metrics, &port);
+        let admission = Admission::new(self.config.limits, &self.config.metrics, &port);
 
         loop {
+            admission.ready().await;
             let (stream, remote_address) = listener.accept().await?;
-            log::info!("accepted connection from {}", remote_address);
+            let mut context =
+                ConnectionContext::new(remote_address, local_address, stopped.clone());
+            if let Some(lifetime) = self.config.max_lifetime {
+                context = context.with_max_lifetime(lifetime);
+            }
+            // behind a proxy the source is only known once its header is read
+            let admitted = match self.config.proxy_protocol {
+                Some(_) => admission.reserve(),
+                None => admission.admit(remote_address.ip()),
+            };
+            let mut ticket = match admitted {
+                Ok(ticket) => ticket,
+                Err(refusal) => {
+                    log::warn!("closing connection from {}: {}", remote_address, refusal);
+                    reject(
+                        &self.config.on_rejected,
+                        &context,
+                        Rejection::Refused(refusal),
+                    );
+                    continue;
+                }
+            };
+            log::info!(
+                "accepted connection {} from {}",
+                context.id(),
+                remote_address
+            );
 
-            let acceptor = acceptor.clone();
+            let acceptor = TlsAcceptor::from(self.tls.read().unwrap().clone());
+            let proxy_protocol = self.config.proxy_protocol;
+            let trusted = self.config.trusted_proxies.contains(&remote_address.ip());
+            let handshake_timeout = self.config.handshake_timeout;
+            let handshake_deadline = tokio::time::Instant::now() + handshake_timeout;
+            let guard = self.connections.track();
+            let open = metrics.connections.accept();
+            let metrics = metrics.clone();
+            let admission = admission.clone();
+            let on_rejected = self.config.on_rejected.clone();
+            let handler = handler.clone();
 
             tokio::spawn(async move {
-                match acceptor.accept(stream).await {
-                    Ok(stream) => {
-                        handler(stream).await;
-                        log::info!("closing connection from {}", remote_address);
+                let _guard = guard;
+                let _open = open;
+                let header = read_proxy_header(stream, proxy_protocol, trusted);
+                let stream = match timeout_at(handshake_deadline, header).await {
+                    Ok(Ok(stream)) => stream,
+                    Ok(Err(e)) => {
+                        log::warn!("not accepted from {}: {}", remote_address, e);
+                        metrics.handshake_failed("proxy_header");
+                        admission.failed(remote_address.ip());
+                        let rejection = Rejection::Failed(format!("bad proxy header: {}", e));
+                        reject(&on_rejected, &context, rejection);
+                        return;
+                    }
+                    Err(_) => {
+                        log::warn!(
+                            "no proxy header from {} within {:?}",
+                            remote_address,
+                            handshake_timeout
+                        );
+                        metrics.handshake_failed("timeout");
+                        admission.failed(remote_address.ip());
+                        reject(&on_rejected, &context, Rejection::TimedOut);
+                        return;
+                    }
+                };
+                let stream = stream.with_counters(metrics.received.clone(), metrics.sent.clone());
+                let remote_address = stream.peer_addr().unwrap_or(remote_address);
+                let context = context
+                    .with_addresses(remote_address, stream.local_addr().unwrap_or(local_address));
+                if let Err(refusal) = ticket.admit_from(remote_address.ip()) {
+                    log::warn!("closing connection from {}: {}", remote_address, refusal);
+                    reject(&on_rejected, &context, Rejection::Refused(refusal));
+                    return;
+                }
+                let id = context.id();
+
+                let started = std::time::Instant::now();
+                match timeout_at(handshake_deadline, acceptor.accept(stream)).await {
+                    Ok(Ok(mut stream)) => {
+                        let duration = started.elapsed();
+                        metrics.handshake_duration.observe_duration(duration);
+                        stream.get_mut().0.set_handshake_duration(duration);
+
+                        let deadline = context.deadline();
+                        let handled = handler(stream, context);
+                        match deadline {
+                            Some(deadline) => {
+                                let deadline = tokio::time::Instant::from_std(deadline);
+                                if timeout_at(deadline, handled).await.is_err() {
+                                    log::warn!(
+                                        "cutting connection {} from {}, it reached its maximum lifetime",
+                                        id,
+                                        remote_address
+                                    );
+                                    return;
+                                }
+                            }
+                            None => {
+                                handled.await;
+                            }
+                        }
+                        log::info!("closing connection {} from {}", id, remote_address);
+                    }
+                    Ok(Err(e)) => {
+                        log::warn!(
+                            "not accepted connection {} from {}: {}",
+                            id,
+                            remote_address,
+                            e
+                        );
+                        metrics.handshake_failed(failure_reason(&e));
+                        ticket.failed();
+                        reject(&on_rejected, &context, Rejection::Failed(e.to_string()));
                     }
-                    Err(e) => {
-                        log::warn!("not accepted: {}", e);
+                    Err(_) => {
+                        log::warn!(
+                            "handshake with connection {} from {} timed out after {:?}",
+                            id,
+                            remote_address,
+                            handshake_timeout
+                        );
+                        metrics.handshake_failed("timeout");
+                        ticket.failed();
+                        reject(&on_rejected, &context, Rejection::TimedOut);
                     }
                 }
             });
@@ -170,6 +714,45 @@ impl Server {
     }
 }
 
+async fn read_proxy_header(
+    mut stream: tokio::net::TcpStream,
+    mode: Option<ProxyProtocol>,
+    trusted: bool,
+) -> std::io::Result<Connection> {
+    let expected = match mode {
+        Some(ProxyProtocol::Required) if !trusted => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "not a trusted proxy",
+            ));
+        }
+        Some(ProxyProtocol::Required) => true,
+        // whatever others send goes to the handshake, where a header fails
+        Some(ProxyProtocol::Optional) if !trusted => false,
+        Some(ProxyProtocol::Optional) => {
+            // a proxy header and a tls handshake can be told apart by their first byte
+            let mut first = [0u8; 1];
+            let n = stream.peek(&mut first).await?;
+            proxy_protocol::starts_header(&first[..n])
+        }
+        None => false,
+    };
+
+    if !expected {
+        return Ok(Connection::new(stream, None));
+    }
+
+    let header = proxy_protocol::read(&mut stream).await?;
+    if let Some(h) = &header {
+        log::info!(
+            "connection from {} is proxied for {}",
+            stream.peer_addr()?,
+            h.source
+        );
+    }
+    Ok(Connection::new(stream, header))
+}
+
 impl Drop for Server {
     fn drop(&mut self) {
         if let Some(rt) = self.runtime.take() {
@@ -0,0 +1,95 @@
+use rustls::TLSError;
+use tcp_server::{ConnectionMetrics, Counter, Histogram, Metrics};
+
+// What a listener counts on top of the connections: handshakes and the bytes going through.
+#[derive(Clone)]
+pub struct ListenerMetrics {
+    metrics: Metrics,
+    listener: String,
+    pub connections: ConnectionMetrics,
+    pub handshake_duration: Histogram,
+    pub received: Counter,
+    pub sent: Counter,
+}
+
+impl ListenerMetrics {
+    pub fn new(metrics: &Metrics, listener: &str) -> ListenerMetrics {
+        let labels = [("listener", listener)];
+        ListenerMetrics {
+            metrics: metrics.clone(),
+            listener: listener.to_string(),
+            connections: ConnectionMetrics::new(metrics, listener),
+            handshake_duration: metrics.histogram(
+                "tls_handshake_duration_seconds",
+                "Time taken by successful TLS handshakes.",
+                &labels,
+            ),
+            received: metrics.counter(
+                "bytes_received_total",
+                "Bytes received from clients, as sent over the wire.",
+                &labels,
+            ),
+            sent: metrics.counter(
+                "bytes_sent_total",
+                "Bytes sent to clients, as sent over the wire.",
+                &labels,
+            ),
+        }
+    }
+
+    pub fn handshake_failed(&self, reason: &str) {
+        self.metrics
+            .counter(
+                "tls_handshake_failures_total",
+                "Connections that did not complete the TLS handshake, by reason.",
+                &[("listener", &self.listener), ("reason", reason)],
+            )
+            .inc();
+    }
+}
+
+// Why a handshake failed, in a few broad categories to keep the number of series small.
+pub fn failure_reason(error: &std::io::Error) -> &'static str {
+    match error.get_ref().and_then(|e| e.downcast_ref::<TLSError>()) {
+        Some(TLSError::AlertReceived(_)) => "alert",
+        Some(TLSError::NoCertificatesPresented) | Some(TLSError::WebPKIError(_)) => {
+            "bad_certificate"
+        }
+        Some(TLSError::General(_)) => "rejected",
+        Some(TLSError::PeerIncompatibleError(_)) | Some(TLSError::NoApplicationProtocol) => {
+            "incompatible"
+        }
+        Some(_) => "protocol",
+        None if error.kind() == std::io::ErrorKind::UnexpectedEof => "eof",
+        None => "io",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Error, ErrorKind};
+
+    #[test]
+    fn tells_failure_reasons() {
+        let tls = |e| Error::new(ErrorKind::InvalidData, e);
+
+        assert_eq!(
+            "rejected",
+            failure_reason(&tls(TLSError::General("revoked".to_string())))
+        );
+        assert_eq!(
+            "bad_certificate",
+            failure_reason(&tls(TLSError::NoCertificatesPresented))
+        );
+        assert_eq!("protocol", failure_reason(&tls(TLSError::CorruptMessage)));
+        assert_eq!(
+            "eof",
+            failure_reason(&Error::from(ErrorKind::UnexpectedEof))
+        );
+        assert_eq!(
+            "io",
+            failure_reason(&Error::from(ErrorKind::ConnectionReset))
+        );
+    }
+}
@@ -0,0 +1,32 @@
+use std::sync::Arc;
+use tcp_server::{ConnectionContext, Refusal};
+
+// Why a connection never made it to the handler.
+#[derive(Debug)]
+pub enum Rejection {
+    // over one of the connection limits
+    Refused(Refusal),
+    // a bad PROXY protocol header or a failed handshake, which includes failing authentication
+    Failed(String),
+    // no PROXY protocol header or finished handshake within the handshake timeout
+    TimedOut,
+}
+
+impl std::fmt::Display for Rejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rejection::Refused(refusal) => write!(f, "{}", refusal),
+            Rejection::Failed(e) => write!(f, "{}", e),
+            Rejection::TimedOut => write!(f, "handshake timeout"),
+        }
+    }
+}
+
+// Told about every connection rejected before the handler, see Config::with_rejection_handler.
+pub type OnRejected = Arc<dyn Fn(&ConnectionContext, &Rejection) + Send + Sync>;
+
+pub fn reject(on_rejected: &Option<OnRejected>, context: &ConnectionContext, rejection: Rejection) {
+    if let Some(on_rejected) = on_rejected {
+        on_rejected(context, &rejection);
+    }
+}
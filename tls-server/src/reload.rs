@@ -0,0 +1,9 @@
+use std::time::SystemTime;
+
+// The modification times of the files, None for files that can not be inspected.
+pub fn modified(files: &[&str]) -> Vec<Option<SystemTime>> {
+    files
+        .iter()
+        .map(|f| std::fs::metadata(f).and_then(|m| m.modified()).ok())
+        .collect()
+}
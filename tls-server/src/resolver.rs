@@ -0,0 +1,198 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use super::Result;
+
+// Picks the certificate by the server name the client sent, falling back to the default
+// certificate for unknown or absent names. Without a default the handshake is aborted.
+#[derive(Clone, Default)]
+pub struct CertificateResolver {
+    by_name: HashMap<String, rustls::sign::CertifiedKey>,
+    default: Option<rustls::sign::CertifiedKey>,
+    files: Vec<CertificateFiles>,
+    // the OCSP response to staple to the default certificate
+    ocspfile: Option<String>,
+    // OCSP responses to staple, by the DER encoded certificate they are about, shared with the
+    // resolvers reloaded from this one
+    staples: Arc<RwLock<HashMap<Vec<u8>, certutils::OcspResponse>>>,
+}
+
+#[derive(Clone)]
+struct CertificateFiles {
+    name: Option<String>,
+    certfile: String,
+    keyfile: String,
+}
+
+impl CertificateResolver {
+    pub fn set_default(&mut self, certfile: &str, keyfile: &str) -> Result<()> {
+        let key = CertificateResolver::load(certfile, keyfile)?;
+        key.cross_check_end_entity_cert(None)?;
+        self.default = Some(key);
+        self.remember(None, certfile, keyfile);
+        Ok(())
+    }
+
+    // Staple the OCSP response in this file to the default certificate, from the next refresh of
+    // the staples on.
+    pub fn set_default_ocsp_response_file(&mut self, ocspfile: &str) {
+        self.ocspfile = Some(ocspfile.to_string());
+    }
+
+    pub fn add(&mut self, name: &str, certfile: &str, keyfile: &str) -> Result<()> {
+        let dns_name = webpki::DNSNameRef::try_from_ascii_str(name)
+            .map_err(|_| string_error::into_err(format!("invalid server name {}", name)))?;
+
+        let key = CertificateResolver::load(certfile, keyfile)?;
+        key.cross_check_end_entity_cert(Some(dns_name))?;
+        self.by_name.insert(name.to_lowercase(), key);
+        self.remember(Some(name.to_lowercase()), certfile, keyfile);
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_name.is_empty() && self.default.is_none()
+    }
+
+    // A new resolver with every certificate and key read again from its files.
+    pub fn reload(&self) -> Result<CertificateResolver> {
+        let mut resolver = CertificateResolver {
+            staples: self.staples.clone(),
+            ocspfile: self.ocspfile.clone(),
+            ..CertificateResolver::default()
+        };
+        for f in self.files.iter() {
+            match &f.name {
+                Some(name) => resolver.add(name, &f.certfile, &f.keyfile)?,
+                None => resolver.set_default(&f.certfile, &f.keyfile)?,
+            }
+        }
+        resolver.refresh_staples();
+        Ok(resolver)
+    }
+
+    // Read the OCSP response again. A response that can not be read, has expired, is not valid
+    // yet or is not about the default certificate is not stapled. Telling that takes the issuer,
+    // which must follow the certificate in its file.
+    pub fn refresh_staples(&self) {
+        let staples = self.read_staple().into_iter().collect();
+        *self.staples.write().unwrap() = staples;
+    }
+
+    fn read_staple(&self) -> Option<(Vec<u8>, certutils::OcspResponse)> {
+        let ocspfile = self.ocspfile.as_ref()?;
+        let certfile = &self.files.iter().find(|f| f.name.is_none())?.certfile;
+
+        // as the certificate may have changed since it was loaded, read it again too
+        let certs = match certutils::read_certs(certfile) {
+            Ok(certs) if !certs.is_empty() => certs,
+            _ => {
+                log::warn!("not stapling {}, could not read {}", ocspfile, certfile);
+                return None;
+            }
+        };
+        let cert = certs[0].clone();
+        let issuer = match certs.get(1) {
+            Some(issuer) => issuer,
+            None => {
+                log::warn!(
+                    "not stapling {}, {} does not hold the issuer to check it against",
+                    ocspfile,
+                    certfile
+                );
+                return None;
+            }
+        };
+
+        let response = match certutils::read_ocsp_response(ocspfile) {
+            Ok(response) => response,
+            Err(e) => {
+                log::warn!("not stapling {}: {}", ocspfile, e);
+                return None;
+            }
+        };
+        if !response.matches(&cert, issuer) {
+            log::warn!(
+                "not stapling {}, it is not about the certificate in {}",
+                ocspfile,
+                certfile
+            );
+            return None;
+        }
+        if response.is_expired() {
+            log::warn!("not stapling {}, it has expired", ocspfile);
+            return None;
+        }
+        if response.is_premature() {
+            log::warn!("not stapling {}, it is not valid yet", ocspfile);
+            return None;
+        }
+        if response.status != certutils::CertStatus::Good {
+            log::warn!(
+                "stapling {}, it says the certificate in {} is {:?}",
+                ocspfile,
+                certfile,
+                response.status
+            );
+        }
+        Some((cert.0, response))
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = &str> {
+        self.files
+            .iter()
+            .flat_map(|f| vec![f.certfile.as_str(), f.keyfile.as_str()])
+    }
+
+    fn remember(&mut self, name: Option<String>, certfile: &str, keyfile: &str) {
+        self.files.retain(|f| f.name != name);
+        self.files.push(CertificateFiles {
+            name,
+            certfile: certfile.to_string(),
+            keyfile: keyfile.to_string(),
+        });
+    }
+
+    // The key with its OCSP response stapled, if there is one that has not expired since it
+    // was read.
+    fn staple(&self, key: &rustls::sign::CertifiedKey) -> rustls::sign::CertifiedKey {
+        let mut key = key.clone();
+        if let Some(response) = key
+            .cert
+            .first()
+            .and_then(|c| self.staples.read().unwrap().get(&c.0).cloned())
+        {
+            if !response.is_expired() {
+                key.ocsp = Some(response.der().to_vec());
+            }
+        }
+        key
+    }
+
+    fn load(certfile: &str, keyfile: &str) -> Result<rustls::sign::CertifiedKey> {
+        let cert = certutils::read_certs(certfile)?;
+        let key = certutils::read_key(keyfile)?;
+        let key = rustls::sign::any_supported_type(&key)
+            .map_err(|_| string_error::static_err("invalid private key"))?;
+        Ok(rustls::sign::CertifiedKey::new(cert, Arc::new(key)))
+    }
+}
+
+impl rustls::ResolvesServerCert for CertificateResolver {
+    fn resolve(&self, client_hello: rustls::ClientHello) -> Option<rustls::sign::CertifiedKey> {
+        let name = client_hello.server_name().map(|n| {
+            let n: &str = n.into();
+            n.to_lowercase()
+        });
+
+        match name.as_ref().and_then(|n| self.by_name.get(n)) {
+            Some(key) => Some(self.staple(key)),
+            None => {
+                if self.default.is_none() {
+                    log::warn!("no certificate for server name {:?}", name);
+                }
+                self.default.as_ref().map(|key| self.staple(key))
+            }
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use certutils::CertificateInfo;
+use rustls::{CipherSuite, ProtocolVersion, Session as _};
+use std::time::Duration;
+
+use super::Stream;
+
+// What was negotiated in the handshake of a connection, and who is on the other side.
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub version: Option<ProtocolVersion>,
+    pub cipher_suite: Option<CipherSuite>,
+    pub server_name: Option<String>,
+    pub alpn_protocol: Option<Vec<u8>>,
+    pub handshake_duration: Option<Duration>,
+    // verified by client authentication, the client's own certificate first
+    pub peer_certificates: Vec<CertificateInfo>,
+}
+
+impl Session {
+    pub fn new(stream: &Stream) -> Session {
+        let (connection, session) = stream.get_ref();
+
+        let peer_certificates = session
+            .get_peer_certificates()
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|c| match certutils::certificate_info(c) {
+                Ok(info) => Some(info),
+                Err(e) => {
+                    log::warn!("could not parse peer certificate: {}", e);
+                    None
+                }
+            })
+            .collect();
+
+        Session {
+            version: session.get_protocol_version(),
+            cipher_suite: session.get_negotiated_ciphersuite().map(|c| c.suite),
+            server_name: session.get_sni_hostname().map(|s| s.to_string()),
+            alpn_protocol: session.get_alpn_protocol().map(|p| p.to_vec()),
+            handshake_duration: connection.handshake_duration(),
+            peer_certificates,
+        }
+    }
+
+    // The certificate the client authenticated with, if any.
+    pub fn peer_certificate(&self) -> Option<&CertificateInfo> {
+        self.peer_certificates.first()
+    }
+
+    // The protocol version as OpenSSL names it, i.e. "TLSv1.3".
+    pub fn version_name(&self) -> Option<String> {
+        self.version.map(version_name)
+    }
+
+    pub fn cipher_suite_name(&self) -> Option<String> {
+        self.cipher_suite.map(|c| format!("{:?}", c))
+    }
+}
+
+fn version_name(version: ProtocolVersion) -> String {
+    match version {
+        ProtocolVersion::SSLv2 => "SSLv2".to_string(),
+        ProtocolVersion::SSLv3 => "SSLv3".to_string(),
+        ProtocolVersion::TLSv1_0 => "TLSv1".to_string(),
+        ProtocolVersion::TLSv1_1 => "TLSv1.1".to_string(),
+        ProtocolVersion::TLSv1_2 => "TLSv1.2".to_string(),
+        ProtocolVersion::TLSv1_3 => "TLSv1.3".to_string(),
+        other => format!("{:?}", other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_versions_like_openssl() {
+        assert_eq!("TLSv1.2", version_name(ProtocolVersion::TLSv1_2));
+        assert_eq!("TLSv1.3", version_name(ProtocolVersion::TLSv1_3));
+    }
+}
@@ -0,0 +1,94 @@
+use certutils::{CertificateInfo, RevocationList};
+use rustls::{
+    AllowAnyAuthenticatedClient, Certificate, ClientCertVerified, ClientCertVerifier,
+    DistinguishedNames, RootCertStore, TLSError,
+};
+use std::sync::Arc;
+
+// Decides whether an authenticated client may connect, see Config::with_client_authorization.
+pub type Authorize = Arc<dyn Fn(&CertificateInfo) -> std::result::Result<(), String> + Send + Sync>;
+
+// Verifies the client certificate chains to the roots and is not revoked, and then asks whether
+// the client is authorized.
+pub struct ClientVerifier {
+    roots: Arc<dyn ClientCertVerifier>,
+    crls: Vec<RevocationList>,
+    authorize: Option<Authorize>,
+}
+
+impl ClientVerifier {
+    pub fn new(
+        roots: RootCertStore,
+        crls: Vec<RevocationList>,
+        authorize: Option<Authorize>,
+    ) -> ClientVerifier {
+        ClientVerifier {
+            roots: AllowAnyAuthenticatedClient::new(roots),
+            crls,
+            authorize,
+        }
+    }
+
+    fn check_revocation(&self, chain: &[Certificate]) -> Result<(), TLSError> {
+        for cert in chain.iter() {
+            if let Some(crl) = self.crls.iter().find(|crl| crl.is_revoked(cert)) {
+                let subject = certutils::certificate_info(cert)
+                    .map(|c| format!("{} (serial {})", c.subject, c.serial))
+                    .unwrap_or_else(|_| "unparsable certificate".to_string());
+                log::warn!(
+                    target: "audit",
+                    "rejected client, {} was revoked by {}",
+                    subject,
+                    crl.issuer()
+                );
+                return Err(TLSError::General(format!("{} was revoked", subject)));
+            }
+        }
+        Ok(())
+    }
+
+    fn authorize(&self, cert: &Certificate) -> Result<(), TLSError> {
+        let authorize = match &self.authorize {
+            Some(authorize) => authorize,
+            None => return Ok(()),
+        };
+
+        let client =
+            certutils::certificate_info(cert).map_err(|e| TLSError::General(format!("{}", e)))?;
+
+        authorize(&client).map_err(|reason| {
+            log::warn!(
+                target: "audit",
+                "rejected client {} (serial {}, fingerprint {}): {}",
+                client.subject,
+                client.serial,
+                client.fingerprint,
+                reason
+            );
+            TLSError::General(format!(
+                "client {} is not authorized: {}",
+                client.subject, reason
+            ))
+        })
+    }
+}
+
+impl ClientCertVerifier for ClientVerifier {
+    fn client_auth_root_subjects(
+        &self,
+        sni: Option<&webpki::DNSName>,
+    ) -> Option<DistinguishedNames> {
+        self.roots.client_auth_root_subjects(sni)
+    }
+
+    fn verify_client_cert(
+        &self,
+        presented_certs: &[Certificate],
+        sni: Option<&webpki::DNSName>,
+    ) -> Result<ClientCertVerified, TLSError> {
+        let verified = self.roots.verify_client_cert(presented_certs, sni)?;
+        self.check_revocation(presented_certs)?;
+        self.authorize(&presented_certs[0])?;
+        Ok(verified)
+    }
+}
@@ -0,0 +1,77 @@
+mod common;
+
+use common::{read_all, Certificates};
+use std::sync::{Arc, Mutex};
+use tokio::io::AsyncWriteExt;
+
+async fn greet(mut stream: tls_server::Stream, _context: tls_server::ConnectionContext) {
+    let _ = stream.write_all(b"hello").await;
+    let _ = stream.shutdown().await;
+}
+
+// Only lets in clients named alice, and keeps why others were rejected.
+fn spawn(certs: &Certificates) -> (tls_server::Handle, Arc<Mutex<Vec<String>>>) {
+    let rejections = Arc::new(Mutex::new(Vec::new()));
+    let rejected = rejections.clone();
+    let (cert, key) = certs.issue("server");
+    let mut config = tls_server::Config::new(0);
+    config
+        .with_certificate_and_key_files(&cert, &key)
+        .expect("certificate")
+        .with_client_authentication(&certs.root())
+        .expect("root")
+        .with_client_authorization(|client| match client.common_name.as_deref() {
+            Some("alice") => Ok(()),
+            _ => Err("not alice".to_string()),
+        })
+        .with_rejection_handler(move |_, rejection| {
+            rejected.lock().unwrap().push(rejection.to_string());
+        });
+
+    let handle = tls_server::Server::new(config)
+        .expect("server")
+        .spawn(greet)
+        .expect("spawn");
+    (handle, rejections)
+}
+
+#[tokio::test]
+async fn lets_in_authorized_clients() {
+    let certs = Certificates::new();
+    let (handle, rejections) = spawn(&certs);
+
+    let greeting = read_all(&certs.connector(Some("alice")), handle.address()).await;
+    assert_eq!("hello", greeting.unwrap());
+    assert!(rejections.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn keeps_out_other_clients() {
+    let certs = Certificates::new();
+    let (handle, rejections) = spawn(&certs);
+
+    read_all(&certs.connector(Some("bob")), handle.address())
+        .await
+        .expect_err("bob is not authorized");
+
+    // the server handles connections in order, so surely knows about bob by the time alice is in
+    read_all(&certs.connector(Some("alice")), handle.address())
+        .await
+        .expect("alice is authorized");
+    let rejections = rejections.lock().unwrap();
+    assert_eq!(1, rejections.len());
+    assert!(rejections[0].ends_with("not alice"), "{}", rejections[0]);
+}
+
+#[test]
+fn needs_client_authentication() {
+    let certs = Certificates::new();
+    let (cert, key) = certs.issue("server");
+    let mut config = tls_server::Config::new(0);
+    config
+        .with_certificate_and_key_files(&cert, &key)
+        .expect("certificate")
+        .with_client_authorization(|_| Ok(()));
+
+    assert!(tls_server::Server::new(config).is_err());
+}
@@ -0,0 +1,220 @@
+// not every test uses every helper
+#![allow(dead_code)]
+
+extern crate katey_client;
+extern crate rcgen;
+extern crate ring;
+extern crate tempfile;
+extern crate x509_parser;
+
+use rcgen::{BasicConstraints, Certificate, CertificateParams, DnType, IsCa};
+use std::path::Path;
+
+// A root and certificates signed by it, written as .pem files to a temporary directory.
+pub struct Certificates {
+    dir: tempfile::TempDir,
+    root: Certificate,
+}
+
+impl Certificates {
+    pub fn new() -> Certificates {
+        let mut params = CertificateParams::new(vec!["localhost".to_string()]);
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let root = Certificate::from_params(params).expect("root");
+
+        let certs = Certificates {
+            dir: tempfile::TempDir::new().expect("tempdir"),
+            root,
+        };
+        certs.write("root-cert.pem", &certs.root.serialize_pem().expect("pem"));
+        certs
+    }
+
+    // Issue a certificate for localhost, with the name as its common name and alternative name.
+    // Returns the paths to the certificate and key.
+    pub fn issue(&self, name: &str) -> (String, String) {
+        let mut params = CertificateParams::new(vec!["localhost".to_string(), name.to_string()]);
+        params.distinguished_name.push(DnType::CommonName, name);
+        let cert = Certificate::from_params(params).expect("certificate");
+
+        let pem = cert.serialize_pem_with_signer(&self.root).expect("pem");
+        let certfile = self.write(&format!("{}-cert.pem", name), &pem);
+        let keyfile = self.write(
+            &format!("{}-key.pem", name),
+            &cert.serialize_private_key_pem(),
+        );
+        (certfile, keyfile)
+    }
+
+    // Write the certificate in the file followed by the root, which issued it, as the chain a
+    // server presents.
+    pub fn chain(&self, filename: &str, certfile: &str) -> String {
+        let cert = std::fs::read_to_string(certfile).expect("read");
+        let root = std::fs::read_to_string(self.root()).expect("read");
+        self.write(filename, &(cert + &root))
+    }
+
+    pub fn root(&self) -> String {
+        self.path("root-cert.pem")
+    }
+
+    pub fn path(&self, filename: &str) -> String {
+        self.dir.path().join(filename).to_str().unwrap().to_string()
+    }
+
+    pub fn write(&self, filename: &str, content: &str) -> String {
+        let path = self.path(filename);
+        std::fs::write(Path::new(&path), content).expect("write");
+        path
+    }
+
+    // Write a revocation list from the root revoking the given certificate files, in DER format.
+    // It is not signed, that is not checked.
+    pub fn revoke(&self, filename: &str, certfiles: &[&str]) -> String {
+        let root = self.root.serialize_der().expect("der");
+        let (_, root) = x509_parser::parse_x509_certificate(&root).expect("root");
+
+        let revoked: Vec<u8> = certfiles
+            .iter()
+            .flat_map(|f| {
+                let pem = std::fs::read_to_string(f).expect("read");
+                let (_, pem) = x509_parser::pem::parse_x509_pem(pem.as_bytes()).expect("pem");
+                let cert = pem.parse_x509().expect("certificate");
+                let serial = der(0x02, cert.tbs_certificate.raw_serial());
+                der(0x30, &[serial, utc_time()].concat())
+            })
+            .collect();
+
+        let algorithm = der(0x30, &der(0x06, &ECDSA_WITH_SHA256));
+        let mut tbs = [
+            der(0x02, &[1]),
+            algorithm.clone(),
+            root.subject().as_raw().to_vec(),
+            utc_time(),
+        ]
+        .concat();
+        // an empty list is left out altogether
+        if !revoked.is_empty() {
+            tbs.extend(der(0x30, &revoked));
+        }
+        let tbs = der(0x30, &tbs);
+        let crl = der(0x30, &[tbs, algorithm, der(0x03, &[0, 0])].concat());
+
+        let path = self.path(filename);
+        std::fs::write(&path, crl).expect("write");
+        path
+    }
+
+    // Write an OCSP response from the root about the certificate in the file, in DER format, valid
+    // until the next update as YYYYMMDDHHMMSSZ. It is not signed, the server does not check that.
+    pub fn ocsp(&self, filename: &str, certfile: &str, next_update: &str) -> String {
+        let pem = std::fs::read_to_string(certfile).expect("read");
+        let (_, pem) = x509_parser::pem::parse_x509_pem(pem.as_bytes()).expect("pem");
+        let cert = pem.parse_x509().expect("certificate");
+        let issuer_name_hash = ring::digest::digest(&ring::digest::SHA256, cert.issuer().as_raw());
+        let root = self.root.serialize_der().expect("der");
+        let (_, root) = x509_parser::parse_x509_certificate(&root).expect("root");
+        let issuer_key_hash = ring::digest::digest(
+            &ring::digest::SHA256,
+            root.tbs_certificate.subject_pki.subject_public_key.data,
+        );
+
+        let cert_id = der(
+            0x30,
+            &[
+                der(0x30, &der(0x06, &SHA256)),
+                der(0x04, issuer_name_hash.as_ref()),
+                der(0x04, issuer_key_hash.as_ref()),
+                der(0x02, cert.tbs_certificate.raw_serial()),
+            ]
+            .concat(),
+        );
+        let single = [
+            cert_id,
+            der(0x80, &[]), // good
+            der(0x18, b"20200101000000Z"),
+            der(0xa0, &der(0x18, next_update.as_bytes())),
+        ]
+        .concat();
+        let data = der(
+            0x30,
+            &[
+                der(0xa2, &der(0x04, &[0; 20])),
+                der(0x18, b"20200101000000Z"),
+                der(0x30, &der(0x30, &single)),
+            ]
+            .concat(),
+        );
+        let algorithm = der(0x30, &der(0x06, &ECDSA_WITH_SHA256));
+        let basic = der(0x30, &[data, algorithm, der(0x03, &[0, 0])].concat());
+        let bytes = der(0x30, &[der(0x06, &OCSP_BASIC), der(0x04, &basic)].concat());
+        let response = der(0x30, &[der(0x0a, &[0]), der(0xa0, &bytes)].concat());
+
+        let path = self.path(filename);
+        std::fs::write(&path, response).expect("write");
+        path
+    }
+
+    // A connector trusting the root, authenticating with a newly issued certificate if given a
+    // name.
+    pub fn connector(&self, client: Option<&str>) -> katey_client::Connector {
+        match client {
+            Some(name) => {
+                let (cert, key) = self.issue(name);
+                self.client_connector(&cert, &key)
+            }
+            None => {
+                let mut connector = katey_client::Connector::new();
+                connector.with_root(&self.root()).expect("root");
+                connector
+            }
+        }
+    }
+
+    // A connector trusting the root, authenticating with the given certificate.
+    pub fn client_connector(&self, certfile: &str, keyfile: &str) -> katey_client::Connector {
+        let mut connector = self.connector(None);
+        connector
+            .with_certificate_and_key_files(certfile, keyfile)
+            .expect("client certificate");
+        connector
+    }
+}
+
+const ECDSA_WITH_SHA256: [u8; 8] = [0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+const SHA256: [u8; 9] = [0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01];
+const OCSP_BASIC: [u8; 9] = [0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01, 0x01];
+
+fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+    let len = content.len();
+    let mut out = vec![tag];
+    if len < 0x80 {
+        out.push(len as u8);
+    } else if len < 0x100 {
+        out.extend_from_slice(&[0x81, len as u8]);
+    } else {
+        out.extend_from_slice(&[0x82, (len >> 8) as u8, len as u8]);
+    }
+    out.extend_from_slice(content);
+    out
+}
+
+fn utc_time() -> Vec<u8> {
+    der(0x17, b"200101000000Z")
+}
+
+// Connect to a local server and read everything it sends.
+pub async fn read_all(
+    connector: &katey_client::Connector,
+    address: std::net::SocketAddr,
+) -> std::io::Result<String> {
+    use tokio::io::AsyncReadExt;
+
+    let address = format!("localhost:{}", address.port());
+    let stream = tokio::net::TcpStream::connect(&address).await?;
+    let mut stream = connector.connect(&address, stream).await?;
+
+    let mut buf = String::new();
+    stream.read_to_string(&mut buf).await?;
+    Ok(buf)
+}
@@ -0,0 +1,265 @@
+mod common;
+
+use common::{read_all, Certificates};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+// Greets, then keeps the connection open until the server stops.
+async fn greet_and_hold(mut stream: tls_server::Stream, context: tls_server::ConnectionContext) {
+    let _ = stream.write_all(b"hello").await;
+    let _ = stream.flush().await;
+    context.shutdown().wait().await;
+}
+
+// Connects as if through a proxy, which says the connection is from this source.
+async fn connect_from(
+    connector: &katey_client::Connector,
+    address: std::net::SocketAddr,
+    source: &str,
+) -> std::io::Result<katey_client::Stream> {
+    let address = format!("localhost:{}", address.port());
+    let mut stream = tokio::net::TcpStream::connect(&address).await?;
+    let header = format!("PROXY TCP4 {} 127.0.0.1 40000 5000\r\n", source);
+    stream.write_all(header.as_bytes()).await?;
+    let mut stream = connector.connect(&address, stream).await?;
+
+    let mut greeting = [0u8; 5];
+    stream.read_exact(&mut greeting).await?;
+    Ok(stream)
+}
+
+// Where the test connections come from, as the proxy.
+fn localhost() -> Vec<std::net::IpAddr> {
+    vec!["127.0.0.1".parse().unwrap(), "::1".parse().unwrap()]
+}
+
+#[tokio::test]
+async fn closes_connections_over_the_limit() {
+    let certs = Certificates::new();
+    let (cert, key) = certs.issue("server");
+    let metrics = tls_server::Metrics::default();
+
+    let mut config = tls_server::Config::new(0);
+    config
+        .with_certificate_and_key_files(&cert, &key)
+        .expect("certificate")
+        .with_max_connections(1)
+        .with_over_limit(tls_server::OverLimit::Close)
+        .with_metrics(metrics.clone());
+    let handle = tls_server::Server::new(config)
+        .expect("server")
+        .spawn(greet_and_hold)
+        .expect("spawn");
+    let port = handle.address().port().to_string();
+
+    let address = format!("localhost:{}", handle.address().port());
+    let connector = certs.connector(None);
+    let stream = tokio::net::TcpStream::connect(&address)
+        .await
+        .expect("connect");
+    let _first = connector
+        .connect(&address, stream)
+        .await
+        .expect("handshake");
+
+    read_all(&connector, handle.address())
+        .await
+        .expect_err("closed before the handshake");
+
+    assert_eq!(
+        1,
+        metrics
+            .counter(
+                "connections_refused_total",
+                "",
+                &[("listener", &port), ("reason", "max_connections")]
+            )
+            .get()
+    );
+}
+
+#[tokio::test]
+async fn bans_sources_failing_authentication() {
+    let certs = Certificates::new();
+    let (cert, key) = certs.issue("server");
+    let metrics = tls_server::Metrics::default();
+
+    let mut config = tls_server::Config::new(0);
+    config
+        .with_certificate_and_key_files(&cert, &key)
+        .expect("certificate")
+        .with_client_authentication(&certs.root())
+        .expect("root")
+        .with_bans(tls_server::BanPolicy {
+            max_failures: 2,
+            window: std::time::Duration::from_secs(60),
+            duration: std::time::Duration::from_secs(600),
+        })
+        .with_metrics(metrics.clone());
+    let handle = tls_server::Server::new(config)
+        .expect("server")
+        .spawn(greet_and_hold)
+        .expect("spawn");
+    let port = handle.address().port().to_string();
+
+    let anonymous = certs.connector(None);
+    for _ in 0..2 {
+        read_all(&anonymous, handle.address())
+            .await
+            .expect_err("not authenticated");
+    }
+
+    // not even let in with a good certificate anymore
+    let alice = certs.connector(Some("alice"));
+    let address = format!("localhost:{}", handle.address().port());
+    let stream = tokio::net::TcpStream::connect(&address)
+        .await
+        .expect("connect");
+    alice.connect(&address, stream).await.expect_err("banned");
+
+    let labels = [("listener", port.as_str())];
+    assert_eq!(1, metrics.counter("source_bans_total", "", &labels).get());
+    assert_eq!(1, metrics.gauge("sources_banned", "", &labels).get());
+    assert_eq!(
+        1,
+        metrics
+            .counter(
+                "connections_refused_total",
+                "",
+                &[("listener", &port), ("reason", "banned")]
+            )
+            .get()
+    );
+}
+
+#[tokio::test]
+async fn limits_connections_per_proxied_source() {
+    let certs = Certificates::new();
+    let (cert, key) = certs.issue("server");
+
+    let mut config = tls_server::Config::new(0);
+    config
+        .with_certificate_and_key_files(&cert, &key)
+        .expect("certificate")
+        .with_proxy_protocol(tls_server::ProxyProtocol::Required)
+        .with_trusted_proxies(&localhost())
+        .with_max_connections_per_source(1);
+    let handle = tls_server::Server::new(config)
+        .expect("server")
+        .spawn(greet_and_hold)
+        .expect("spawn");
+
+    let connector = certs.connector(None);
+    let _first = connect_from(&connector, handle.address(), "10.0.0.1")
+        .await
+        .expect("first from 10.0.0.1");
+    connect_from(&connector, handle.address(), "10.0.0.1")
+        .await
+        .expect_err("second from 10.0.0.1");
+    // everything comes from the same proxy, yet others are let in
+    connect_from(&connector, handle.address(), "10.0.0.2")
+        .await
+        .expect("first from 10.0.0.2");
+}
+
+#[tokio::test]
+async fn bans_proxied_sources_failing_authentication() {
+    let certs = Certificates::new();
+    let (cert, key) = certs.issue("server");
+
+    let mut config = tls_server::Config::new(0);
+    config
+        .with_certificate_and_key_files(&cert, &key)
+        .expect("certificate")
+        .with_client_authentication(&certs.root())
+        .expect("root")
+        .with_proxy_protocol(tls_server::ProxyProtocol::Required)
+        .with_trusted_proxies(&localhost())
+        .with_bans(tls_server::BanPolicy {
+            max_failures: 2,
+            window: std::time::Duration::from_secs(60),
+            duration: std::time::Duration::from_secs(600),
+        });
+    let handle = tls_server::Server::new(config)
+        .expect("server")
+        .spawn(greet_and_hold)
+        .expect("spawn");
+
+    let anonymous = certs.connector(None);
+    for _ in 0..2 {
+        connect_from(&anonymous, handle.address(), "10.0.0.1")
+            .await
+            .expect_err("not authenticated");
+    }
+
+    let alice = certs.connector(Some("alice"));
+    connect_from(&alice, handle.address(), "10.0.0.1")
+        .await
+        .expect_err("banned");
+    // not the proxy everything comes through
+    connect_from(&alice, handle.address(), "10.0.0.2")
+        .await
+        .expect("not banned");
+}
+
+#[tokio::test]
+async fn ignores_proxy_headers_from_untrusted_peers() {
+    let certs = Certificates::new();
+    let (cert, key) = certs.issue("server");
+    let untrusted = ["10.0.0.100".parse().unwrap()];
+
+    let mut config = tls_server::Config::new(0);
+    config
+        .with_certificate_and_key_files(&cert, &key)
+        .expect("certificate")
+        .with_proxy_protocol(tls_server::ProxyProtocol::Required)
+        .with_trusted_proxies(&untrusted);
+    let handle = tls_server::Server::new(config)
+        .expect("server")
+        .spawn(greet_and_hold)
+        .expect("spawn");
+
+    let connector = certs.connector(None);
+    connect_from(&connector, handle.address(), "10.0.0.1")
+        .await
+        .expect_err("required from an untrusted peer");
+
+    let mut config = tls_server::Config::new(0);
+    config
+        .with_certificate_and_key_files(&cert, &key)
+        .expect("certificate")
+        .with_proxy_protocol(tls_server::ProxyProtocol::Optional)
+        .with_trusted_proxies(&untrusted)
+        .with_handshake_timeout(std::time::Duration::from_millis(500));
+    let handle = tls_server::Server::new(config)
+        .expect("server")
+        .spawn(greet_and_hold)
+        .expect("spawn");
+
+    // the header is not taken, so it breaks the handshake instead
+    connect_from(&connector, handle.address(), "10.0.0.1")
+        .await
+        .expect_err("optional from an untrusted peer");
+    let address = format!("localhost:{}", handle.address().port());
+    let stream = tokio::net::TcpStream::connect(&address)
+        .await
+        .expect("connect");
+    let mut stream = connector
+        .connect(&address, stream)
+        .await
+        .expect("direct from an untrusted peer");
+    let mut greeting = [0u8; 5];
+    stream.read_exact(&mut greeting).await.expect("greeting");
+}
+
+#[test]
+fn proxy_protocol_needs_trusted_proxies() {
+    let certs = Certificates::new();
+    let (cert, key) = certs.issue("server");
+
+    let mut config = tls_server::Config::new(0);
+    config
+        .with_certificate_and_key_files(&cert, &key)
+        .expect("certificate")
+        .with_proxy_protocol(tls_server::ProxyProtocol::Optional);
+    assert!(tls_server::Server::new(config).is_err());
+}
@@ -0,0 +1,60 @@
+mod common;
+
+use common::{read_all, Certificates};
+use tokio::io::AsyncWriteExt;
+
+async fn greet(mut stream: tls_server::Stream, _context: tls_server::ConnectionContext) {
+    let _ = stream.write_all(b"hello").await;
+    let _ = stream.shutdown().await;
+}
+
+#[tokio::test]
+async fn counts_connections_and_handshakes() {
+    let certs = Certificates::new();
+    let (cert, key) = certs.issue("server");
+    let metrics = tls_server::Metrics::default();
+
+    let mut config = tls_server::Config::new(0);
+    config
+        .with_certificate_and_key_files(&cert, &key)
+        .expect("certificate")
+        .with_client_authentication(&certs.root())
+        .expect("root")
+        .with_metrics(metrics.clone());
+    let handle = tls_server::Server::new(config)
+        .expect("server")
+        .spawn(greet)
+        .expect("spawn");
+    let port = handle.address().port().to_string();
+
+    read_all(&certs.connector(Some("alice")), handle.address())
+        .await
+        .expect("alice is authenticated");
+    read_all(&certs.connector(None), handle.address())
+        .await
+        .expect_err("anonymous clients are not");
+    drop(handle);
+
+    let labels = [("listener", port.as_str())];
+    let counter = |name| metrics.counter(name, "", &labels).get();
+    assert_eq!(2, counter("connections_accepted_total"));
+    assert_eq!(2, counter("connections_closed_total"));
+    assert!(counter("bytes_received_total") > 0);
+    assert!(counter("bytes_sent_total") > 0);
+    assert_eq!(
+        1,
+        metrics
+            .histogram("tls_handshake_duration_seconds", "", &labels)
+            .count()
+    );
+    assert_eq!(
+        1,
+        metrics
+            .counter(
+                "tls_handshake_failures_total",
+                "",
+                &[("listener", &port), ("reason", "bad_certificate")]
+            )
+            .get()
+    );
+}
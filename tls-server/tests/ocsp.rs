@@ -0,0 +1,164 @@
+mod common;
+
+use common::Certificates;
+use std::sync::{Arc, Mutex};
+use tokio::io::AsyncWriteExt;
+
+async fn greet(mut stream: tls_server::Stream, _context: tls_server::ConnectionContext) {
+    let _ = stream.write_all(b"hello").await;
+    let _ = stream.shutdown().await;
+}
+
+// Serve the certificate along with its issuer, which the server needs to tell what the response
+// is about.
+fn spawn(certs: &Certificates, cert: &str, key: &str, ocsp: &str) -> tls_server::Handle {
+    spawn_chain(&certs.chain("chain.pem", cert), key, ocsp)
+}
+
+fn spawn_chain(chain: &str, key: &str, ocsp: &str) -> tls_server::Handle {
+    let mut config = tls_server::Config::new(0);
+    config
+        .with_certificate_and_key_files(chain, key)
+        .expect("certificate")
+        .with_ocsp_response_file(ocsp)
+        .expect("ocsp response")
+        .with_ocsp_refresh_interval(std::time::Duration::from_millis(50));
+
+    tls_server::Server::new(config)
+        .expect("server")
+        .spawn(greet)
+        .expect("spawn")
+}
+
+// Verifies the server like any client, remembering the stapled OCSP response.
+struct Stapled {
+    verifier: rustls::WebPKIVerifier,
+    response: Mutex<Vec<u8>>,
+}
+
+impl rustls::ServerCertVerifier for Stapled {
+    fn verify_server_cert(
+        &self,
+        roots: &rustls::RootCertStore,
+        presented_certs: &[rustls::Certificate],
+        dns_name: webpki::DNSNameRef,
+        ocsp_response: &[u8],
+    ) -> Result<rustls::ServerCertVerified, rustls::TLSError> {
+        *self.response.lock().unwrap() = ocsp_response.to_vec();
+        self.verifier
+            .verify_server_cert(roots, presented_certs, dns_name, ocsp_response)
+    }
+}
+
+// Connect and return the OCSP response the server stapled, empty if none.
+async fn stapled(certs: &Certificates, address: std::net::SocketAddr) -> Vec<u8> {
+    let verifier = Arc::new(Stapled {
+        verifier: rustls::WebPKIVerifier::new(),
+        response: Mutex::new(vec![]),
+    });
+
+    let mut config = rustls::ClientConfig::new();
+    for cert in certutils::read_certs(&certs.root()).expect("root") {
+        config.root_store.add(&cert).expect("root");
+    }
+    config
+        .dangerous()
+        .set_certificate_verifier(verifier.clone());
+
+    let connector = tokio_rustls::TlsConnector::from(Arc::new(config));
+    let stream = tokio::net::TcpStream::connect(("localhost", address.port()))
+        .await
+        .expect("connect");
+    let name = webpki::DNSNameRef::try_from_ascii_str("localhost").unwrap();
+    connector.connect(name, stream).await.expect("handshake");
+
+    let response = verifier.response.lock().unwrap().clone();
+    response
+}
+
+#[tokio::test]
+async fn staples_the_response() {
+    let certs = Certificates::new();
+    let (cert, key) = certs.issue("server");
+    let ocsp = certs.ocsp("server.ocsp", &cert, "20991231000000Z");
+    let handle = spawn(&certs, &cert, &key, &ocsp);
+
+    assert_eq!(
+        std::fs::read(&ocsp).unwrap(),
+        stapled(&certs, handle.address()).await
+    );
+}
+
+#[tokio::test]
+async fn does_not_staple_expired_responses() {
+    let certs = Certificates::new();
+    let (cert, key) = certs.issue("server");
+    let ocsp = certs.ocsp("server.ocsp", &cert, "20200102000000Z");
+    let handle = spawn(&certs, &cert, &key, &ocsp);
+
+    assert!(stapled(&certs, handle.address()).await.is_empty());
+}
+
+#[tokio::test]
+async fn does_not_staple_responses_about_other_certificates() {
+    let certs = Certificates::new();
+    let (cert, key) = certs.issue("server");
+    let (other, _) = certs.issue("other");
+    let ocsp = certs.ocsp("other.ocsp", &other, "20991231000000Z");
+    let handle = spawn(&certs, &cert, &key, &ocsp);
+
+    assert!(stapled(&certs, handle.address()).await.is_empty());
+}
+
+#[tokio::test]
+async fn does_not_staple_responses_from_other_issuers() {
+    let certs = Certificates::new();
+    let (cert, key) = certs.issue("server");
+    // a root by the same name, with another key
+    let others = Certificates::new();
+    let ocsp = others.ocsp("server.ocsp", &cert, "20991231000000Z");
+    let handle = spawn(&certs, &cert, &key, &ocsp);
+
+    assert!(stapled(&certs, handle.address()).await.is_empty());
+}
+
+#[tokio::test]
+async fn does_not_staple_without_the_issuer() {
+    let certs = Certificates::new();
+    let (cert, key) = certs.issue("server");
+    let ocsp = certs.ocsp("server.ocsp", &cert, "20991231000000Z");
+    let handle = spawn_chain(&cert, &key, &ocsp);
+
+    assert!(stapled(&certs, handle.address()).await.is_empty());
+}
+
+#[tokio::test]
+async fn reads_the_response_again() {
+    let certs = Certificates::new();
+    let (cert, key) = certs.issue("server");
+    let ocsp = certs.ocsp("server.ocsp", &cert, "20200102000000Z");
+    let handle = spawn(&certs, &cert, &key, &ocsp);
+
+    assert!(stapled(&certs, handle.address()).await.is_empty());
+
+    certs.ocsp("server.ocsp", &cert, "20991231000000Z");
+    tokio::time::delay_for(std::time::Duration::from_millis(500)).await;
+
+    assert_eq!(
+        std::fs::read(&ocsp).unwrap(),
+        stapled(&certs, handle.address()).await
+    );
+}
+
+#[test]
+fn rejects_invalid_responses() {
+    let certs = Certificates::new();
+    let (cert, key) = certs.issue("server");
+    let ocsp = certs.write("invalid.ocsp", "not a response");
+
+    let mut config = tls_server::Config::new(0);
+    config
+        .with_certificate_and_key_files(&cert, &key)
+        .expect("certificate");
+    assert!(config.with_ocsp_response_file(&ocsp).is_err());
+}
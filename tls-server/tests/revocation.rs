@@ -0,0 +1,72 @@
+mod common;
+
+use common::{read_all, Certificates};
+use tokio::io::AsyncWriteExt;
+
+async fn greet(mut stream: tls_server::Stream, _context: tls_server::ConnectionContext) {
+    let _ = stream.write_all(b"hello").await;
+    let _ = stream.shutdown().await;
+}
+
+fn spawn(certs: &Certificates, crl: &str) -> tls_server::Handle {
+    let (cert, key) = certs.issue("server");
+    let mut config = tls_server::Config::new(0);
+    config
+        .with_certificate_and_key_files(&cert, &key)
+        .expect("certificate")
+        .with_client_authentication(&certs.root())
+        .expect("root")
+        .with_client_revocation_list(crl)
+        .expect("crl")
+        .with_reload_interval(std::time::Duration::from_millis(50));
+
+    tls_server::Server::new(config)
+        .expect("server")
+        .spawn(greet)
+        .expect("spawn")
+}
+
+#[tokio::test]
+async fn rejects_revoked_clients() {
+    let certs = Certificates::new();
+    let (cert, key) = certs.issue("alice");
+    let crl = certs.revoke("revoked.crl", &[&cert]);
+    let handle = spawn(&certs, &crl);
+
+    read_all(&certs.connector(Some("bob")), handle.address())
+        .await
+        .expect("bob is not revoked");
+    read_all(&certs.client_connector(&cert, &key), handle.address())
+        .await
+        .expect_err("alice is revoked");
+}
+
+#[tokio::test]
+async fn reloads_revocation_lists() {
+    let certs = Certificates::new();
+    let crl = certs.revoke("revoked.crl", &[]);
+    let handle = spawn(&certs, &crl);
+
+    let (cert, key) = certs.issue("alice");
+    let alice = certs.client_connector(&cert, &key);
+
+    read_all(&alice, handle.address())
+        .await
+        .expect("alice is not revoked yet");
+
+    certs.revoke("revoked.crl", &[&cert]);
+    tokio::time::delay_for(std::time::Duration::from_millis(500)).await;
+
+    read_all(&alice, handle.address())
+        .await
+        .expect_err("alice is revoked now");
+}
+
+#[test]
+fn rejects_invalid_revocation_lists() {
+    let certs = Certificates::new();
+    let crl = certs.write("invalid.crl", "not a revocation list");
+
+    let mut config = tls_server::Config::new(0);
+    assert!(config.with_client_revocation_list(&crl).is_err());
+}
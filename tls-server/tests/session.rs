@@ -0,0 +1,69 @@
+mod common;
+
+use common::{read_all, Certificates};
+use tokio::io::AsyncWriteExt;
+
+// Tells the client what the server knows about the session.
+async fn describe(mut stream: tls_server::Stream, _context: tls_server::ConnectionContext) {
+    let session = tls_server::Session::new(&stream);
+    let client = session
+        .peer_certificate()
+        .map(|c| {
+            let names: Vec<&str> = c.dns_names().collect();
+            format!("{:?} {}", c.common_name, names.join(","))
+        })
+        .unwrap_or_else(|| "anonymous".to_string());
+
+    let timed = match session.handshake_duration {
+        Some(_) => "timed",
+        None => "untimed",
+    };
+    let description = format!(
+        "{} {} {} {}",
+        session.version_name().unwrap_or_default(),
+        session.server_name.unwrap_or_default(),
+        timed,
+        client
+    );
+    let _ = stream.write_all(description.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+fn spawn(certs: &Certificates, authenticate: bool) -> tls_server::Handle {
+    let (cert, key) = certs.issue("server");
+    let mut config = tls_server::Config::new(0);
+    config
+        .with_certificate_and_key_files(&cert, &key)
+        .expect("certificate");
+    if authenticate {
+        config
+            .with_client_authentication(&certs.root())
+            .expect("root");
+    }
+
+    tls_server::Server::new(config)
+        .expect("server")
+        .spawn(describe)
+        .expect("spawn")
+}
+
+#[tokio::test]
+async fn describes_anonymous_clients() {
+    let certs = Certificates::new();
+    let handle = spawn(&certs, false);
+
+    let description = read_all(&certs.connector(None), handle.address()).await;
+    assert_eq!("TLSv1.3 localhost timed anonymous", description.unwrap());
+}
+
+#[tokio::test]
+async fn describes_the_client_certificate() {
+    let certs = Certificates::new();
+    let handle = spawn(&certs, true);
+
+    let description = read_all(&certs.connector(Some("alice")), handle.address()).await;
+    assert_eq!(
+        "TLSv1.3 localhost timed Some(\"alice\") localhost,alice",
+        description.unwrap()
+    );
+}
@@ -0,0 +1,49 @@
+mod common;
+
+use common::{read_all, Certificates};
+use tokio::io::AsyncWriteExt;
+
+async fn greet(mut stream: tls_server::Stream, _context: tls_server::ConnectionContext) {
+    let _ = stream.write_all(b"hello").await;
+    let _ = stream.shutdown().await;
+}
+
+fn spawn(certs: &Certificates) -> tls_server::Handle {
+    let (cert, key) = certs.issue("server");
+    let mut config = tls_server::Config::new(0);
+    config
+        .with_certificate_and_key_files(&cert, &key)
+        .expect("certificate");
+
+    tls_server::Server::new(config)
+        .expect("server")
+        .spawn(greet)
+        .expect("spawn")
+}
+
+#[tokio::test]
+async fn serves_in_the_background() {
+    let certs = Certificates::new();
+    let handle = spawn(&certs);
+    let connector = certs.connector(None);
+
+    assert_ne!(0, handle.address().port());
+    assert_eq!(
+        "hello",
+        read_all(&connector, handle.address()).await.unwrap()
+    );
+}
+
+#[tokio::test]
+async fn shuts_down_through_the_handle() {
+    let certs = Certificates::new();
+    let handle = spawn(&certs);
+    let address = handle.address();
+
+    handle.shutdown();
+    handle.join().expect("clean shutdown");
+
+    read_all(&certs.connector(None), address)
+        .await
+        .expect_err("no longer accepting");
+}
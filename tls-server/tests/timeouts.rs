@@ -0,0 +1,92 @@
+mod common;
+
+use common::{read_all, Certificates};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+async fn greet(mut stream: tls_server::Stream, _context: tls_server::ConnectionContext) {
+    let _ = stream.write_all(b"hello").await;
+    let _ = stream.shutdown().await;
+}
+
+// Greets, then keeps the connection open forever.
+async fn linger(mut stream: tls_server::Stream, _context: tls_server::ConnectionContext) {
+    let _ = stream.write_all(b"hello").await;
+    let _ = stream.flush().await;
+    futures::future::pending::<()>().await;
+}
+
+fn config(certs: &Certificates) -> tls_server::Config {
+    let (cert, key) = certs.issue("server");
+    let mut config = tls_server::Config::new(0);
+    config
+        .with_certificate_and_key_files(&cert, &key)
+        .expect("certificate");
+    config
+}
+
+#[tokio::test]
+async fn times_out_handshakes() {
+    let certs = Certificates::new();
+    let metrics = tls_server::Metrics::default();
+    let mut config = config(&certs);
+    config
+        .with_handshake_timeout(Duration::from_millis(500))
+        .with_metrics(metrics.clone());
+    let handle = tls_server::Server::new(config)
+        .expect("server")
+        .spawn(greet)
+        .expect("spawn");
+    let port = handle.address().port().to_string();
+
+    // connect, but never start the handshake
+    let mut stream = tokio::net::TcpStream::connect(handle.address())
+        .await
+        .expect("connect");
+    let mut buf = Vec::new();
+    tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut buf))
+        .await
+        .expect("closed by the server")
+        .expect("read");
+    assert!(buf.is_empty());
+
+    // clients that do handshake in time are let in
+    let greeting = read_all(&certs.connector(None), handle.address())
+        .await
+        .expect("read");
+    assert_eq!("hello", greeting);
+
+    // counted right after closing, so surely by the time the next connection got through
+    assert_eq!(
+        1,
+        metrics
+            .counter(
+                "tls_handshake_failures_total",
+                "",
+                &[("listener", &port), ("reason", "timeout")]
+            )
+            .get()
+    );
+}
+
+#[tokio::test]
+async fn cuts_connections_at_their_maximum_lifetime() {
+    let certs = Certificates::new();
+    let mut config = config(&certs);
+    config.with_max_lifetime(Duration::from_millis(100));
+    let handle = tls_server::Server::new(config)
+        .expect("server")
+        .spawn(linger)
+        .expect("spawn");
+
+    let connector = certs.connector(None);
+    let read = read_all(&connector, handle.address());
+    let result = tokio::time::timeout(Duration::from_secs(5), read)
+        .await
+        .expect("cut by the server");
+
+    // cut without a close notify, which may show as an error
+    if let Ok(greeting) = result {
+        assert_eq!("hello", greeting);
+    }
+}